@@ -1,4 +1,7 @@
 use crate::ai::AiError::{NoPlayAvailable, NotMyTurn};
+use crate::neural_eval::EvalNetwork;
+use crate::opening_book::OpeningBook;
+use crate::tablebase::{Tablebase, TablebaseResult};
 use hnefatafl::board::state::BoardState;
 use hnefatafl::collections::PieceMap;
 use hnefatafl::game::logic::GameLogic;
@@ -11,8 +14,14 @@ use hnefatafl::pieces::Side::{Attacker, Defender};
 use hnefatafl::pieces::{Piece, Side, KING};
 use hnefatafl::play::ValidPlay;
 use hnefatafl::tiles::Coords;
-use rand::{thread_rng, Rng};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::SmallRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
@@ -20,6 +29,118 @@ use std::time::Instant;
 use web_time::Instant;
 
 
+/// The AI accepts a draw offer if its evaluation of the position, from its own point of view, is no
+/// better than this.
+const DRAW_ACCEPT_SCORE: i32 = -20;
+/// Score of a position that the tablebase says is won for the attacker in no plays. Lower than the
+/// scores of wins found by searching, so that the AI takes a win it can see over one it is told of.
+const TABLEBASE_WIN_SCORE: i32 = i32::MAX - 512;
+/// Scores further from zero than this mean that a forced win has been found.
+const WIN_SCORE_THRESHOLD: i32 = i32::MAX / 2;
+/// Analysis never searches deeper than this.
+const MAX_ANALYSIS_DEPTH: u8 = 64;
+/// Analysis stops on its own after this long.
+const MAX_ANALYSIS_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// The search checks whether it should stop each time it has searched this many more states.
+const SEARCH_POLL_INTERVAL: u32 = 1024;
+
+/// How strongly the AI plays. The weaker levels limit how far the AI searches, add noise to its
+/// evaluation and sometimes have it choose a play other than the best one it found.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Difficulty {
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    /// No limits other than the time per move.
+    #[default]
+    Maximum
+}
+
+/// The ways in which the AI is weakened at a given difficulty.
+struct Weakening {
+    /// The deepest search to perform, if limited.
+    max_depth: Option<u8>,
+    /// The number of states after which to stop searching, if limited.
+    max_states: Option<u32>,
+    /// The maximum amount of random noise to add to (or subtract from) each evaluation.
+    eval_noise: i32,
+    /// The probability of choosing a play at random instead of the best play.
+    blunder_chance: f64,
+    /// When choosing at random, how much worse (in evaluation points) a play can be than the best
+    /// play and still be likely to be chosen. Plays are weighted by `exp(-loss / temperature)`.
+    blunder_temperature: f64
+}
+
+impl Difficulty {
+
+    pub(crate) const ALL: [Difficulty; 5] = [
+        Difficulty::Beginner,
+        Difficulty::Easy,
+        Difficulty::Medium,
+        Difficulty::Hard,
+        Difficulty::Maximum
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+            Difficulty::Maximum => "Maximum"
+        }
+    }
+
+    fn weakening(&self) -> Weakening {
+        match self {
+            Difficulty::Beginner => Weakening {
+                max_depth: Some(1),
+                max_states: Some(20_000),
+                eval_noise: 80,
+                blunder_chance: 0.4,
+                blunder_temperature: 100.0
+            },
+            Difficulty::Easy => Weakening {
+                max_depth: Some(2),
+                max_states: Some(100_000),
+                eval_noise: 40,
+                blunder_chance: 0.2,
+                blunder_temperature: 60.0
+            },
+            Difficulty::Medium => Weakening {
+                max_depth: Some(3),
+                max_states: Some(1_000_000),
+                eval_noise: 15,
+                blunder_chance: 0.08,
+                blunder_temperature: 30.0
+            },
+            Difficulty::Hard => Weakening {
+                max_depth: Some(5),
+                max_states: None,
+                eval_noise: 5,
+                blunder_chance: 0.02,
+                blunder_temperature: 15.0
+            },
+            Difficulty::Maximum => Weakening {
+                max_depth: None,
+                max_states: None,
+                eval_noise: 0,
+                blunder_chance: 0.0,
+                blunder_temperature: 1.0
+            }
+        }
+    }
+}
+
+/// The play with the best score for the side to play, preferring the earliest of equally good
+/// plays.
+fn best_of(scores: &[(ValidPlay, i32)], maximize: bool) -> Option<(ValidPlay, i32)> {
+    scores.iter().copied().reduce(|best, s| {
+        if (maximize && s.1 > best.1) || (!maximize && s.1 < best.1) { s } else { best }
+    })
+}
+
 #[derive(Default)]
 pub(crate) struct SearchStats {
     states: u32,
@@ -28,9 +149,47 @@ pub(crate) struct SearchStats {
     tt_replacements: u32,
     tt_inserts: u32,
     ab_prunes: u32,
+    tb_hits: u32,
     max_depth: u8
 }
 
+/// Describe a score from the search: the number of points in the attacker's favour, or which side
+/// has a forced win.
+pub(crate) fn describe_score(score: i32) -> String {
+    if score > WIN_SCORE_THRESHOLD {
+        "Attacker wins".to_string()
+    } else if score < -WIN_SCORE_THRESHOLD {
+        "Defender wins".to_string()
+    } else {
+        format!("{score:+}")
+    }
+}
+
+/// Whether a score from the search means that `side` has a forced win.
+pub(crate) fn is_forced_win(score: i32, side: Side) -> bool {
+    if side == Attacker { score > WIN_SCORE_THRESHOLD } else { score < -WIN_SCORE_THRESHOLD }
+}
+
+/// One of the best lines found by analysis.
+#[derive(Clone, Debug)]
+pub(crate) struct AnalysisLine {
+    /// The score of the line's first play. Higher = better for attacker.
+    pub(crate) score: i32,
+    /// The first play and the best replies found to it (the principal variation).
+    pub(crate) plays: Vec<ValidPlay>
+}
+
+/// The result of analysing a position to some depth.
+#[derive(Clone, Debug)]
+pub(crate) struct AnalysisUpdate {
+    pub(crate) depth: u8,
+    /// The best lines found, best first.
+    pub(crate) lines: Vec<AnalysisLine>,
+    /// The number of states searched so far.
+    pub(crate) states: u32,
+    pub(crate) elapsed: Duration
+}
+
 pub(crate) enum AiError {
     NoPlayAvailable,
     NotMyTurn
@@ -117,17 +276,22 @@ pub(crate) struct TranspositionTable {
 }
 
 impl TranspositionTable {
+    /// Create a table of about `size_mb` megabytes. The memory is only allocated by the first
+    /// search, so that replacing the table (see [`BasicAi::with_hash_size`]) costs nothing.
     fn new(size_mb: usize) -> Self {
         let entry_size = std::mem::size_of::<Option<TTEntry>>();
-        let n_entries = (size_mb * 1024 * 1024) / entry_size;
+        let n_entries = ((size_mb * 1024 * 1024) / entry_size).max(1);
         Self {
-            entries: vec![None; n_entries],
+            entries: vec![],
             size: n_entries,
             current_age: 0
         }
     }
 
     fn new_search(&mut self) {
+        if self.entries.is_empty() {
+            self.entries = vec![None; self.size];
+        }
         self.current_age = self.current_age.wrapping_add(1);
     }
 
@@ -166,7 +330,7 @@ impl TranspositionTable {
     
     fn probe(&self, hash: u64) -> Option<TTEntry> {
         let index = self.get_index(hash);
-        self.entries[index].and_then(|e| {
+        self.entries.get(index)?.and_then(|e| {
             // Verify correct hash (in case of collision)
             if e.hash == hash {
                 Some(e)
@@ -178,9 +342,106 @@ impl TranspositionTable {
 
 }
 
+/// Evaluate board state and return a score. Higher = better for attacker, lower = better for
+/// defender.
+fn eval_board<T: BoardState>(logic: &GameLogic<T>, board: &T) -> i32 {
+    // unwrap should be safe here because we have already checked for win conditions (including
+    // capture of king) in `eval_state`
+    let king_tile = board.get_king().expect("There should be a king on the board.");
+    let king_coords = Coords::from(king_tile);
+
+    let mut score = 0i32;
+    let att_count = board.count_pieces_of_side(Attacker) as i32;
+    let def_count = board.count_pieces_of_side(Defender) as i32;
+
+    // More pieces a side has/fewer pieces the other side has = better for that side
+    score += att_count * 10;
+    score -= (def_count - 1) * 20;
+
+    // More pieces on the board generally = better for attacker
+    score += att_count + def_count;
+
+    // King closer to edge = better for defender
+    let side_len = logic.board_geo.side_len;
+    let col_dist = min(king_tile.col, side_len - king_tile.col - 1);
+    let row_dist = min(king_tile.row, side_len - king_tile.row - 1);
+    score += (col_dist * 5) as i32;
+    score += (row_dist * 5) as i32;
+
+    // Fewer hostile pieces near king = better for defender
+    score += (logic.board_geo.neighbors(king_tile).iter()
+        .filter(|n| logic.tile_hostile(**n, Piece::new(King, Defender), board))
+        .count() * 10) as i32;
+
+    // Attacker pieces closer to king = better for attacker
+    let mut total_dist = 0u32;
+    let mut attacker_count = 0u32;
+    for tile in board.occupied_by_side(Attacker) {
+        total_dist += Coords::from(tile).row_col_offset_from(king_coords)
+            .manhattan_dist() as u32;
+        attacker_count += 1;
+    }
+    score -= ((total_dist / attacker_count) as i32) * 10;
+    
+    score
+}
+
+/// Quickly evaluate a play. Used in play ordering.
+fn eval_play<T: BoardState>(logic: &GameLogic<T>, vp: ValidPlay, state: &GameState<T>) -> i32 {
+    let mut score = 0i32;
+    let to = vp.play.to();
+    let board = &state.board;
+    let moving_piece = board.get_piece(vp.play.from).expect("No piece to move.");
+
+    // Prioritise capture plays
+    score += (logic.get_captures(vp, moving_piece, state).occupied().count() as i32) * 1000;
+    
+    // King-specific plays
+    if moving_piece == KING {
+        // Bonus for king moves towards edges (escape routes)
+        let to_edge_dist = min(
+            min(to.row, logic.board_geo.side_len - 1 - to.row),
+            min(to.col, logic.board_geo.side_len - 1 - to.col)
+        );
+        score += (4 - to_edge_dist as i32) * 300;
+
+        // Penalty for moving king next to attackers
+        let hostile_neighbors = logic.board_geo.neighbors(to)
+            .iter()
+            .filter(|pos| {
+                board.get_piece(**pos)
+                    .map_or(false, |p| p.side == Attacker)
+            })
+            .count();
+        score -= (hostile_neighbors as i32) * 400;
+    }
+
+    // 3. Mobility scoring
+    let mobility = logic.board_geo.neighbors(to)
+        .iter()
+        .filter(|pos| board.get_piece(**pos).is_none())
+        .count();
+    score += (mobility as i32) * 50;
+    
+    score
+}
+
+/// Sort plays by score, best first for the side that is maximizing or minimizing.
+fn sort_scores(scores: &mut [(ValidPlay, i32)], maximize: bool) {
+    if maximize {
+        scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    } else {
+        scores.sort_by_key(|(_, score)| *score);
+    }
+}
+
 pub trait Ai {
     type BoardState: BoardState;
     fn next_play(&mut self, game_state: &GameState<Self::BoardState>) -> Result<(ValidPlay, Vec<String>), AiError>;
+
+    /// Whether to accept a draw offered by the opponent, based on the AI's assessment of the
+    /// position from its last search. Offers are declined until the AI has searched at least once.
+    fn accepts_draw(&self) -> bool;
 }
 
 pub struct BasicAi<T: BoardState> {
@@ -188,12 +449,33 @@ pub struct BasicAi<T: BoardState> {
     logic: GameLogic<T>,
     zt: ZobristTable,
     tt: TranspositionTable,
-    time_to_play: Duration
+    time_to_play: Duration,
+    difficulty: Difficulty,
+    weakening: Weakening,
+    /// Mixed into the hash of each position to derive its evaluation noise.
+    noise_seed: u64,
+    /// Book of opening plays for the variant being played, consulted before searching.
+    opening_book: Option<Arc<OpeningBook>>,
+    /// Results of endgame positions in the variant being played, used instead of searching them.
+    tablebase: Option<Arc<Tablebase>>,
+    /// Network used to evaluate positions instead of the handcrafted evaluation, if any.
+    network: Option<Arc<EvalNetwork>>,
+    /// Flag that stops the current search when set (used by analysis).
+    interrupt: Option<Arc<AtomicBool>>,
+    /// The time by which the current search must stop, if it is running.
+    cutoff_time: Option<Instant>,
+    /// The score of the best play found by the last search, if any.
+    last_score: Option<i32>
 }
 
 impl<T: BoardState> BasicAi<T> {
     
-    pub(crate) fn new(logic: GameLogic<T>, side: Side, time_to_play: Duration) -> Self {
+    pub(crate) fn new(
+        logic: GameLogic<T>,
+        side: Side,
+        time_to_play: Duration,
+        difficulty: Difficulty
+    ) -> Self {
         let mut rng = thread_rng();
         Self {
             side,
@@ -204,54 +486,52 @@ impl<T: BoardState> BasicAi<T> {
             tt: TranspositionTable::new(128),
             #[cfg(not(target_arch = "wasm32"))]
             tt: TranspositionTable::new(512),
-            time_to_play
+            time_to_play,
+            difficulty,
+            weakening: difficulty.weakening(),
+            noise_seed: rng.gen(),
+            opening_book: None,
+            tablebase: None,
+            network: None,
+            interrupt: None,
+            cutoff_time: None,
+            last_score: None
         }
     }
-    
-    /// Evaluate board state and return a score. Higher = better for attacker, lower = better for
-    /// defender.
-    fn eval_board(&self, board: &T) -> i32 {
-        // unwrap should be safe here because we have already checked for win conditions (including
-        // capture of king) in `eval_state`
-        let king_tile = board.get_king().expect("There should be a king on the board.");
-        let king_coords = Coords::from(king_tile);
-
-        let mut score = 0i32;
-        let att_count = board.count_pieces_of_side(Attacker) as i32;
-        let def_count = board.count_pieces_of_side(Defender) as i32;
-
-        // More pieces a side has/fewer pieces the other side has = better for that side
-        score += att_count * 10;
-        score -= (def_count - 1) * 20;
-
-        // More pieces on the board generally = better for attacker
-        score += att_count + def_count;
-
-        // King closer to edge = better for defender
-        let side_len = self.logic.board_geo.side_len;
-        let col_dist = min(king_tile.col, side_len - king_tile.col - 1);
-        let row_dist = min(king_tile.row, side_len - king_tile.row - 1);
-        score += (col_dist * 5) as i32;
-        score += (row_dist * 5) as i32;
-
-        // Fewer hostile pieces near king = better for defender
-        score += (self.logic.board_geo.neighbors(king_tile).iter()
-            .filter(|n| self.logic.tile_hostile(**n, Piece::new(King, Defender), board))
-            .count() * 10) as i32;
-
-        // Attacker pieces closer to king = better for attacker
-        let mut total_dist = 0u32;
-        let mut attacker_count = 0u32;
-        for tile in board.occupied_by_side(Attacker) {
-            total_dist += Coords::from(tile).row_col_offset_from(king_coords)
-                .manhattan_dist() as u32;
-            attacker_count += 1;
-        }
-        score -= ((total_dist / attacker_count) as i32) * 10;
-        
-        score
+
+    /// Play from `opening_book` (if given) whenever the position is in it, rather than searching.
+    pub(crate) fn with_opening_book(mut self, opening_book: Option<Arc<OpeningBook>>) -> Self {
+        self.opening_book = opening_book;
+        self
     }
-    
+
+    /// Play endgames covered by `tablebase` (if given) perfectly.
+    pub(crate) fn with_tablebase(mut self, tablebase: Option<Arc<Tablebase>>) -> Self {
+        self.tablebase = tablebase;
+        self
+    }
+
+    /// Use a transposition table of about `size_mb` megabytes instead of the default size.
+    pub(crate) fn with_hash_size(mut self, size_mb: usize) -> Self {
+        self.tt = TranspositionTable::new(size_mb);
+        self
+    }
+
+    /// Search no deeper than `max_depth` plays, if given, instead of the difficulty's depth limit.
+    pub(crate) fn with_max_depth(mut self, max_depth: Option<u8>) -> Self {
+        if max_depth.is_some() {
+            self.weakening.max_depth = max_depth;
+        }
+        self
+    }
+
+    /// Evaluate positions with `network` (if given) rather than the handcrafted evaluation. A
+    /// network trained on a board of a different size is ignored.
+    pub(crate) fn with_network(mut self, network: Option<Arc<EvalNetwork>>) -> Self {
+        self.network = network.filter(|n| n.side_len == self.logic.board_geo.side_len);
+        self
+    }
+
     /// Evaluate game state (board state + repetitions) and return a score. Higher = better for
     /// attacker, lower = better for defender.
     fn eval_state(&self, state: &GameState<T>, depth: u8) -> i32 {
@@ -268,8 +548,22 @@ impl<T: BoardState> BasicAi<T> {
             return 0
         }
 
-        let mut score = self.eval_board(&state.board);
-            
+        let mut score = match &self.network {
+            Some(network) => network.evaluate(state),
+            None => eval_board(&self.logic, &state.board)
+        };
+        if self.weakening.eval_noise > 0 {
+            // The noise is derived from the position, so that it is the same whenever the position
+            // is reached during a search. The seed makes it differ between AIs.
+            let mut h = self.zt.hash(state.board, state.side_to_play) ^ self.noise_seed;
+            // Finalizer from SplitMix64, to mix the bits
+            h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+            h ^= h >> 31;
+            let range = 2 * self.weakening.eval_noise as u64 + 1;
+            score += (h % range) as i32 - self.weakening.eval_noise;
+        }
+
         // Penalise repetitions
         score -= (state.repetitions.get_repetitions(Attacker) * 10) as i32;
         score += (state.repetitions.get_repetitions(Defender) * 10) as i32;
@@ -277,49 +571,52 @@ impl<T: BoardState> BasicAi<T> {
         score
     }
     
-    /// Quickly evaluate a play. Used in play ordering.
-    fn eval_play(&self, vp: ValidPlay, state: &GameState<T>) -> i32 {
-        let mut score = 0i32;
-        let to = vp.play.to();
-        let board = &state.board;
-        let moving_piece = board.get_piece(vp.play.from).expect("No piece to move.");
-
-        // Prioritise capture plays
-        score += (self.logic.get_captures(vp, moving_piece, state).occupied().count() as i32) * 1000;
-        
-        // King-specific plays
-        if moving_piece == KING {
-            // Bonus for king moves towards edges (escape routes)
-            let to_edge_dist = min(
-                min(to.row, self.logic.board_geo.side_len - 1 - to.row),
-                min(to.col, self.logic.board_geo.side_len - 1 - to.col)
-            );
-            score += (4 - to_edge_dist as i32) * 300;
-
-            // Penalty for moving king next to attackers
-            let hostile_neighbors = self.logic.board_geo.neighbors(to)
-                .iter()
-                .filter(|pos| {
-                    board.get_piece(**pos)
-                        .map_or(false, |p| p.side == Attacker)
-                })
-                .count();
-            score -= (hostile_neighbors as i32) * 400;
+    /// Score the state from the tablebase, if it covers the position. Higher = better for attacker,
+    /// lower = better for defender.
+    fn tablebase_score(&self, state: &GameState<T>, depth: u8) -> Option<i32> {
+        let result = self.tablebase.as_ref()?.probe(&self.logic, state)?;
+        let (winner, plays) = match result {
+            TablebaseResult::Win(n) | TablebaseResult::Loss(n) =>
+                (result.winner(state.side_to_play).expect("Result should have a winner."), n),
+            TablebaseResult::Draw => return Some(0)
+        };
+        // As in `eval_state`, prefer quick wins and slow losses.
+        let prox_penalty = (u8::MAX as i32) - (depth as i32) + plays as i32;
+        let score = TABLEBASE_WIN_SCORE - prox_penalty;
+        Some(if winner == Attacker { score } else { -score })
+    }
+
+    /// The score of every play in the state according to the tablebase (higher = better for
+    /// attacker), if it covers the position, with a description of the result.
+    fn tablebase_scores(&self, state: &GameState<T>) -> Option<(Vec<(ValidPlay, i32)>, TablebaseResult)> {
+        let result = self.tablebase.as_ref()?.probe(&self.logic, state)?;
+        let mut scores = vec![];
+        for t in state.board.occupied_by_side(state.side_to_play) {
+            for vp in self.logic.iter_plays(t, state).expect("Could not iterate plays") {
+                let new_state = self.logic.do_valid_play(vp, *state).new_state;
+                let score = match new_state.status {
+                    Ongoing => self.tablebase_score(&new_state, 1),
+                    Over(_) => None
+                };
+                scores.push((vp, score.unwrap_or_else(|| self.eval_state(&new_state, 1))));
+            }
         }
+        Some((scores, result))
+    }
 
-        // 3. Mobility scoring
-        let mobility = self.logic.board_geo.neighbors(to)
-            .iter()
-            .filter(|pos| board.get_piece(**pos).is_none())
-            .count();
-        score += (mobility as i32) * 50;
-        
-        score
+    /// A play from the opening book, if it covers the position. At weaker difficulties the book
+    /// is sometimes ignored, as the best play found by searching sometimes is.
+    fn book_play(&self, state: &GameState<T>) -> Option<ValidPlay> {
+        let mut rng = thread_rng();
+        if rng.gen_bool(self.weakening.blunder_chance) {
+            return None
+        }
+        self.opening_book.as_ref()?.choose(&self.logic, state, &mut rng)
     }
     
     fn order_plays(&self, valid_plays: Vec<ValidPlay>, state: &GameState<T>, tt_play: Option<ValidPlay>) -> Vec<ValidPlay> {
         let mut scored_plays: Vec<(ValidPlay, i32)> = valid_plays.into_iter()
-            .map(|p| (p, self.eval_play(p, state)))
+            .map(|p| (p, eval_play(&self.logic, p, state)))
             .collect();
         
         // If we have a TT move, give it maximum priority
@@ -333,7 +630,8 @@ impl<T: BoardState> BasicAi<T> {
         scored_plays.into_iter().map(|ps| ps.0).collect()
     }
 
-    /// The minimax algorithm. Returns (best score, best play) tuple.
+    /// The minimax algorithm. Returns (best score, best play) tuple, or `None` if the search was
+    /// stopped (see [`BasicAi::search_exhausted`]) before the result was known.
     pub(crate) fn minimax(
         &mut self,
         vp: ValidPlay,
@@ -343,8 +641,11 @@ impl<T: BoardState> BasicAi<T> {
         mut alpha: i32,
         mut beta: i32,
         stats: &mut SearchStats
-    ) -> (i32, Option<ValidPlay>) {
+    ) -> Option<(i32, Option<ValidPlay>)> {
         stats.states += 1;
+        if stats.states.is_multiple_of(SEARCH_POLL_INTERVAL) && self.search_exhausted(stats) {
+            return None
+        }
         let state = self.logic.do_valid_play(vp, starting_state).new_state;
         let hash = self.zt.hash(state.board, state.side_to_play);
         
@@ -353,19 +654,28 @@ impl<T: BoardState> BasicAi<T> {
             if tt_entry.depth > depth {
                 stats.tt_hits += 1;
                 match tt_entry.node_type {
-                    NodeType::Exact => return (tt_entry.score, tt_entry.best_play),
-                    NodeType::LowerBound if tt_entry.score >= beta => return (beta, tt_entry.best_play),
-                    NodeType::UpperBound if tt_entry.score <= alpha => return (alpha, tt_entry.best_play),
+                    NodeType::Exact => return Some((tt_entry.score, tt_entry.best_play)),
+                    NodeType::LowerBound if tt_entry.score >= beta => return Some((beta, tt_entry.best_play)),
+                    NodeType::UpperBound if tt_entry.score <= alpha => return Some((alpha, tt_entry.best_play)),
                     _ => {}
                 }
 
             }
         }
         
+        if state.status == Ongoing {
+            // No need to search positions that the tablebase has solved
+            if let Some(score) = self.tablebase_score(&state, depth) {
+                stats.tb_hits += 1;
+                stats.paths += 1;
+                return Some((score, None));
+            }
+        }
+
         if depth == 0 || state.status != Ongoing {
             // Leaf node
             stats.paths += 1;
-            return (self.eval_state(&state, depth), None);
+            return Some((self.eval_state(&state, depth), None));
         }
         
         let mut node_type = NodeType::Exact;
@@ -385,7 +695,7 @@ impl<T: BoardState> BasicAi<T> {
         
         if maximize {
             for p in plays {
-                let (score, _) = self.minimax(p, state, depth-1, false, alpha, beta, stats);
+                let (score, _) = self.minimax(p, state, depth-1, false, alpha, beta, stats)?;
                 if score > best_score {
                     node_type = NodeType::Exact;
                     best_score = score;
@@ -401,7 +711,7 @@ impl<T: BoardState> BasicAi<T> {
             }
         } else {
             for p in plays {
-                let (score, _) = self.minimax(p, state, depth-1, true, alpha, beta, stats);
+                let (score, _) = self.minimax(p, state, depth-1, true, alpha, beta, stats)?;
                 if score < best_score {
                     node_type = NodeType::Exact;
                     best_score = score;
@@ -420,10 +730,20 @@ impl<T: BoardState> BasicAi<T> {
         // Store in transposition table
         self.tt.insert(hash, depth, best_score, node_type, best_play, stats);
         
-        (best_score, best_play)
+        Some((best_score, best_play))
+    }
+
+    /// Whether the search should stop, because time has run out, (at weaker difficulties) enough
+    /// states have been searched or the search has been interrupted.
+    fn search_exhausted(&self, stats: &SearchStats) -> bool {
+        self.cutoff_time.is_some_and(|t| Instant::now() > t)
+            || self.weakening.max_states.is_some_and(|max| stats.states >= max)
+            || self.interrupt.as_ref().is_some_and(|i| i.load(Ordering::Relaxed))
     }
 
-    /// Perform minimax search (with alpha beta pruning) up to the given depth.
+    /// Perform minimax search (with alpha beta pruning) up to the given depth. Returns the score of
+    /// each play searched, and whether the search was cut short. The play being searched when the
+    /// search is cut short is left out, as its score is not known.
     fn search_to_depth(
         &mut self,
         depth: u8,
@@ -431,7 +751,7 @@ impl<T: BoardState> BasicAi<T> {
         maximize: bool,
         stats: &mut SearchStats,
         cutoff_time: Instant
-    ) -> (Option<ValidPlay>, i32, bool) {
+    ) -> (Vec<(ValidPlay, i32)>, bool) {
         
         let mut plays: Vec<(ValidPlay, GameState<T>)> = Vec::new();
         for t in state.board.occupied_by_side(state.side_to_play) {
@@ -441,66 +761,154 @@ impl<T: BoardState> BasicAi<T> {
             }
         }
 
-        let mut best_score = if maximize { i32::MIN } else { i32::MAX };
-        let mut best_play: Option<ValidPlay> = None;
-        
+        let mut scores: Vec<(ValidPlay, i32)> = Vec::with_capacity(plays.len());
+        self.cutoff_time = Some(cutoff_time);
+        let mut exhausted = false;
         for (vp, _) in plays {
-            if Instant::now() > cutoff_time {
-                return (best_play, best_score, true);
+            if self.search_exhausted(stats) {
+                exhausted = true;
+                break
             }
             // Not really sure why we need to negate maximize here but the algo definitely
             // performs better when we do...
-            let (score, _) = self.minimax(vp, state, depth, !maximize, i32::MIN, i32::MAX, stats);
-            if maximize && (score > best_score) {
-                best_score = score;
-                best_play = Some(vp);
-            } else if (!maximize) && (score < best_score) {
-                best_score = score;
-                best_play = Some(vp);
-            }
+            let Some((score, _)) = self.minimax(vp, state, depth, !maximize, i32::MIN, i32::MAX, stats) else {
+                exhausted = true;
+                break
+            };
+            scores.push((vp, score));
         }
-        
-        (best_play, best_score, false)
+        self.cutoff_time = None;
+        (scores, exhausted)
     }
 
+    /// Search to increasing depths until time (or the difficulty's limits) run out. Returns the
+    /// score of each play from the deepest complete search, or from the partial first search if
+    /// no search could be completed.
     fn iddfs(
         &mut self,
         state: GameState<T>,
         maximize: bool,
         stats: &mut SearchStats
-    ) -> (Option<ValidPlay>, i32) {
+    ) -> Vec<(ValidPlay, i32)> {
         self.tt.new_search();
         let mut depth = 1;
-        let mut best_play: Option<ValidPlay> = None;
-        let mut best_score: i32 = if maximize { i32::MIN } else { i32::MAX };
+        let mut scores: Vec<(ValidPlay, i32)> = Vec::new();
         let start_time = Instant::now();
         loop {
-            let (play, score, out_of_time) = self.search_to_depth(
+            let (depth_scores, out_of_time) = self.search_to_depth(
                 depth,
                 state,
                 maximize,
                 stats,
                 start_time + self.time_to_play
             );
-            if let Some(p) = play {
-                if !out_of_time {
-                    println!("Best play after search depth {}: {} (score: {})", depth, p, score);
-                    best_play = play;
-                    best_score = score;
-
+            if !out_of_time || scores.is_empty() {
+                if let Some((p, score)) = best_of(&depth_scores, maximize) {
+                    log::debug!("Best play after search depth {depth}: {p} (score: {score})");
                 }
-            } 
-            if out_of_time || play.is_none() {
+                scores = depth_scores;
+            }
+            let depth_limit_reached = self.weakening.max_depth.is_some_and(|max| depth >= max);
+            if out_of_time || scores.is_empty() || depth_limit_reached {
                 if out_of_time {
                     stats.max_depth = depth - 1;
                 } else {
                     stats.max_depth = depth;
                 }
-                return (best_play, best_score);
+                return scores;
             }
             depth += 1
         }
     }
+
+    /// The principal variation starting with `vp` from `state`: `vp` followed by the best replies
+    /// recorded in the transposition table, up to `depth` of them.
+    fn principal_variation(&self, vp: ValidPlay, state: GameState<T>, depth: u8) -> Vec<ValidPlay> {
+        let mut plays = vec![vp];
+        let mut state = self.logic.do_valid_play(vp, state).new_state;
+        while plays.len() <= depth as usize && state.status == Ongoing {
+            let hash = self.zt.hash(state.board, state.side_to_play);
+            let Some(next) = self.tt.probe(hash).and_then(|e| e.best_play) else { break };
+            // Entries can be left over from searches of other positions
+            if self.logic.validate_play(next.play, &state).is_err() {
+                break
+            }
+            plays.push(next);
+            state = self.logic.do_valid_play(next, state).new_state;
+        }
+        plays
+    }
+
+    /// Search `state` to increasing depths until `stop` is set (or the search can go no deeper),
+    /// passing the best `multi_pv` lines to `report` each time a depth is completed. The opening
+    /// book is not used and every play at the root is searched fully, so the scores of all the
+    /// lines reported are exact.
+    pub(crate) fn analyse(
+        &mut self,
+        state: GameState<T>,
+        multi_pv: usize,
+        stop: Arc<AtomicBool>,
+        mut report: impl FnMut(AnalysisUpdate)
+    ) {
+        self.side = state.side_to_play;
+        self.interrupt = Some(stop);
+        self.tt.new_search();
+        let maximize = state.side_to_play == Attacker;
+        let mut stats = SearchStats::default();
+        let start_time = Instant::now();
+        for depth in 1..=MAX_ANALYSIS_DEPTH {
+            let (mut scores, interrupted) = self.search_to_depth(
+                depth,
+                state,
+                maximize,
+                &mut stats,
+                start_time + MAX_ANALYSIS_TIME
+            );
+            if interrupted || scores.is_empty() {
+                break
+            }
+            sort_scores(&mut scores, maximize);
+            let lines = scores.iter()
+                .take(multi_pv)
+                .map(|(vp, score)| AnalysisLine {
+                    score: *score,
+                    plays: self.principal_variation(*vp, state, depth)
+                })
+                .collect();
+            report(AnalysisUpdate { depth, lines, states: stats.states, elapsed: start_time.elapsed() });
+        }
+        self.interrupt = None;
+    }
+
+    /// Score every play available to the side to play in `state`, best first, by searching for this
+    /// AI's time to play. The opening book is not used.
+    pub(crate) fn score_plays(&mut self, state: &GameState<T>) -> Vec<(ValidPlay, i32)> {
+        self.side = state.side_to_play;
+        let maximize = self.side == Attacker;
+        let mut stats = SearchStats::default();
+        let mut scores = self.iddfs(*state, maximize, &mut stats);
+        sort_scores(&mut scores, maximize);
+        scores
+    }
+
+    /// Choose a play given the score of each play. This is the best play except at weaker
+    /// difficulties, where another play is sometimes chosen at random, favouring plays that are
+    /// only a little worse than the best.
+    fn choose_play(&self, scores: &[(ValidPlay, i32)], best: (ValidPlay, i32)) -> ValidPlay {
+        let mut rng = thread_rng();
+        if scores.len() < 2 || !rng.gen_bool(self.weakening.blunder_chance) {
+            return best.0
+        }
+        let weights = scores.iter().map(|(_, score)| {
+            // The best score is the highest or lowest, so this is how much worse the play is.
+            let loss = (best.1 as i64 - *score as i64).abs() as f64;
+            (-loss / self.weakening.blunder_temperature).exp()
+        });
+        match WeightedIndex::new(weights) {
+            Ok(dist) => scores[dist.sample(&mut rng)].0,
+            Err(_) => best.0
+        }
+    }
     
 }
 
@@ -511,29 +919,435 @@ impl<T: BoardState> Ai for BasicAi<T> {
         if game_state.side_to_play != self.side {
             return Err(NotMyTurn)
         }
+        let maximize = self.side == Attacker;
+        if let Some(play) = self.book_play(game_state) {
+            log::debug!("Book play: {play}");
+            // The book has no scores, so the position reached is evaluated instead.
+            let new_state = self.logic.do_valid_play(play, *game_state).new_state;
+            self.last_score = Some(self.eval_state(&new_state, 1));
+            return Ok((play, vec![format!("Book move: {play}.")]))
+        }
+        if let Some((scores, result)) = self.tablebase_scores(game_state) {
+            if let Some((best_play, best_score)) = best_of(&scores, maximize) {
+                log::debug!("Tablebase play: {best_play} ({})", result.describe());
+                self.last_score = Some(best_score);
+                let play = self.choose_play(&scores, (best_play, best_score));
+                let mut log_lines = vec![format!("Tablebase move: {best_play} ({}).", result.describe())];
+                if play != best_play {
+                    log_lines.push(format!("{} difficulty: chose {play} instead of {best_play}.",
+                        self.difficulty.name()));
+                }
+                return Ok((play, log_lines))
+            }
+        }
         let mut stats = SearchStats::default();
         let start_time = Instant::now();
-        let (best_play, best_score) = self.iddfs(
-            *game_state, 
-            self.side == Attacker,
-            &mut stats
-        );
+        let scores = self.iddfs(*game_state, maximize, &mut stats);
         
-        let log_lines: Vec<String> = vec![
+        let mut log_lines: Vec<String> = vec![
             format!("Searched {} paths ({} states) in {}s.",
                      stats.paths, stats.states, start_time.elapsed().as_secs_f32()),
             format!("Maximum depth searched: {}", stats.max_depth),
             format!("Pruned {} paths.", stats.ab_prunes),
             
-            format!("TT hits: {}, insertions: {}, replacements: {}.", stats.tt_hits, stats.tt_inserts, stats.tt_replacements)
+            format!("TT hits: {}, insertions: {}, replacements: {}.", stats.tt_hits, stats.tt_inserts, stats.tt_replacements),
+            format!("Tablebase hits: {}.", stats.tb_hits)
         ];
         
-        if let Some(p) = best_play {
-            println!("Best play: {p}, score: {best_score}");
-            Ok((p, log_lines))
+        if let Some((best_play, best_score)) = best_of(&scores, maximize) {
+            log::debug!("Best play: {best_play}, score: {best_score}");
+            self.last_score = Some(best_score);
+            let play = self.choose_play(&scores, (best_play, best_score));
+            if play != best_play {
+                log_lines.push(format!("{} difficulty: chose {play} instead of {best_play}.",
+                    self.difficulty.name()));
+            }
+            Ok((play, log_lines))
         } else {
-            println!("No play found");
+            log::debug!("No play found");
             Err(NoPlayAvailable)
         }
     }
-}
\ No newline at end of file
+
+    fn accepts_draw(&self) -> bool {
+        self.last_score.is_some_and(|score| {
+            let own_score = if self.side == Attacker { score } else { score.saturating_neg() };
+            own_score <= DRAW_ACCEPT_SCORE
+        })
+    }
+}
+
+/// How the plays in an MCTS playout are chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PlayoutPolicy {
+    /// Uniformly at random.
+    Random,
+    /// The best of a few random plays, according to the heuristic used to order plays in
+    /// alpha-beta search.
+    Heuristic
+}
+
+impl PlayoutPolicy {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            PlayoutPolicy::Random => "Random",
+            PlayoutPolicy::Heuristic => "Heuristic"
+        }
+    }
+}
+
+/// The number of plays after which playouts are scored with the evaluation function, by default.
+pub(crate) const DEFAULT_EVAL_CUTOFF: u16 = 20;
+
+/// Settings for [`MctsAi`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct MctsConfig {
+    /// The exploration constant in the UCT formula. Higher values spend more time on plays that
+    /// have not scored well so far.
+    pub(crate) exploration: f64,
+    pub(crate) playout_policy: PlayoutPolicy,
+    /// If set, playouts stop after this many plays and the position reached is scored with the
+    /// evaluation function, rather than being played to the end.
+    pub(crate) eval_cutoff: Option<u16>,
+    /// Whether to keep the part of the search tree that is still relevant after each play.
+    pub(crate) reuse_tree: bool
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration: std::f64::consts::SQRT_2,
+            playout_policy: PlayoutPolicy::Heuristic,
+            eval_cutoff: Some(DEFAULT_EVAL_CUTOFF),
+            reuse_tree: true
+        }
+    }
+}
+
+/// The algorithm the AI uses to choose its plays.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub(crate) enum AiEngine {
+    /// Alpha-beta search with iterative deepening ([`BasicAi`]).
+    #[default]
+    AlphaBeta,
+    /// Monte Carlo tree search ([`MctsAi`]).
+    Mcts(MctsConfig)
+}
+
+impl AiEngine {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            AiEngine::AlphaBeta => "Alpha-beta",
+            AiEngine::Mcts(_) => "Monte Carlo tree search"
+        }
+    }
+}
+
+/// Number of random plays that the heuristic playout policy chooses between.
+const HEURISTIC_PLAYOUT_CANDIDATES: usize = 3;
+/// Playouts that have not ended after this many plays (and are not scored with the evaluation
+/// function) are scored as draws.
+const MAX_PLAYOUT_PLIES: u16 = 200;
+/// Scale used to convert scores from `eval_board` into the attacker's chance of winning.
+const EVAL_SCALE: f64 = 100.0;
+/// The search tree is not expanded once it uses this much memory, in bytes. Much less on the web,
+/// where the browser limits the memory available.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_MCTS_TREE_BYTES: usize = 256 * 1024 * 1024;
+#[cfg(target_arch = "wasm32")]
+const MAX_MCTS_TREE_BYTES: usize = 16 * 1024 * 1024;
+/// The memory used by each node of the search tree, not counting its untried plays: the node itself
+/// and its index in its parent's children.
+const MCTS_NODE_BYTES: usize = size_of::<MctsNode>() + size_of::<usize>();
+/// The MCTS AI accepts a draw offer if the expected result of its best play (from 0 for a loss to
+/// 1 for a win) is no better than this.
+const MCTS_DRAW_ACCEPT_VALUE: f64 = 0.4;
+
+/// All valid plays for the side to play.
+pub(crate) fn all_plays<T: BoardState>(logic: &GameLogic<T>, state: &GameState<T>) -> Vec<ValidPlay> {
+    let mut plays = Vec::new();
+    for t in state.board.occupied_by_side(state.side_to_play) {
+        plays.extend(logic.iter_plays(t, state).expect("Could not iterate plays"));
+    }
+    plays
+}
+
+/// The result of the game for the attacker (1 for a win, 0 for a loss, 0.5 for a draw), if it is
+/// over.
+fn result_for_attacker<T: BoardState>(state: &GameState<T>) -> Option<f64> {
+    match state.status {
+        Over(Win(_, Attacker)) => Some(1.0),
+        Over(Win(_, Defender)) => Some(0.0),
+        Over(Draw(_)) => Some(0.5),
+        Ongoing => None
+    }
+}
+
+/// A node in the MCTS search tree. Nodes do not store their positions, which are rebuilt by making
+/// the plays on the path from the root.
+struct MctsNode {
+    /// The play that leads to this node from its parent (`None` for the root of a new tree).
+    play: Option<ValidPlay>,
+    children: Vec<usize>,
+    /// Plays from this node that have no child node yet, or `None` if they have not been generated,
+    /// which is done when the search first tries to expand the node.
+    untried: Option<Vec<ValidPlay>>,
+    visits: u32,
+    /// Sum of the results of playouts through this node, for the side that made `play`.
+    total_reward: f64
+}
+
+impl MctsNode {
+    fn new(play: Option<ValidPlay>) -> Self {
+        Self { play, children: vec![], untried: None, visits: 0, total_reward: 0.0 }
+    }
+
+    /// The memory used by the node's untried plays.
+    fn untried_bytes(&self) -> usize {
+        self.untried.as_ref().map_or(0, |u| u.capacity() * size_of::<ValidPlay>())
+    }
+}
+
+/// An AI that uses Monte Carlo tree search (with the UCT selection rule) instead of a handcrafted
+/// evaluation of every position.
+pub struct MctsAi<T: BoardState> {
+    side: Side,
+    logic: GameLogic<T>,
+    time_to_play: Duration,
+    config: MctsConfig,
+    rng: SmallRng,
+    /// The search tree, with the root at index 0. Empty before the first search.
+    nodes: Vec<MctsNode>,
+    /// The position at the root of the tree.
+    root_state: Option<GameState<T>>,
+    /// The approximate memory used by the tree, in bytes.
+    tree_bytes: usize,
+    /// The expected result (for this AI) of the play chosen by the last search, if any.
+    last_value: Option<f64>
+}
+
+impl<T: BoardState> MctsAi<T> {
+
+    pub(crate) fn new(logic: GameLogic<T>, side: Side, time_to_play: Duration, config: MctsConfig) -> Self {
+        Self {
+            side,
+            logic,
+            time_to_play,
+            config,
+            rng: SmallRng::from_entropy(),
+            nodes: vec![],
+            root_state: None,
+            tree_bytes: 0,
+            last_value: None
+        }
+    }
+
+    /// Make the position in `state` the root of the tree, keeping its subtree if it is already in
+    /// the tree (and tree reuse is enabled). Returns whether any of the tree was kept.
+    fn set_root(&mut self, state: &GameState<T>) -> bool {
+        let mut found = None;
+        if let Some(root_state) = self.root_state.filter(|_| self.config.reuse_tree) {
+            // The new position is normally reached by our last play and the opponent's reply, so is
+            // a grandchild of the root.
+            let is_new_root = |s: &GameState<T>| s.board == state.board && s.side_to_play == state.side_to_play;
+            'search: for &c in &self.nodes[0].children {
+                let child_state = self.child_state(c, root_state);
+                if is_new_root(&child_state) {
+                    found = Some(c);
+                    break
+                }
+                for &g in &self.nodes[c].children {
+                    if is_new_root(&self.child_state(g, child_state)) {
+                        found = Some(g);
+                        break 'search
+                    }
+                }
+            }
+        }
+        self.root_state = Some(*state);
+        match found {
+            Some(i) => {
+                self.nodes = self.take_subtree(i);
+                self.tree_bytes = self.nodes.iter().map(|n| MCTS_NODE_BYTES + n.untried_bytes()).sum();
+                true
+            },
+            None => {
+                self.nodes = vec![MctsNode::new(None)];
+                self.tree_bytes = MCTS_NODE_BYTES;
+                false
+            }
+        }
+    }
+
+    /// The position at `node`, given the position at its parent.
+    fn child_state(&self, node: usize, parent_state: GameState<T>) -> GameState<T> {
+        let play = self.nodes[node].play.expect("Child nodes should have a play.");
+        self.logic.do_valid_play(play, parent_state).new_state
+    }
+
+    /// Remove the tree, returning the subtree rooted at `root` as a new tree.
+    fn take_subtree(&mut self, root: usize) -> Vec<MctsNode> {
+        let mut old: Vec<Option<MctsNode>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        let mut new: Vec<MctsNode> = Vec::new();
+        let mut queue = VecDeque::from([(root, None)]);
+        while let Some((i, parent)) = queue.pop_front() {
+            let mut node = old[i].take().expect("Node should only be visited once.");
+            let new_index = new.len();
+            for c in std::mem::take(&mut node.children) {
+                queue.push_back((c, Some(new_index)));
+            }
+            new.push(node);
+            if let Some(p) = parent {
+                new[p].children.push(new_index);
+            }
+        }
+        new
+    }
+
+    /// Generate the untried plays of `node`, whose position is `state`, if they have not been
+    /// generated yet.
+    fn generate_untried(&mut self, node: usize, state: &GameState<T>) {
+        if self.nodes[node].untried.is_none() {
+            let plays = if state.status == Ongoing { all_plays(&self.logic, state) } else { vec![] };
+            self.nodes[node].untried = Some(plays);
+            self.tree_bytes += self.nodes[node].untried_bytes();
+        }
+    }
+
+    /// The child of `node` with the highest UCT value.
+    fn uct_child(&self, node: usize) -> usize {
+        let ln_visits = (self.nodes[node].visits as f64).ln();
+        let uct = |c: usize| {
+            let child = &self.nodes[c];
+            let visits = child.visits as f64;
+            child.total_reward / visits + self.config.exploration * (ln_visits / visits).sqrt()
+        };
+        self.nodes[node].children.iter()
+            .copied()
+            .max_by(|a, b| uct(*a).total_cmp(&uct(*b)))
+            .expect("Node should have children.")
+    }
+
+    /// Play the game on from `state` according to the playout policy, returning the result for
+    /// the attacker.
+    fn playout(&mut self, mut state: GameState<T>) -> f64 {
+        for _ in 0..self.config.eval_cutoff.unwrap_or(MAX_PLAYOUT_PLIES) {
+            if let Some(result) = result_for_attacker(&state) {
+                return result
+            }
+            let plays = all_plays(&self.logic, &state);
+            if plays.is_empty() {
+                // The side to play is stuck, and loses
+                return if state.side_to_play == Attacker { 0.0 } else { 1.0 }
+            }
+            let vp = match self.config.playout_policy {
+                PlayoutPolicy::Random => plays[self.rng.gen_range(0..plays.len())],
+                PlayoutPolicy::Heuristic => (0..HEURISTIC_PLAYOUT_CANDIDATES)
+                    .map(|_| plays[self.rng.gen_range(0..plays.len())])
+                    .max_by_key(|vp| eval_play(&self.logic, *vp, &state))
+                    .expect("There should be candidate plays.")
+            };
+            state = self.logic.do_valid_play(vp, state).new_state;
+        }
+        if let Some(result) = result_for_attacker(&state) {
+            result
+        } else if self.config.eval_cutoff.is_some() {
+            let score = eval_board(&self.logic, &state.board) as f64;
+            1.0 / (1.0 + (-score / EVAL_SCALE).exp())
+        } else {
+            0.5
+        }
+    }
+
+    /// Run one iteration of MCTS: select a node, expand it, play out from the new node and update
+    /// the statistics of the nodes on the path to it.
+    fn iterate(&mut self, root_state: GameState<T>) {
+        let mut node = 0;
+        let mut state = root_state;
+        // The nodes visited, each with the side that made the play leading to it.
+        let mut path = vec![(0, root_state.side_to_play.other())];
+        loop {
+            self.generate_untried(node, &state);
+            let n = &self.nodes[node];
+            if n.untried.as_ref().is_some_and(|u| !u.is_empty()) || n.children.is_empty() {
+                break
+            }
+            let mover = state.side_to_play;
+            node = self.uct_child(node);
+            state = self.child_state(node, state);
+            path.push((node, mover));
+        }
+        if self.tree_bytes < MAX_MCTS_TREE_BYTES {
+            if let Some(untried) = self.nodes[node].untried.as_mut().filter(|u| !u.is_empty()) {
+                let vp = untried.swap_remove(self.rng.gen_range(0..untried.len()));
+                if untried.is_empty() {
+                    // Free the memory of the list once every play has been tried
+                    self.tree_bytes -= untried.capacity() * size_of::<ValidPlay>();
+                    *untried = vec![];
+                }
+                let mover = state.side_to_play;
+                state = self.logic.do_valid_play(vp, state).new_state;
+                let child = self.nodes.len();
+                self.nodes.push(MctsNode::new(Some(vp)));
+                self.tree_bytes += MCTS_NODE_BYTES;
+                self.nodes[node].children.push(child);
+                path.push((child, mover));
+            }
+        }
+        let result = self.playout(state);
+        for (i, mover) in path {
+            let n = &mut self.nodes[i];
+            n.visits += 1;
+            n.total_reward += if mover == Attacker { result } else { 1.0 - result };
+        }
+    }
+}
+
+impl<T: BoardState> Ai for MctsAi<T> {
+    type BoardState = T;
+
+    fn next_play(&mut self, game_state: &GameState<T>) -> Result<(ValidPlay, Vec<String>), AiError> {
+        if game_state.side_to_play != self.side {
+            return Err(NotMyTurn)
+        }
+        let start_time = Instant::now();
+        let reused = self.set_root(game_state);
+        let reused_visits = self.nodes[0].visits;
+        self.generate_untried(0, game_state);
+        if self.nodes[0].untried.as_ref().is_some_and(|u| u.is_empty()) && self.nodes[0].children.is_empty() {
+            log::debug!("No play found");
+            return Err(NoPlayAvailable)
+        }
+        let mut iterations = 0u32;
+        loop {
+            self.iterate(*game_state);
+            iterations += 1;
+            if start_time.elapsed() >= self.time_to_play {
+                break
+            }
+        }
+        let best = self.nodes[0].children.iter()
+            .copied()
+            .max_by_key(|c| self.nodes[*c].visits)
+            .ok_or(NoPlayAvailable)?;
+        let node = &self.nodes[best];
+        let play = node.play.expect("Child nodes should have a play.");
+        let value = node.total_reward / node.visits as f64;
+        self.last_value = Some(value);
+        log::debug!("Best play: {play}, expected result: {value:.3}");
+
+        let mut log_lines = vec![
+            format!("Ran {} playouts in {}s ({} nodes in tree).",
+                iterations, start_time.elapsed().as_secs_f32(), self.nodes.len()),
+            format!("Expected result: {:.0}% (from {} playouts).", value * 100.0, node.visits)
+        ];
+        if reused {
+            log_lines.push(format!("Reused {reused_visits} playouts from the previous search."));
+        }
+        Ok((play, log_lines))
+    }
+
+    fn accepts_draw(&self) -> bool {
+        self.last_value.is_some_and(|value| value <= MCTS_DRAW_ACCEPT_VALUE)
+    }
+}
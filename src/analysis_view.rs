@@ -0,0 +1,353 @@
+use crate::ai::{describe_score, AnalysisUpdate, BasicAi, Difficulty};
+use crate::board::Board;
+use crate::game_play_view::GameSetup;
+use crate::settings::Settings;
+use eframe::emath::Align;
+use egui::{Layout, RichText};
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::pieces::Side::Attacker;
+use hnefatafl::play::Play;
+use std::cmp::min;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(target_arch = "wasm32")]
+use wasm_thread as thread;
+
+/// How often the view is redrawn to show new results while analysis is running.
+const ANALYSIS_REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+/// The largest number of lines that can be shown.
+const MAX_LINES: usize = 10;
+
+pub(crate) enum AnalysisAction {
+    /// Return to the screen the analysis was started from.
+    Back
+}
+
+/// A request for the analysis thread to analyse a position until `stop` is set.
+struct AnalysisRequest<T: BoardState> {
+    id: u64,
+    state: GameState<T>,
+    multi_pv: usize,
+    stop: Arc<AtomicBool>
+}
+
+/// A view in which the AI analyses positions without playing, showing its best lines as it
+/// searches deeper. The position can be changed by stepping through the plays that led to it or by
+/// making plays on the board.
+pub(crate) struct AnalysisView<T: BoardState> {
+    setup: GameSetup,
+    /// The index in the archive of the game being analysed, if any.
+    archive_index: Option<usize>,
+    /// The game, at the position being analysed.
+    game: Game<T>,
+    /// The plays of the line being analysed, from the start of the game. The position being
+    /// analysed may be before the end of the line.
+    line: Vec<Play>,
+    board_ui: Board<T>,
+    /// The number of lines to show.
+    multi_pv: usize,
+    request_sender: Sender<AnalysisRequest<T>>,
+    update_receiver: Receiver<(u64, AnalysisUpdate)>,
+    /// The ID of the running analysis and the flag that stops it, if analysis is running.
+    running: Option<(u64, Arc<AtomicBool>)>,
+    next_id: u64,
+    /// The latest results of the running (or last) analysis.
+    latest: Option<AnalysisUpdate>,
+    /// A line from the analysis being previewed on the board: the game after the first plays of
+    /// the line, the index of the line and the number of its plays made.
+    preview: Option<(Game<T>, usize, usize)>,
+    /// Short description of the game, shown above the board.
+    title: String
+}
+
+impl<T: BoardState + Send + 'static> AnalysisView<T> where T::BitField: Send {
+
+    /// Analyse the position reached by making `plays` in a game set up with `setup`. The game is
+    /// the archived game with index `archive_index`, if given.
+    pub(crate) fn new(
+        setup: GameSetup,
+        plays: &[Play],
+        archive_index: Option<usize>,
+        settings: &Settings
+    ) -> Result<Self, String> {
+        let mut game: Game<T> = Game::new(setup.ruleset, &setup.starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        for play in plays {
+            game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+        }
+        let mut board_ui = Board::new(&game, None);
+        board_ui.flipped = settings.flip_board;
+        board_ui.animate_moves = settings.animate_moves;
+
+        let (request_sender, request_receiver) = std::sync::mpsc::channel::<AnalysisRequest<T>>();
+        let (update_sender, update_receiver) = std::sync::mpsc::channel::<(u64, AnalysisUpdate)>();
+        let logic = game.logic;
+        let tablebase = setup.tablebase.clone();
+        let network = setup.network.clone();
+        thread::spawn(move || {
+            // The side is set for each position analysed
+            let mut ai = BasicAi::new(logic, Attacker, Duration::ZERO, Difficulty::Maximum)
+                .with_tablebase(tablebase)
+                .with_network(network);
+            while let Ok(AnalysisRequest { id, state, multi_pv, stop }) = request_receiver.recv() {
+                ai.analyse(state, multi_pv, stop, |update| {
+                    // Don't panic if we can't send the update, it probably just means that the
+                    // user has left the view
+                    let _ = update_sender.send((id, update));
+                });
+            }
+        });
+
+        let title = format!("{} ({} plays)", setup.ruleset_name, plays.len());
+        Ok(Self {
+            setup,
+            archive_index,
+            game,
+            line: plays.to_vec(),
+            board_ui,
+            multi_pv: 3,
+            request_sender,
+            update_receiver,
+            running: None,
+            next_id: 0,
+            latest: None,
+            preview: None,
+            title
+        })
+    }
+
+    /// The index in the archive of the game being analysed, if any.
+    pub(crate) fn archive_index(&self) -> Option<usize> {
+        self.archive_index
+    }
+
+    /// The number of plays made to reach the position being analysed.
+    fn current_play(&self) -> usize {
+        self.game.play_history.len()
+    }
+
+    /// Start analysing the current position, stopping any analysis already running.
+    fn start(&mut self) {
+        self.stop();
+        self.latest = None;
+        if self.game.state.status != Ongoing {
+            return
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let stop = Arc::new(AtomicBool::new(false));
+        let request = AnalysisRequest { id, state: self.game.state, multi_pv: self.multi_pv, stop: stop.clone() };
+        if self.request_sender.send(request).is_ok() {
+            self.running = Some((id, stop));
+        }
+    }
+
+    /// Stop the running analysis, if any. Its latest results are still shown.
+    fn stop(&mut self) {
+        if let Some((_, stop)) = self.running.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Called whenever the position being analysed changes.
+    fn position_changed(&mut self) {
+        self.preview = None;
+        if self.running.is_some() {
+            self.start();
+        } else {
+            self.latest = None;
+        }
+    }
+
+    /// Analyse the position after the first `n` plays of the line.
+    fn go_to_play(&mut self, n: usize) {
+        let n = min(n, self.line.len());
+        if n == self.current_play() {
+            return
+        }
+        while self.current_play() > n {
+            self.game.undo_last_play();
+        }
+        while self.current_play() < n {
+            let play = self.line[self.current_play()];
+            // All plays in the line have been checked.
+            self.game.do_play(play).expect("Play in line should be legal.");
+        }
+        self.position_changed();
+    }
+
+    /// Make a play from the current position, replacing the rest of the line.
+    fn make_play(&mut self, play: Play) {
+        match self.game.do_play(play) {
+            Ok(_) => {
+                self.line.truncate(self.current_play() - 1);
+                self.line.push(play);
+                self.position_changed();
+            },
+            Err(e) => log::warn!("Invalid play {play}: {e:?}")
+        }
+    }
+
+    /// Show the first `n` plays of line `i` of the latest results on the board.
+    fn preview_line(&mut self, i: usize, n: usize) {
+        let Some(line) = self.latest.as_ref().and_then(|u| u.lines.get(i)) else { return };
+        let Ok(mut game) = Game::new(self.setup.ruleset, &self.setup.starting_board) else { return };
+        let plays = self.game.play_history.iter()
+            .map(|r| r.play)
+            .chain(line.plays.iter().take(n).map(|vp| vp.play));
+        for play in plays {
+            if game.do_play(play).is_err() {
+                return
+            }
+        }
+        self.preview = Some((game, i, n));
+    }
+
+    /// Receive any new results from the analysis thread.
+    fn receive_updates(&mut self, ctx: &egui::Context) {
+        while let Ok((id, update)) = self.update_receiver.try_recv() {
+            // Ignore results of analyses that have since been stopped
+            if self.running.as_ref().is_some_and(|(running_id, _)| *running_id == id) {
+                self.latest = Some(update);
+            }
+        }
+        if self.running.is_some() {
+            ctx.request_repaint_after(ANALYSIS_REPAINT_INTERVAL);
+        }
+    }
+
+    /// The latest results, with each play clickable to preview the line up to that play.
+    fn lines_ui(&mut self, ui: &mut egui::Ui) {
+        let side_to_play = format!("{:?} to play.", self.game.state.side_to_play);
+        ui.label(if self.game.state.status == Ongoing { side_to_play } else { "The game is over.".to_string() });
+        let Some(update) = &self.latest else {
+            if self.running.is_some() {
+                ui.label("Analysing...");
+            }
+            return
+        };
+        ui.label(format!(
+            "Depth {}, {} states searched in {:.1}s.",
+            update.depth,
+            update.states,
+            update.elapsed.as_secs_f32()
+        ));
+        ui.label("Scores are in the attacker's favour.");
+        ui.separator();
+        let mut selected = None;
+        egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+            for (i, line) in update.lines.iter().enumerate() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(RichText::new(describe_score(line.score)).strong());
+                    for (j, vp) in line.plays.iter().enumerate() {
+                        let is_previewed = self.preview.as_ref().is_some_and(|(_, pi, pn)| *pi == i && *pn == j + 1);
+                        let label = format!("{}. {vp}", self.current_play() + j + 1);
+                        if ui.selectable_label(is_previewed, label).clicked() {
+                            selected = Some((i, j + 1));
+                        }
+                    }
+                });
+            }
+        });
+        if let Some((i, n)) = selected {
+            if self.preview.as_ref().is_some_and(|(_, pi, pn)| (*pi, *pn) == (i, n)) {
+                self.preview = None;
+            } else {
+                self.preview_line(i, n);
+            }
+        }
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<AnalysisAction> {
+        let mut action: Option<AnalysisAction> = None;
+        self.receive_updates(ctx);
+        let bottom_panel_height = ctx.screen_rect().max.y * 0.2;
+
+        egui::TopBottomPanel::bottom("analysis_controls").exact_height(bottom_panel_height).show(ctx, |ui| {
+            ui.with_layout(Layout::bottom_up(Align::Min), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Back").clicked() {
+                        action = Some(AnalysisAction::Back);
+                    }
+                    ui.separator();
+                    let n = self.current_play();
+                    let at_end = n >= self.line.len();
+                    let mut target = None;
+                    if ui.add_enabled(n > 0, egui::Button::new("⏮")).clicked() {
+                        target = Some(0);
+                    }
+                    if ui.add_enabled(n > 0, egui::Button::new("⏴")).clicked() {
+                        target = Some(n - 1);
+                    }
+                    if ui.add_enabled(!at_end, egui::Button::new("⏵")).clicked() {
+                        target = Some(n + 1);
+                    }
+                    if ui.add_enabled(!at_end, egui::Button::new("⏭")).clicked() {
+                        target = Some(self.line.len());
+                    }
+                    if let Some(n) = target {
+                        self.go_to_play(n);
+                    }
+                    ui.separator();
+                    if self.running.is_some() {
+                        if ui.button("Stop").clicked() {
+                            self.stop();
+                        }
+                    } else {
+                        let can_analyse = self.game.state.status == Ongoing;
+                        if ui.add_enabled(can_analyse, egui::Button::new("Analyse")).clicked() {
+                            self.start();
+                        }
+                    }
+                    ui.label("Lines:");
+                    if ui.add(egui::DragValue::new(&mut self.multi_pv).range(1..=MAX_LINES)).changed()
+                        && self.running.is_some() {
+                        self.start();
+                    }
+                    if self.preview.is_some() && ui.button("Clear preview").clicked() {
+                        self.preview = None;
+                    }
+                });
+                ui.label(format!("Play {} of {}. Make a play on the board to analyse a new line.",
+                    self.current_play(), self.line.len()));
+                ui.label(RichText::new(&self.title).strong());
+            })
+        });
+        egui::SidePanel::right("analysis_lines").min_width(240.0).show(ctx, |ui| {
+            self.lines_ui(ui);
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // The board is square, so it fits in whatever is left after the side panel
+            let board_side_px = ui.available_width().min(ui.available_height());
+            match &self.preview {
+                Some((preview_game, _, _)) => {
+                    self.board_ui.read_only = true;
+                    self.board_ui.update(preview_game, ctx, ui, board_side_px);
+                },
+                None => {
+                    self.board_ui.read_only = false;
+                    if let Some(play) = self.board_ui.update(&self.game, ctx, ui, board_side_px) {
+                        self.make_play(play);
+                    }
+                }
+            }
+        });
+        action
+    }
+}
+
+impl<T: BoardState> Drop for AnalysisView<T> {
+    fn drop(&mut self) {
+        // Let the analysis thread finish
+        if let Some((_, stop)) = &self.running {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::ai::{BasicAi, Difficulty};
+use crate::game_play_view::GameSetup;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameOutcome::{Draw, Win};
+use hnefatafl::game::GameStatus::{Ongoing, Over};
+use hnefatafl::pieces::Side;
+use hnefatafl::pieces::Side::Attacker;
+use hnefatafl::play::Play;
+use std::time::Duration;
+
+/// How long the AI searches each position when annotating a game. Shorter on the web, where the
+/// search blocks the UI.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const ANNOTATION_TIME_PER_PLAY: Duration = Duration::from_millis(500);
+#[cfg(target_arch = "wasm32")]
+pub(crate) const ANNOTATION_TIME_PER_PLAY: Duration = Duration::from_millis(100);
+
+/// Scores are converted to winning chances with a logistic curve on this scale, so that a score of
+/// this many points gives the attacker about a 73% chance.
+const WIN_CHANCE_SCALE: f64 = 40.0;
+/// Plays that lose at least this much of the player's winning chances are inaccuracies.
+const INACCURACY_LOSS: f64 = 0.05;
+/// Plays that lose at least this much of the player's winning chances are mistakes.
+const MISTAKE_LOSS: f64 = 0.1;
+/// Plays that lose at least this much of the player's winning chances are blunders.
+const BLUNDER_LOSS: f64 = 0.2;
+
+/// The chance of `side` winning from a position with the given score.
+pub(crate) fn win_chance(score: i32, side: Side) -> f64 {
+    let attacker_chance = 1.0 / (1.0 + (-score as f64 / WIN_CHANCE_SCALE).exp());
+    if side == Attacker { attacker_chance } else { 1.0 - attacker_chance }
+}
+
+/// How bad a play was.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder
+}
+
+impl Judgement {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "inaccuracy",
+            Judgement::Mistake => "mistake",
+            Judgement::Blunder => "blunder"
+        }
+    }
+
+    /// The symbol conventionally written after a play of this kind.
+    pub(crate) fn symbol(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "?!",
+            Judgement::Mistake => "?",
+            Judgement::Blunder => "??"
+        }
+    }
+}
+
+/// The AI's assessment of a play in a game, compared with the best play it found.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PlayAnnotation {
+    /// The side that made the play.
+    pub(crate) side: Side,
+    pub(crate) best_play: Play,
+    /// The score of the best play. Higher = better for attacker.
+    pub(crate) best_score: i32,
+    /// The score of the play made, from the same search as `best_score`.
+    pub(crate) played_score: i32
+}
+
+impl PlayAnnotation {
+
+    /// How much of the player's chance of winning was lost by not making the best play.
+    pub(crate) fn win_chance_loss(&self) -> f64 {
+        (win_chance(self.best_score, self.side) - win_chance(self.played_score, self.side)).max(0.0)
+    }
+
+    pub(crate) fn judgement(&self) -> Option<Judgement> {
+        let loss = self.win_chance_loss();
+        if loss >= BLUNDER_LOSS {
+            Some(Judgement::Blunder)
+        } else if loss >= MISTAKE_LOSS {
+            Some(Judgement::Mistake)
+        } else if loss >= INACCURACY_LOSS {
+            Some(Judgement::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    /// The accuracy of the play, as a percentage: 100 for the best play, falling off quickly as the
+    /// winning chances lost increase.
+    pub(crate) fn accuracy(&self) -> f64 {
+        let loss_percent = self.win_chance_loss() * 100.0;
+        (103.1668 * (-0.04354 * loss_percent).exp() - 3.1669).clamp(0.0, 100.0)
+    }
+}
+
+/// The AI's analysis of one position in a game.
+pub(crate) struct PositionAnalysis {
+    /// The score of the position. Higher = better for attacker.
+    pub(crate) eval: i32,
+    /// The assessment of the play made from the position, unless it is the last position of the
+    /// game.
+    pub(crate) play: Option<PlayAnnotation>
+}
+
+/// The analysis of a game so far, position by position.
+pub(crate) struct GameAnnotation {
+    /// The number of plays in the game.
+    pub(crate) play_count: usize,
+    /// The score of each position analysed, starting with the starting position.
+    pub(crate) evals: Vec<i32>,
+    /// The assessment of each play whose starting position has been analysed, if the play was
+    /// found by the search.
+    pub(crate) plays: Vec<Option<PlayAnnotation>>,
+    /// Whether analysis has finished.
+    pub(crate) complete: bool
+}
+
+impl GameAnnotation {
+
+    pub(crate) fn new(play_count: usize) -> Self {
+        Self { play_count, evals: vec![], plays: vec![], complete: false }
+    }
+
+    pub(crate) fn push(&mut self, analysis: PositionAnalysis) {
+        if self.evals.len() < self.play_count {
+            self.plays.push(analysis.play);
+        }
+        self.evals.push(analysis.eval);
+    }
+
+    /// The average accuracy of the plays made by `side`, as a percentage, if any have been
+    /// assessed.
+    pub(crate) fn accuracy(&self, side: Side) -> Option<f64> {
+        let accuracies: Vec<f64> = self.plays.iter()
+            .flatten()
+            .filter(|a| a.side == side)
+            .map(PlayAnnotation::accuracy)
+            .collect();
+        if accuracies.is_empty() {
+            None
+        } else {
+            Some(accuracies.iter().sum::<f64>() / accuracies.len() as f64)
+        }
+    }
+}
+
+/// Analyses each position of a game in turn, yielding a [`PositionAnalysis`] for each. Each
+/// position takes [`ANNOTATION_TIME_PER_PLAY`] to analyse, so on the web, where it runs on the UI
+/// thread, positions are analysed one per frame.
+pub(crate) struct Annotator<T: BoardState> {
+    ai: BasicAi<T>,
+    /// The game, at the next position to analyse.
+    game: Game<T>,
+    plays: Vec<Play>,
+    done: bool
+}
+
+impl<T: BoardState> Annotator<T> {
+
+    pub(crate) fn new(setup: &GameSetup, plays: Vec<Play>) -> Result<Self, String> {
+        let game: Game<T> = Game::new(setup.ruleset, &setup.starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        // The side is set for each position analysed
+        let ai = BasicAi::new(game.logic, Attacker, ANNOTATION_TIME_PER_PLAY, Difficulty::Maximum)
+            .with_tablebase(setup.tablebase.clone())
+            .with_network(setup.network.clone());
+        Ok(Self { ai, game, plays, done: false })
+    }
+}
+
+impl<T: BoardState> Iterator for Annotator<T> {
+    type Item = PositionAnalysis;
+
+    fn next(&mut self) -> Option<PositionAnalysis> {
+        if self.done {
+            return None
+        }
+        let state = self.game.state;
+        let eval = match state.status {
+            Over(Win(_, Attacker)) => i32::MAX,
+            Over(Win(_, _)) => -i32::MAX,
+            Over(Draw(_)) => 0,
+            Ongoing => {
+                let scores = self.ai.score_plays(&state);
+                let Some(&(best, best_score)) = scores.first() else {
+                    self.done = true;
+                    return Some(PositionAnalysis { eval: 0, play: None })
+                };
+                let i = self.game.play_history.len();
+                if let Some(played) = self.plays.get(i).copied() {
+                    let annotation = scores.iter()
+                        .find(|(vp, _)| vp.play == played)
+                        .map(|(_, played_score)| PlayAnnotation {
+                            side: state.side_to_play,
+                            best_play: best.play,
+                            best_score,
+                            played_score: *played_score
+                        });
+                    if self.game.do_play(played).is_err() {
+                        self.done = true;
+                    }
+                    return Some(PositionAnalysis { eval: best_score, play: annotation })
+                }
+                best_score
+            }
+        };
+        // The last position of the game
+        self.done = true;
+        Some(PositionAnalysis { eval, play: None })
+    }
+}
@@ -1,26 +1,196 @@
-use crate::game_play_view::{GamePlayAction, GamePlayView};
+use crate::analysis_view::{AnalysisAction, AnalysisView};
+use crate::archive::{ArchivedGame, ARCHIVE_KEY};
+use crate::archive_view::{ArchiveAction, ArchiveView};
+use crate::custom_rules::{CustomVariant, CUSTOM_VARIANTS_KEY};
+use crate::game_play_view::{GamePlayAction, GamePlayView, GameSetup};
 use crate::game_setup_view::{GameSetupAction, GameSetupView};
+use crate::lobby_view::{LobbyAction, LobbyView};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::book_builder::load_book_files;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::tablebase_builder::load_tablebase;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::network;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::neural_trainer::load_network_files;
+use crate::neural_eval::EvalNetworks;
+use crate::opening_book::{books_from_archive, OpeningBook, OpeningBooks, OPENING_BOOKS_KEY};
+use crate::position_editor_view::{PositionEditorAction, PositionEditorView};
+use crate::puzzle::{Puzzle, PuzzleProgress, PUZZLE_PROGRESS_KEY};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::puzzle_generator::load_puzzle_files;
+use crate::puzzle_view::{PuzzleAction, PuzzleView};
+use crate::replay_view::{ReplayAction, ReplayView};
+use crate::saved_game::{SavedGame, SAVED_GAME_KEY};
+use crate::settings::{Settings, SettingsAction, SettingsView, SETTINGS_KEY};
 use eframe::{App, CreationContext, Frame};
 use std::process::exit;
 use egui::RichText;
 use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
 use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::play::Play;
+use std::sync::Arc;
+
+/// The name of the app, which also determines where eframe stores its data on native.
+pub(crate) const APP_NAME: &str = "Tafl egui demo";
 
 enum View {
     GameSetup(GameSetupView),
     GamePlay(GamePlayView<LargeBasicBoardState>),
+    PositionEditor(PositionEditorView),
+    Lobby(LobbyView),
+    Archive(ArchiveView),
+    Replay(ReplayView<LargeBasicBoardState>),
+    Analysis(AnalysisView<LargeBasicBoardState>),
+    Puzzle(PuzzleView),
+    Settings(SettingsView),
     About
 }
 
 pub(crate) struct MyApp {
     current_view: View,
+    /// Variants created by the user, persisted between sessions.
+    custom_variants: Vec<CustomVariant>,
+    /// User preferences, persisted between sessions.
+    settings: Settings,
+    /// The unfinished game (if any), autosaved after every play so it can be resumed.
+    saved_game: Option<SavedGame>,
+    /// Finished games, oldest first.
+    archive: Vec<ArchivedGame>,
+    /// Opening books built from the archive, by variant.
+    opening_books: OpeningBooks,
+    /// Opening books built by the `book` subcommand, by variant (always empty on the web).
+    book_files: OpeningBooks,
+    /// Evaluation networks trained by the `train` subcommand, by variant (always empty on the web).
+    networks: EvalNetworks,
+    /// Puzzles generated by the `puzzles` subcommand (always empty on the web).
+    puzzles: Vec<Arc<Puzzle>>,
+    /// The user's puzzle rating and results, persisted between sessions.
+    puzzle_progress: PuzzleProgress
 }
 
 impl MyApp {
     pub(crate) fn new(cc: &CreationContext) -> Self {
+        let custom_variants: Vec<CustomVariant> = cc.storage
+            .and_then(|s| eframe::get_value(s, CUSTOM_VARIANTS_KEY))
+            .unwrap_or_default();
+        let settings: Settings = cc.storage
+            .and_then(|s| eframe::get_value(s, SETTINGS_KEY))
+            .unwrap_or_default();
+        let saved_game: Option<SavedGame> = cc.storage
+            .and_then(|s| eframe::get_value(s, SAVED_GAME_KEY))
+            .unwrap_or_default();
+        let archive: Vec<ArchivedGame> = cc.storage
+            .and_then(|s| eframe::get_value(s, ARCHIVE_KEY))
+            .unwrap_or_default();
+        let opening_books: OpeningBooks = cc.storage
+            .and_then(|s| eframe::get_value(s, OPENING_BOOKS_KEY))
+            .unwrap_or_default();
+        let puzzle_progress: PuzzleProgress = cc.storage
+            .and_then(|s| eframe::get_value(s, PUZZLE_PROGRESS_KEY))
+            .unwrap_or_default();
+        #[cfg(not(target_arch = "wasm32"))]
+        let book_files = load_book_files();
+        #[cfg(target_arch = "wasm32")]
+        let book_files = OpeningBooks::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        let networks = load_network_files();
+        #[cfg(target_arch = "wasm32")]
+        let networks = EvalNetworks::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        let puzzles = load_puzzle_files();
+        #[cfg(target_arch = "wasm32")]
+        let puzzles = vec![];
+        cc.egui_ctx.set_theme(settings.theme);
         Self {
-            current_view: View::GameSetup(GameSetupView::default())
+            current_view: View::GameSetup(
+                GameSetupView::from_saved(&custom_variants, &settings, saved_game.as_ref())
+            ),
+            custom_variants,
+            settings,
+            saved_game,
+            archive,
+            opening_books,
+            book_files,
+            networks,
+            puzzles,
+            puzzle_progress
+        }
+    }
+
+    /// The opening book for the AI to use in games of `variant`, combining the books built from
+    /// the archive and by the `book` subcommand.
+    fn opening_book(&self, variant: &str) -> Option<Arc<OpeningBook>> {
+        let mut book: Option<OpeningBook> = None;
+        for b in [self.opening_books.get(variant), self.book_files.get(variant)].into_iter().flatten() {
+            book.get_or_insert_with(|| OpeningBook::new(variant)).merge(b);
         }
+        book.map(Arc::new)
+    }
+
+    /// Give the AI in games set up with `setup` the opening book and (on native) the endgame
+    /// tablebase for the variant, if there are any, and its evaluation network if the settings
+    /// say to use one.
+    fn with_ai_data(&self, setup: GameSetup) -> GameSetup {
+        #[cfg(not(target_arch = "wasm32"))]
+        let tablebase = load_tablebase(&setup.ruleset_name, &setup.ruleset).map(Arc::new);
+        #[cfg(target_arch = "wasm32")]
+        let tablebase = None;
+        GameSetup {
+            opening_book: self.opening_book(&setup.ruleset_name),
+            tablebase,
+            network: self.networks.get(&setup.ruleset_name).filter(|_| self.settings.use_network_eval).cloned(),
+            ..setup
+        }
+    }
+
+    fn game_setup_view(&self) -> View {
+        View::GameSetup(GameSetupView::from_saved(
+            &self.custom_variants,
+            &self.settings,
+            self.saved_game.as_ref()
+        ))
+    }
+
+    /// Start a new game, which replaces any saved unfinished game.
+    fn game_play_view(&mut self, setup: GameSetup) -> View {
+        let setup = self.with_ai_data(setup);
+        let view = GamePlayView::new(setup, &self.settings);
+        self.saved_game = view.saved_game();
+        View::GamePlay(view)
+    }
+
+    /// Analyse the position reached by making `plays`, in the archived game with index
+    /// `archive_index` if given, or `None` if the position cannot be reached.
+    fn analysis_view(&self, setup: GameSetup, plays: &[Play], archive_index: Option<usize>) -> Option<View> {
+        match AnalysisView::new(self.with_ai_data(setup), plays, archive_index, &self.settings) {
+            Ok(view) => Some(View::Analysis(view)),
+            Err(e) => {
+                log::error!("Could not analyse position: {e}");
+                None
+            }
+        }
+    }
+
+    /// Replay the archived game with index `i`, or `None` if it cannot be replayed.
+    fn replay_view(&self, i: usize) -> Option<View> {
+        match ReplayView::new(i, &self.archive[i], &self.settings) {
+            Ok(view) => Some(View::Replay(view)),
+            Err(e) => {
+                log::error!("Could not replay archived game: {e}");
+                None
+            }
+        }
+    }
+
+    /// Write everything that should persist between sessions to `storage`.
+    fn persist(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, CUSTOM_VARIANTS_KEY, &self.custom_variants);
+        eframe::set_value(storage, SETTINGS_KEY, &self.settings);
+        eframe::set_value(storage, SAVED_GAME_KEY, &self.saved_game);
+        eframe::set_value(storage, ARCHIVE_KEY, &self.archive);
+        eframe::set_value(storage, OPENING_BOOKS_KEY, &self.opening_books);
+        eframe::set_value(storage, PUZZLE_PROGRESS_KEY, &self.puzzle_progress);
     }
 
     fn about_view(&self, ctx: &egui::Context) -> bool {
@@ -53,11 +223,69 @@ impl MyApp {
 impl App for MyApp {
     
     fn update(&mut self, ctx: &egui::Context, frame: &mut Frame) {
+        let mut save_needed = false;
         let new_view = match self.current_view {
             View::GameSetup(ref mut game_setup_view) => {
                 // Game setup screen
                 match game_setup_view.update(ctx) {
-                    Some(GameSetupAction::StartGame(gs)) => Some(View::GamePlay(GamePlayView::new(gs))),
+                    Some(GameSetupAction::StartGame(gs)) => {
+                        game_setup_view.remember(&mut self.settings);
+                        save_needed = true;
+                        Some(self.game_play_view(gs))
+                    },
+                    Some(GameSetupAction::PlayOnline(gs)) => {
+                        game_setup_view.remember(&mut self.settings);
+                        Some(View::Lobby(LobbyView::new(self.settings.server_url.clone(), gs)))
+                    },
+                    Some(GameSetupAction::ResumeGame) => {
+                        let resumed = self.saved_game.as_ref()
+                            .map(|g| g.setup().and_then(|setup| {
+                                GamePlayView::resume(self.with_ai_data(setup), g, &self.settings)
+                            }));
+                        match resumed {
+                            Some(Ok(view)) => Some(View::GamePlay(view)),
+                            Some(Err(e)) => {
+                                log::error!("Could not resume saved game: {e}");
+                                self.saved_game = None;
+                                save_needed = true;
+                                Some(self.game_setup_view())
+                            },
+                            None => None
+                        }
+                    },
+                    Some(GameSetupAction::EditPosition(variants)) =>
+                        Some(View::PositionEditor(PositionEditorView::new(variants))),
+                    Some(GameSetupAction::SaveCustomVariants(variants)) => {
+                        self.custom_variants = variants;
+                        save_needed = true;
+                        None
+                    },
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Some(GameSetupAction::HostGame { setup, port }) => {
+                        game_setup_view.remember(&mut self.settings);
+                        let started = network::host(port)
+                            .and_then(|remote| GamePlayView::networked(setup, &self.settings, &[], remote));
+                        match started {
+                            Ok(view) => Some(View::GamePlay(view)),
+                            Err(e) => {
+                                log::error!("Could not host network game: {e}");
+                                game_setup_view.set_network_message(e);
+                                None
+                            }
+                        }
+                    },
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Some(GameSetupAction::JoinGame { setup, address }) =>
+                        Some(View::GamePlay(GamePlayView::join(setup, address, &self.settings))),
+                    Some(GameSetupAction::ViewArchive) =>
+                        Some(View::Archive(ArchiveView::new(self.archive.clone()))),
+                    Some(GameSetupAction::ViewPuzzles) => Some(View::Puzzle(
+                        PuzzleView::new(self.puzzles.clone(), self.puzzle_progress.clone(), &self.settings)
+                    )),
+                    Some(GameSetupAction::ViewSettings) => {
+                        game_setup_view.remember(&mut self.settings);
+                        Some(View::Settings(SettingsView::new(self.settings.clone())))
+                    },
                     Some(GameSetupAction::ViewAbout) => Some(View::About),
                     Some(GameSetupAction::Quit) => exit(0),
                     None => None,
@@ -65,15 +293,154 @@ impl App for MyApp {
             },
             View::GamePlay(ref mut game_play_view) => {
                 // Game play screen
-                match game_play_view.update(ctx) {
-                    Some(GamePlayAction::QuitGame) => Some(View::GameSetup(GameSetupView::default())),
-                    Some(GamePlayAction::QuitApp) => exit(0),
+                let action = game_play_view.update(ctx);
+                if game_play_view.changed_since_save() {
+                    if let Some(i) = game_play_view.branch_of() {
+                        // Games started from an archived game are stored as variations of it
+                        self.archive[i].add_variation(&game_play_view.plays());
+                    } else {
+                        self.saved_game = game_play_view.saved_game();
+                        if let Some(finished) = game_play_view.archived_game() {
+                            // A game that is finished again after undoing plays replaces its
+                            // earlier result.
+                            match self.archive.iter().position(|g| g.game.id == finished.game.id) {
+                                Some(i) => self.archive[i] = finished,
+                                None => self.archive.push(finished)
+                            }
+                        }
+                    }
+                    save_needed = true;
+                }
+                match action {
+                    Some(GamePlayAction::QuitGame) => match game_play_view.branch_of() {
+                        Some(i) => self.replay_view(i),
+                        None => Some(self.game_setup_view())
+                    },
+                    Some(GamePlayAction::QuitApp) => {
+                        if let Some(storage) = frame.storage_mut() {
+                            self.persist(storage);
+                            storage.flush();
+                        }
+                        exit(0)
+                    },
+                    Some(GamePlayAction::Rematch { mut setup, swap_sides }) => {
+                        if swap_sides {
+                            setup.ai_side = setup.ai_side.map(|s| s.other());
+                        }
+                        save_needed = true;
+                        Some(self.game_play_view(setup))
+                    },
+                    Some(GamePlayAction::Review) => {
+                        // A finished game has been added to the archive, unless it was a variation
+                        // of an archived game.
+                        let id = game_play_view.id();
+                        game_play_view.branch_of()
+                            .or_else(|| self.archive.iter().position(|g| g.game.id == id))
+                            .and_then(|i| self.replay_view(i))
+                    },
                     _ => None
                 }
             },
+            View::PositionEditor(ref mut position_editor_view) => {
+                match position_editor_view.update(ctx) {
+                    Some(PositionEditorAction::StartGame(gs)) => {
+                        save_needed = true;
+                        Some(self.game_play_view(gs))
+                    },
+                    Some(PositionEditorAction::Analyse(gs)) => self.analysis_view(gs, &[], None),
+                    Some(PositionEditorAction::Back) => Some(self.game_setup_view()),
+                    None => None
+                }
+            },
+            View::Lobby(ref mut lobby_view) => {
+                match lobby_view.update(ctx) {
+                    Some(LobbyAction::Start { client, game, side }) => {
+                        match GamePlayView::online(client, &game, side, &self.settings) {
+                            Ok(view) => Some(View::GamePlay(view)),
+                            Err(e) => {
+                                log::error!("Could not start online game: {e}");
+                                None
+                            }
+                        }
+                    },
+                    Some(LobbyAction::Back(server_url)) => {
+                        self.settings.server_url = server_url;
+                        save_needed = true;
+                        Some(self.game_setup_view())
+                    },
+                    None => None
+                }
+            },
+            View::Archive(ref mut archive_view) => {
+                match archive_view.update(ctx) {
+                    Some(ArchiveAction::Replay(i)) => self.replay_view(i),
+                    Some(ArchiveAction::BuildOpeningBooks) => {
+                        self.opening_books = books_from_archive(&self.archive);
+                        save_needed = true;
+                        archive_view.set_message(format!(
+                            "Built opening books for {} variant(s).",
+                            self.opening_books.len()
+                        ));
+                        None
+                    },
+                    Some(ArchiveAction::Back) => Some(self.game_setup_view()),
+                    None => None
+                }
+            },
+            View::Replay(ref mut replay_view) => {
+                match replay_view.update(ctx) {
+                    Some(ReplayAction::PlayFromHere { archive_index, setup, plays }) => {
+                        let setup = self.with_ai_data(setup);
+                        match GamePlayView::branch(archive_index, setup, &self.settings, &plays) {
+                            Ok(view) => Some(View::GamePlay(view)),
+                            Err(e) => {
+                                log::error!("Could not start game from archived position: {e}");
+                                None
+                            }
+                        }
+                    },
+                    Some(ReplayAction::Analyse { archive_index, setup, plays }) =>
+                        self.analysis_view(setup, &plays, Some(archive_index)),
+                    Some(ReplayAction::Back) =>
+                        Some(View::Archive(ArchiveView::new(self.archive.clone()))),
+                    None => None
+                }
+            },
+            View::Analysis(ref mut analysis_view) => {
+                match analysis_view.update(ctx) {
+                    Some(AnalysisAction::Back) => match analysis_view.archive_index() {
+                        Some(i) => self.replay_view(i),
+                        None => Some(self.game_setup_view())
+                    },
+                    None => None
+                }
+            },
+            View::Puzzle(ref mut puzzle_view) => {
+                match puzzle_view.update(ctx) {
+                    Some(PuzzleAction::SaveProgress(progress)) => {
+                        self.puzzle_progress = progress;
+                        save_needed = true;
+                        None
+                    },
+                    Some(PuzzleAction::Back) => Some(self.game_setup_view()),
+                    None => None
+                }
+            },
+            View::Settings(ref mut settings_view) => {
+                match settings_view.update(ctx) {
+                    Some(SettingsAction::Save(settings)) => {
+                        ctx.set_theme(settings.theme);
+                        self.settings = settings;
+                        save_needed = true;
+                        Some(self.game_setup_view())
+                    },
+                    Some(SettingsAction::Cancel) => Some(self.game_setup_view()),
+                    None => None
+                }
+            },
             View::About => {
                 if self.about_view(ctx) {
-                    Some(View::GameSetup(GameSetupView::default()))
+                    Some(self.game_setup_view())
                 } else {
                     None
                 }
@@ -82,5 +449,18 @@ impl App for MyApp {
         if let Some(view) = new_view {
             self.current_view = view;
         }
+        if save_needed {
+            if let Some(storage) = frame.storage_mut() {
+                self.persist(storage);
+                storage.flush();
+            }
+        }
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        if let View::GameSetup(ref game_setup_view) = self.current_view {
+            game_setup_view.remember(&mut self.settings);
+        }
+        self.persist(storage);
     }
 }
\ No newline at end of file
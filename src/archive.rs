@@ -0,0 +1,174 @@
+use crate::saved_game::{SavedGame, SavedSide};
+use hnefatafl::game::GameOutcome;
+use hnefatafl::pieces;
+use hnefatafl::play::Play;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+use web_time::{SystemTime, UNIX_EPOCH};
+
+/// Key under which the archive of finished games is saved in eframe's storage.
+pub(crate) const ARCHIVE_KEY: &str = "game_archive";
+
+/// The result of a finished game.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum ArchivedResult {
+    Win(SavedSide),
+    Draw
+}
+
+impl ArchivedResult {
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            ArchivedResult::Win(side) => format!("{:?} won", pieces::Side::from(*side)),
+            ArchivedResult::Draw => "Draw".to_string()
+        }
+    }
+}
+
+/// An alternative line of play, branching off another line. Together with the game's own plays
+/// (the main line), variations form a tree.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct Variation {
+    /// The number of plays of the parent line that are made before this variation starts.
+    pub(crate) branch_point: usize,
+    pub(crate) plays: Vec<String>,
+    /// Variations branching off this one.
+    pub(crate) variations: Vec<Variation>
+}
+
+/// Add `line` (a sequence of plays from the start of the game) to the tree formed by `plays` and
+/// the `variations` branching off it. The line follows existing lines for as long as it can, and
+/// becomes a new variation where it diverges from them. If `extendable` is true, a line that
+/// continues past the end of `plays` extends it rather than creating a variation.
+fn add_line(plays: &mut Vec<String>, variations: &mut Vec<Variation>, line: &[String], extendable: bool) {
+    let common = plays.iter().zip(line).take_while(|(a, b)| a == b).count();
+    if common == line.len() {
+        // Line is already in the tree
+        return
+    }
+    if common == plays.len() && extendable {
+        plays.extend_from_slice(&line[common..]);
+        return
+    }
+    let rest = &line[common..];
+    if let Some(v) = variations.iter_mut()
+        .find(|v| v.branch_point == common && v.plays.first() == rest.first()) {
+        add_line(&mut v.plays, &mut v.variations, rest, true);
+    } else {
+        variations.push(Variation { branch_point: common, plays: rest.to_vec(), variations: vec![] });
+    }
+}
+
+/// A finished game, as stored in the archive.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct ArchivedGame {
+    /// The game's setup and all the plays made.
+    pub(crate) game: SavedGame,
+    pub(crate) result: ArchivedResult,
+    /// Why the game ended (eg, `KingEscaped`).
+    pub(crate) reason: String,
+    /// When the game finished, in seconds since the Unix epoch.
+    pub(crate) finished_at: u64,
+    /// Alternative lines explored by playing on from earlier positions in the game.
+    #[serde(default)]
+    pub(crate) variations: Vec<Variation>
+}
+
+impl ArchivedGame {
+
+    pub(crate) fn new(game: SavedGame, outcome: GameOutcome) -> Self {
+        match outcome {
+            GameOutcome::Win(reason, side) =>
+                Self::with_result(game, ArchivedResult::Win(side.into()), format!("{reason:?}")),
+            GameOutcome::Draw(reason) =>
+                Self::with_result(game, ArchivedResult::Draw, format!("{reason:?}"))
+        }
+    }
+
+    /// Archive a game that ended with the given result, for the given reason (which may not be one
+    /// covered by the rules, such as resignation).
+    pub(crate) fn with_result(game: SavedGame, result: ArchivedResult, reason: String) -> Self {
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self { game, result, reason, finished_at, variations: vec![] }
+    }
+
+    /// Record a line of play (starting from the beginning of the game) as a variation, leaving the
+    /// game's own plays unchanged.
+    pub(crate) fn add_variation(&mut self, line: &[Play]) {
+        let line: Vec<String> = line.iter().map(|p| p.to_string()).collect();
+        add_line(&mut self.game.plays, &mut self.variations, &line, false);
+    }
+
+    /// The date the game finished, as `YYYY-MM-DD` (UTC).
+    pub(crate) fn date(&self) -> String {
+        // Convert days since the epoch to a civil date (see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days).
+        let z = (self.finished_at / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{year:04}-{month:02}-{day:02}")
+    }
+
+    /// The sides played by humans in this game (both sides if there was no AI).
+    pub(crate) fn human_sides(&self) -> Vec<SavedSide> {
+        match self.game.ai_side {
+            Some(SavedSide::Attacker) => vec![SavedSide::Defender],
+            Some(SavedSide::Defender) => vec![SavedSide::Attacker],
+            None => vec![SavedSide::Attacker, SavedSide::Defender]
+        }
+    }
+}
+
+/// Results of games played by humans as one side of one variant.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Record {
+    pub(crate) won: usize,
+    pub(crate) drawn: usize,
+    pub(crate) lost: usize
+}
+
+impl Record {
+
+    pub(crate) fn played(&self) -> usize {
+        self.won + self.drawn + self.lost
+    }
+
+    /// The proportion of games won, as a percentage.
+    pub(crate) fn win_rate(&self) -> f32 {
+        if self.played() == 0 {
+            0.0
+        } else {
+            100.0 * self.won as f32 / self.played() as f32
+        }
+    }
+}
+
+/// Win/draw/loss records of the human player, by variant name and side played. Games with no AI
+/// count towards the records of both sides.
+pub(crate) fn player_stats<'a>(
+    games: impl Iterator<Item = &'a ArchivedGame>
+) -> BTreeMap<(String, SavedSide), Record> {
+    let mut stats: BTreeMap<(String, SavedSide), Record> = BTreeMap::new();
+    for game in games {
+        for side in game.human_sides() {
+            let record = stats.entry((game.game.ruleset_name.clone(), side)).or_default();
+            match game.result {
+                ArchivedResult::Win(winner) if winner == side => record.won += 1,
+                ArchivedResult::Win(_) => record.lost += 1,
+                ArchivedResult::Draw => record.drawn += 1
+            }
+        }
+    }
+    stats
+}
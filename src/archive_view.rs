@@ -0,0 +1,194 @@
+use crate::ai::AiEngine;
+use crate::archive::{player_stats, ArchivedGame, ArchivedResult};
+use crate::saved_game::SavedSide;
+use egui::RichText;
+use hnefatafl::pieces;
+use std::collections::BTreeSet;
+
+pub(crate) enum ArchiveAction {
+    /// Open the archived game with the given index in the replay viewer.
+    Replay(usize),
+    /// Rebuild the opening books for the AI from the archived games.
+    BuildOpeningBooks,
+    /// Return to the game setup screen.
+    Back
+}
+
+/// Which results to show in the archive.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ResultFilter {
+    All,
+    AttackerWins,
+    DefenderWins,
+    Draws
+}
+
+impl ResultFilter {
+    fn matches(&self, result: ArchivedResult) -> bool {
+        match self {
+            ResultFilter::All => true,
+            ResultFilter::AttackerWins => result == ArchivedResult::Win(SavedSide::Attacker),
+            ResultFilter::DefenderWins => result == ArchivedResult::Win(SavedSide::Defender),
+            ResultFilter::Draws => result == ArchivedResult::Draw
+        }
+    }
+}
+
+/// Lists finished games, with statistics on how the player has fared in each variant.
+pub(crate) struct ArchiveView {
+    games: Vec<ArchivedGame>,
+    /// Only show games of this variant, or all variants if `None`.
+    variant_filter: Option<String>,
+    result_filter: ResultFilter,
+    /// Result of the last attempt to build opening books.
+    message: Option<String>
+}
+
+impl ArchiveView {
+
+    pub(crate) fn new(games: Vec<ArchivedGame>) -> Self {
+        Self {
+            games,
+            variant_filter: None,
+            result_filter: ResultFilter::All,
+            message: None
+        }
+    }
+
+    pub(crate) fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    fn filters_ui(&mut self, ui: &mut egui::Ui) {
+        let variants: BTreeSet<&String> = self.games.iter().map(|g| &g.game.ruleset_name).collect();
+        ui.horizontal(|ui| {
+            ui.label("Variant:");
+            egui::ComboBox::from_id_salt("archive_variant")
+                .selected_text(self.variant_filter.as_deref().unwrap_or("All"))
+                .show_ui(ui, |combo_box| {
+                    combo_box.selectable_value(&mut self.variant_filter, None, "All");
+                    for v in variants {
+                        combo_box.selectable_value(&mut self.variant_filter, Some(v.clone()), v.as_str());
+                    }
+                });
+            ui.label("Result:");
+            egui::ComboBox::from_id_salt("archive_result")
+                .selected_text(format!("{:?}", self.result_filter))
+                .show_ui(ui, |combo_box| {
+                    for filter in [
+                        ResultFilter::All,
+                        ResultFilter::AttackerWins,
+                        ResultFilter::DefenderWins,
+                        ResultFilter::Draws
+                    ] {
+                        combo_box.selectable_value(&mut self.result_filter, filter, format!("{filter:?}"));
+                    }
+                });
+        });
+    }
+
+    fn stats_ui(&self, ui: &mut egui::Ui) {
+        let stats = player_stats(self.games.iter()
+            .filter(|g| self.variant_filter.as_ref().is_none_or(|v| *v == g.game.ruleset_name)));
+        egui::Grid::new("archive_stats_grid").striped(true).show(ui, |ui| {
+            for heading in ["Variant", "Played as", "Games", "Won", "Drawn", "Lost", "Win rate"] {
+                ui.label(RichText::new(heading).strong());
+            }
+            ui.end_row();
+            for ((variant, side), record) in stats {
+                ui.label(variant);
+                ui.label(format!("{:?}", pieces::Side::from(side)));
+                ui.label(record.played().to_string());
+                ui.label(record.won.to_string());
+                ui.label(record.drawn.to_string());
+                ui.label(record.lost.to_string());
+                ui.label(format!("{:.0}%", record.win_rate()));
+                ui.end_row();
+            }
+        });
+    }
+
+    fn games_ui(&self, ui: &mut egui::Ui) -> Option<ArchiveAction> {
+        let mut action = None;
+        egui::Grid::new("archive_games_grid").striped(true).show(ui, |ui| {
+            for heading in ["Date", "Variant", "Opponent", "AI time", "Result", "Reason", "Plays", ""] {
+                ui.label(RichText::new(heading).strong());
+            }
+            ui.end_row();
+            // Most recent games first
+            for (i, g) in self.games.iter().enumerate().rev() {
+                if self.variant_filter.as_ref().is_some_and(|v| *v != g.game.ruleset_name)
+                    || !self.result_filter.matches(g.result) {
+                    continue
+                }
+                ui.label(g.date());
+                ui.label(&g.game.ruleset_name);
+                match g.game.ai_side {
+                    Some(side) if g.game.remote => {
+                        ui.label(format!("Remote player ({:?})", pieces::Side::from(side)));
+                        ui.label("-");
+                    },
+                    Some(side) => {
+                        let strength = match g.game.ai_engine {
+                            AiEngine::AlphaBeta => g.game.ai_difficulty.name(),
+                            AiEngine::Mcts(_) => "MCTS"
+                        };
+                        ui.label(format!("AI ({:?}, {strength})", pieces::Side::from(side)));
+                        ui.label(format!("{}s", g.game.ai_time_secs));
+                    },
+                    None => {
+                        ui.label("None");
+                        ui.label("-");
+                    }
+                }
+                ui.label(g.result.describe());
+                ui.label(&g.reason);
+                ui.label(g.game.plays.len().to_string());
+                if ui.button("Replay").clicked() {
+                    action = Some(ArchiveAction::Replay(i));
+                }
+                ui.end_row();
+            }
+        });
+        action
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<ArchiveAction> {
+        let mut action: Option<ArchiveAction> = None;
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.label(RichText::new("Game archive").heading());
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if ui.button("Back").clicked() {
+                action = Some(ArchiveAction::Back);
+            }
+            if self.games.is_empty() {
+                ui.label("No finished games yet.");
+                return
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Build opening books").on_hover_text(
+                    "Teach the AI the openings played in these games"
+                ).clicked() {
+                    action = Some(ArchiveAction::BuildOpeningBooks);
+                }
+                if let Some(msg) = &self.message {
+                    ui.label(msg);
+                }
+            });
+            self.filters_ui(ui);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::CollapsingHeader::new("Statistics").default_open(true).show(ui, |ui| {
+                    self.stats_ui(ui);
+                });
+                egui::CollapsingHeader::new("Games").default_open(true).show(ui, |ui| {
+                    if let Some(a) = self.games_ui(ui) {
+                        action = Some(a);
+                    }
+                });
+            });
+        });
+        action
+    }
+
+}
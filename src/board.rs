@@ -1,43 +1,57 @@
 use eframe::epaint::Color32;
-use egui::{Align2, FontId, Rect, Response, Vec2};
+use egui::{Align2, FontId, Id, Pos2, Rect, Response, Stroke, Vec2, WidgetInfo, WidgetType};
 use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
 use hnefatafl::game::Game;
+use hnefatafl::game::GameOutcome::Win;
+use hnefatafl::game::GameStatus::{Ongoing, Over};
 use hnefatafl::pieces;
 use hnefatafl::pieces::{Piece, PieceType, PlacedPiece};
-use hnefatafl::play::{Play, PlayRecord};
+use hnefatafl::play::{Play, PlayRecord, ValidPlay};
 use hnefatafl::tiles::{Axis, Tile};
 use std::collections::{HashMap, HashSet};
 
-struct TileColors {
-    throne: Color32,
-    corner: Color32,
-    base_camp: Color32,
-    plain: Color32,
-    possible_dest: Color32,
-    selected: Color32
+pub(crate) struct TileColors {
+    pub(crate) throne: Color32,
+    pub(crate) corner: Color32,
+    pub(crate) base_camp: Color32,
+    pub(crate) plain: Color32,
+    pub(crate) possible_dest: Color32,
+    pub(crate) selected: Color32,
+    pub(crate) cursor: Color32,
+    pub(crate) en_prise: Color32,
+    pub(crate) king_escape: Color32,
+    pub(crate) capture_dest: Color32,
+    pub(crate) premove: Color32
 }
 
-const TILE_COLORS: TileColors = TileColors {
+pub(crate) const TILE_COLORS: TileColors = TileColors {
     throne: Color32::from_gray(180),
     corner: Color32::from_gray(180),
     base_camp: Color32::from_gray(180),
     plain: Color32::from_rgb(255, 255, 240),
     selected: Color32::from_rgb(200, 240, 200),
-    possible_dest: Color32::from_rgb(200, 240, 200)
+    possible_dest: Color32::from_rgb(200, 240, 200),
+    cursor: Color32::from_rgb(40, 90, 200),
+    en_prise: Color32::from_rgb(220, 40, 40),
+    king_escape: Color32::from_rgb(230, 180, 20),
+    capture_dest: Color32::from_rgb(240, 130, 30),
+    premove: Color32::from_rgb(190, 200, 250)
 };
 
-struct Figures {
-    king: char,
-    white_soldier: char,
-    black_soldier: char,
-    up_arrow: char,
-    down_arrow: char,
-    left_arrow: char,
-    right_arrow: char,
-    captured_tile: char,
+pub(crate) struct Figures {
+    pub(crate) king: char,
+    pub(crate) white_soldier: char,
+    pub(crate) black_soldier: char,
+    pub(crate) up_arrow: char,
+    pub(crate) down_arrow: char,
+    pub(crate) left_arrow: char,
+    pub(crate) right_arrow: char,
+    pub(crate) captured_tile: char,
 }
 
-const FIGURES: Figures = Figures {
+pub(crate) const FIGURES: Figures = Figures {
     king: '♔',
     white_soldier: '♙',
     black_soldier: '♟',
@@ -48,6 +62,41 @@ const FIGURES: Figures = Figures {
     captured_tile: '🗙'
 };
 
+/// How long it takes to animate a piece moving, in seconds.
+const MOVE_ANIM_SECS: f32 = 0.25;
+
+pub(crate) fn piece_figure(piece: Piece) -> char {
+    match piece {
+        Piece {piece_type: PieceType::King, side: pieces::Side::Defender} => FIGURES.king,
+        Piece {piece_type: PieceType::Soldier, side: pieces::Side::Defender} => FIGURES.white_soldier,
+        Piece {piece_type: PieceType::Soldier, side: pieces::Side::Attacker} => FIGURES.black_soldier,
+        _ => panic!("Unexpected piece type")
+    }
+}
+
+/// The figure marking `tile` if it was affected by `record` (the last play made): an arrow on the
+/// tile the piece moved from, pointing the way it moved, or a cross where a piece was captured.
+pub(crate) fn last_play_figure<T: BoardState>(
+    record: &PlayRecord<T>,
+    tile: Tile,
+    flipped: bool
+) -> Option<char> {
+    // Arrows point the other way if the board is flipped
+    let forward = (record.play.movement.displacement > 0) != flipped;
+    if record.effects.captures.into_iter().any(|p: PlacedPiece| p.tile == tile) {
+        Some(FIGURES.captured_tile)
+    } else if record.play.from == tile {
+        Some(match (record.play.movement.axis, forward) {
+            (Axis::Vertical, true) => FIGURES.down_arrow,
+            (Axis::Vertical, false) => FIGURES.up_arrow,
+            (Axis::Horizontal, true) => FIGURES.right_arrow,
+            (Axis::Horizontal, false) => FIGURES.left_arrow
+        })
+    } else {
+        None
+    }
+}
+
 struct TileState {
     piece: Option<Piece>,
     is_throne: bool,
@@ -69,8 +118,77 @@ impl TileState {
             is_base_camp
         }
     }
+
+    /// A plain English description of the tile's contents and role, used as the accessibility
+    /// label for the tile.
+    fn describe(&self, tile: Tile) -> String {
+        let mut desc = format!("{tile}: ");
+        desc.push_str(match self.piece {
+            Some(Piece {piece_type: PieceType::King, ..}) => "king",
+            Some(Piece {piece_type: PieceType::Soldier, side: pieces::Side::Defender}) => "defender",
+            Some(Piece {piece_type: PieceType::Soldier, side: pieces::Side::Attacker}) => "attacker",
+            _ => "empty"
+        });
+        if self.is_throne {
+            desc.push_str(", throne");
+        } else if self.is_corner {
+            desc.push_str(", corner");
+        } else if self.is_base_camp {
+            desc.push_str(", base camp");
+        }
+        desc
+    }
+}
+
+/// Which of the optional tactical overlays should be drawn on the board.
+#[derive(Default, Clone, Copy)]
+pub(crate) struct Overlays {
+    /// Outline pieces (of either side) that could be captured on the next play.
+    pub(crate) en_prise: bool,
+    /// Highlight tiles the king could move to in a single play to win the game.
+    pub(crate) king_escapes: bool,
+    /// Mark destinations of the selected piece that would capture an enemy piece.
+    pub(crate) capture_dests: bool
+}
+
+/// Tactical features of a position, used to draw the overlays.
+#[derive(Default)]
+struct Threats {
+    /// Tiles containing pieces that could be captured on the next play by the other side.
+    en_prise: HashSet<Tile>,
+    /// Tiles the king could move to in a single play to win the game.
+    king_escapes: HashSet<Tile>
 }
 
+impl Threats {
+    fn new<T: BoardState>(logic: &GameLogic<T>, state: &GameState<T>) -> Self {
+        let mut threats = Self::default();
+        if state.status != Ongoing {
+            return threats
+        }
+        // Consider the position from the point of view of both sides, as if it were their turn,
+        // so that we can show threats against the side to play as well as the side that has just
+        // played.
+        for side in [pieces::Side::Attacker, pieces::Side::Defender] {
+            let mut s = *state;
+            s.side_to_play = side;
+            for tile in s.board.occupied_by_side(side) {
+                let piece = s.board.get_piece(tile).expect("There should be a piece here.");
+                let Ok(plays) = logic.iter_plays(tile, &s) else { continue };
+                for vp in plays {
+                    threats.en_prise.extend(logic.get_captures(vp, piece, &s).occupied());
+                    if piece.piece_type == PieceType::King {
+                        if let Over(Win(_, pieces::Side::Defender)) =
+                            logic.do_valid_play(vp, s).new_state.status {
+                            threats.king_escapes.insert(vp.play.to());
+                        }
+                    }
+                }
+            }
+        }
+        threats
+    }
+}
 
 pub(crate) struct Board<T: BoardState> {
     /// The state of each tile.
@@ -79,17 +197,38 @@ pub(crate) struct Board<T: BoardState> {
     selected_tiles: (Option<Tile>, Option<Tile>),
     /// Possible destinations of the currently selected piece.
     possible_dests: HashSet<Tile>,
+    /// Possible destinations of the currently selected piece that would capture a piece.
+    capture_dests: HashSet<Tile>,
+    /// Which tactical overlays to draw.
+    pub(crate) overlays: Overlays,
+    /// Tactical features of the position, along with the state they were calculated for.
+    threats: Option<(GameState<T>, Threats)>,
     /// The last play that was made.
     last_play: Option<PlayRecord<T>>,
-    /// The side that the human is playing as.
-    human_side: pieces::Side,
+    /// A play queued by the human while the AI is thinking, to be made once the AI has played
+    /// (if it is still legal).
+    premove: Option<Play>,
+    /// The tile that currently has keyboard focus, if any.
+    cursor: Option<Tile>,
+    /// The side that the human is playing as, or `None` if humans are playing both sides.
+    human_side: Option<pieces::Side>,
     /// The length of the board in tiles.
-    board_len_tiles: u8
+    board_len_tiles: u8,
+    /// Whether to draw the board rotated by 180 degrees.
+    pub(crate) flipped: bool,
+    /// Whether to animate pieces moving.
+    pub(crate) animate_moves: bool,
+    /// Whether the board only displays the game, ignoring clicks (eg, when replaying a game).
+    pub(crate) read_only: bool,
+    /// The number of plays in the game when the board was last drawn.
+    n_plays_seen: usize,
+    /// The ID of the animation of the last play, if it is still running.
+    move_anim: Option<Id>
 }
 
 impl<T: BoardState> Board<T> {
 
-    pub(crate) fn new(game: &Game<T>, human_side: pieces::Side) -> Self {
+    pub(crate) fn new(game: &Game<T>, human_side: Option<pieces::Side>) -> Self {
         let mut tile_state: HashMap<Tile, TileState> = HashMap::new();
         for tile in game.logic.board_geo.iter_tiles() {
             tile_state.insert(tile, TileState::new(
@@ -103,9 +242,19 @@ impl<T: BoardState> Board<T> {
             tile_state,
             selected_tiles: (None, None),
             possible_dests: HashSet::new(),
+            capture_dests: HashSet::new(),
+            overlays: Overlays::default(),
+            threats: None,
             last_play: None,
+            premove: None,
+            cursor: None,
             human_side,
-            board_len_tiles: game.logic.board_geo.side_len
+            board_len_tiles: game.logic.board_geo.side_len,
+            flipped: false,
+            animate_moves: false,
+            read_only: false,
+            n_plays_seen: game.play_history.len(),
+            move_anim: None
         }
     }
     fn update_tile_state(&mut self, board_state: T) {
@@ -114,10 +263,62 @@ impl<T: BoardState> Board<T> {
         }
     }
     
+    /// Handle the user choosing a tile, either by clicking on it or by pressing Enter while it
+    /// has keyboard focus.
+    fn select_tile(&mut self, game: &Game<T>, tile: Tile) {
+        if game.state.status != Ongoing {
+            return
+        }
+        if self.premove.is_some_and(|p| p.from == tile || p.to() == tile) {
+            // User has chosen a tile that is part of the queued premove, cancelling it.
+            self.premove = None;
+            return
+        }
+        let human_side = self.human_side.unwrap_or(game.state.side_to_play);
+        if game.state.board.get_piece(tile).is_some_and(|p| p.side == human_side) {
+            // We have chosen a tile containing our own piece. If it is not our turn, the play will
+            // be queued as a premove, so we generate plays as if it were our turn.
+            self.selected_tiles.0 = Some(tile);
+            let mut state = game.state;
+            state.side_to_play = human_side;
+            if let Ok(iter) = game.logic.iter_plays(tile, &state) {
+                let plays: Vec<ValidPlay> = iter.collect();
+                self.possible_dests = plays.iter().map(|p| p.play.to()).collect();
+                let piece = state.board.get_piece(tile).expect("There should be a piece here.");
+                self.capture_dests = plays.into_iter()
+                    .filter(|p| game.logic.get_captures(*p, piece, &state).occupied().next().is_some())
+                    .map(|p| p.play.to())
+                    .collect();
+            };
+        } else if Some(tile) == self.selected_tiles.0 {
+            // User has chosen a tile again, unselecting it.
+            self.selected_tiles.0 = None;
+            self.possible_dests = HashSet::new();
+            self.capture_dests = HashSet::new();
+        } else if self.selected_tiles.0.is_some() && self.possible_dests.contains(&tile) {
+            // We have selected a valid destination tile.
+            self.selected_tiles.1 = Some(tile);
+        }
+    }
+
     fn calc_tile_side_px(&self, board_side_px: f32) -> f32 {
         (board_side_px - self.board_len_tiles as f32) / (self.board_len_tiles as f32)
     }
 
+    /// The position of the top left corner of the given tile, taking into account whether the
+    /// board is flipped.
+    fn tile_top_left(&self, tile: Tile, tile_len_px: f32) -> Pos2 {
+        let (row, col) = if self.flipped {
+            (self.board_len_tiles - 1 - tile.row, self.board_len_tiles - 1 - tile.col)
+        } else {
+            (tile.row, tile.col)
+        };
+        egui::pos2(
+            (tile_len_px + 1.0) * col as f32,
+            (tile_len_px + 1.0) * row as f32
+        )
+    }
+
     pub(crate) fn update(
         &mut self,
         game: &Game<T>,
@@ -125,16 +326,49 @@ impl<T: BoardState> Board<T> {
         &mut egui::Ui,
         board_side_px: f32
     ) -> Option<Play> {
-        if let Some(last_play) = game.play_history.last() {
-            self.last_play = Some(last_play.clone());
+        self.last_play = game.play_history.last().cloned();
+        if game.play_history.len() != self.n_plays_seen {
+            // A play has been made (or undone). Only animate new plays.
+            self.move_anim = None;
+            if self.animate_moves && game.play_history.len() > self.n_plays_seen {
+                let id = ui.id().with(("move_anim", game.play_history.len()));
+                // The first call registers the animation at its start value
+                ctx.animate_value_with_time(id, 0.0, MOVE_ANIM_SECS);
+                self.move_anim = Some(id);
+            }
+            self.n_plays_seen = game.play_history.len();
         }
+        let anim_progress = self.move_anim
+            .map(|id| ctx.animate_value_with_time(id, 1.0, MOVE_ANIM_SECS))
+            .filter(|t| *t < 1.0);
+        if anim_progress.is_none() {
+            self.move_anim = None;
+        }
+        // The play being animated, and how far through the animation we are
+        let animating = anim_progress.zip(self.last_play.as_ref().map(|r| r.play));
         self.update_tile_state(game.state.board);
+        // Only recalculate threats when the position has changed and an overlay needs them, as it
+        // requires generating all plays for both sides.
+        let needs_threats = self.overlays.en_prise || self.overlays.king_escapes;
+        let threats = match self.threats.take() {
+            _ if !needs_threats => Threats::default(),
+            Some((state, threats)) if state == game.state => threats,
+            _ => Threats::new(&game.logic, &game.state)
+        };
         
         let tile_len_px = self.calc_tile_side_px(board_side_px);
 
         let tile_size_px = Vec2::new(tile_len_px, tile_len_px);
+        // Allocate tiles in reading order so that keyboard navigation (Tab) moves through the
+        // board row by row.
+        let mut tiles: Vec<Tile> = self.tile_state.keys().copied().collect();
+        tiles.sort_by_key(|t| (t.row, t.col));
+        if self.flipped {
+            tiles.reverse();
+        }
         let mut responses: Vec<(Response, Rect, Color32, Tile)> = vec![];
-        for (tile, state) in &self.tile_state {
+        for tile in tiles {
+            let state = &self.tile_state[&tile];
             let color = if self.possible_dests.contains(&tile) {
                 TILE_COLORS.possible_dest
             } else if state.is_throne {
@@ -143,73 +377,71 @@ impl<T: BoardState> Board<T> {
                 TILE_COLORS.corner
             } else if state.is_base_camp {
                 TILE_COLORS.base_camp
-            } else if self.selected_tiles.0 == Some(*tile) {
+            } else if self.selected_tiles.0 == Some(tile) {
                 TILE_COLORS.selected
-            } else if self.possible_dests.contains(&tile) {
-                TILE_COLORS.possible_dest
+            } else if self.premove.is_some_and(|p| p.from == tile || p.to() == tile) {
+                TILE_COLORS.premove
             } else {
                 TILE_COLORS.plain
             };
-            let top_left = egui::pos2(
-                (tile_len_px + 1.0) * tile.col as f32,
-                (tile_len_px + 1.0) * tile.row as f32
-            );
+            let top_left = self.tile_top_left(tile, tile_len_px);
             let bottom_right = top_left + tile_size_px;
             let rect = egui::Rect::from_two_pos(top_left, bottom_right);
             let response = ui.allocate_rect(rect, egui::Sense::click());
-            responses.push((response, rect, color, *tile));
+            let mut label = state.describe(tile);
+            if self.possible_dests.contains(&tile) {
+                label.push_str(", possible destination");
+            }
+            if self.premove.is_some_and(|p| p.from == tile || p.to() == tile) {
+                label.push_str(", premove");
+            }
+            if self.overlays.capture_dests && self.capture_dests.contains(&tile) {
+                label.push_str(", captures");
+            }
+            if self.overlays.en_prise && threats.en_prise.contains(&tile) {
+                label.push_str(", under threat");
+            }
+            if self.overlays.king_escapes && threats.king_escapes.contains(&tile) {
+                label.push_str(", king escape");
+            }
+            let selected = self.selected_tiles.0 == Some(tile);
+            response.widget_info(|| WidgetInfo::selected(WidgetType::Button, true, selected, &label));
+            responses.push((response, rect, color, tile));
         }
+        // The keyboard cursor follows whichever tile has focus (egui moves focus between tiles with
+        // the arrow keys and Tab, and treats Enter or Space on a focused tile as a click).
+        self.cursor = responses.iter()
+            .find(|(r, ..)| r.has_focus())
+            .map(|(.., tile)| *tile);
         let painter = ui.painter();
         for (response, rect, color, tile) in responses {
-            if response.clicked() {
-                if game.state.board.get_piece(tile).is_some_and(|p|
-                    p.side == game.state.side_to_play && p.side == self.human_side
-                ) {
-                    // We have clicked on a tile containing our own piece and it is our turn
-                    self.selected_tiles.0 = Some(tile);
-                    if let Ok(iter) = game.iter_plays(tile) {
-                        self.possible_dests = iter.map(|p| p.play.to()).collect();
-                    };
-                } else if Some(tile) == self.selected_tiles.0 {
-                    // User has clicked a tile again, unselecting it.
-                    self.selected_tiles.0 = None;
-                    self.possible_dests = HashSet::new();
-                } else if self.selected_tiles.0.is_some() && self.possible_dests.contains(&tile) {
-                    // We have selected a valid destination tile.
-                    self.selected_tiles.1 = Some(tile);
-                }
+            if response.clicked() && !self.read_only {
+                self.select_tile(game, tile);
             }
             painter.rect_filled(rect, 0.0, color);
+            if self.overlays.king_escapes && threats.king_escapes.contains(&tile) {
+                painter.rect_stroke(rect.shrink(2.0), 0.0, Stroke::new(4.0, TILE_COLORS.king_escape));
+            }
+            if self.overlays.capture_dests && self.capture_dests.contains(&tile) {
+                painter.circle_filled(rect.center(), tile_len_px * 0.15, TILE_COLORS.capture_dest);
+            }
+            if self.overlays.en_prise && threats.en_prise.contains(&tile) {
+                painter.circle_stroke(
+                    rect.center(),
+                    tile_len_px * 0.45,
+                    Stroke::new(2.0, TILE_COLORS.en_prise)
+                );
+            }
 
             let fig_opt = if let Some(piece) = game.state.board.get_piece(tile) {
-                Some(match piece {
-                    Piece {piece_type: PieceType::King, side: pieces::Side::Defender} => FIGURES.king,
-                    Piece {piece_type: PieceType::Soldier, side: pieces::Side::Defender} => FIGURES.white_soldier,
-                    Piece {piece_type: PieceType::Soldier, side: pieces::Side::Attacker} => FIGURES.black_soldier,
-                    _ => panic!("Unexpected piece type")
-                })
-            } else if let Some(play_record) = &self.last_play {
-                if play_record.effects.captures.into_iter().any(|p: PlacedPiece| p.tile == tile) {
-                    Some(FIGURES.captured_tile)
-                } else if play_record.play.from == tile {
-                    Some(if play_record.play.movement.axis == Axis::Vertical {
-                        if play_record.play.movement.displacement > 0 {
-                            FIGURES.down_arrow
-                        } else {
-                            FIGURES.up_arrow
-                        }
-                    } else {
-                        if play_record.play.movement.displacement > 0 {
-                            FIGURES.right_arrow
-                        } else {
-                            FIGURES.left_arrow
-                        }
-                    })
-                } else {
+                if animating.is_some_and(|(_, play)| play.to() == tile) {
+                    // Moving piece is drawn separately below
                     None
+                } else {
+                    Some(piece_figure(piece))
                 }
             } else {
-                None
+                self.last_play.as_ref().and_then(|r| last_play_figure(r, tile, self.flipped))
             };
             if let Some(fig) = fig_opt {
                 painter.text(
@@ -224,9 +456,31 @@ impl<T: BoardState> Board<T> {
                 //     .tint(Color32::LIGHT_BLUE);
                 // img.paint_at(ui, rect);
             }
+            if self.cursor == Some(tile) {
+                painter.rect_stroke(rect.shrink(1.0), 0.0, Stroke::new(3.0, TILE_COLORS.cursor));
+            }
+        }
+
+        if let Some((t, play)) = animating {
+            if let Some(piece) = game.state.board.get_piece(play.to()) {
+                let half_tile = Vec2::splat(tile_len_px / 2.0);
+                let from = self.tile_top_left(play.from, tile_len_px) + half_tile;
+                let to = self.tile_top_left(play.to(), tile_len_px) + half_tile;
+                painter.text(
+                    from.lerp(to, t),
+                    Align2::CENTER_CENTER,
+                    piece_figure(piece),
+                    FontId::proportional(tile_len_px * 0.9),
+                    Color32::BLACK,
+                );
+            }
+        }
+
+        if needs_threats {
+            self.threats = Some((game.state, threats));
         }
 
-        if game.state.side_to_play == self.human_side.other() {
+        if self.human_side.is_some_and(|s| s != game.state.side_to_play) {
             // If it's the AI's turn, we need to constantly repaint as egui won't automatically
             // detect when the AI thread has returned a play.  On native, this could be called from
             // the AI thread only when it has selected a play, but this doesn't work on web as only
@@ -238,10 +492,22 @@ impl<T: BoardState> Board<T> {
             // Human has made a play
             self.selected_tiles = (None, None);
             self.possible_dests = HashSet::new();
-            Some(Play::from_tiles(from, to).unwrap())
+            self.capture_dests = HashSet::new();
+            let play = Play::from_tiles(from, to).unwrap();
+            if self.human_side.is_none_or(|s| s == game.state.side_to_play) {
+                Some(play)
+            } else {
+                self.premove = Some(play);
+                None
+            }
         } else {
             None
         }
     }
 
+    /// Remove and return the queued premove, if any.
+    pub(crate) fn take_premove(&mut self) -> Option<Play> {
+        self.premove.take()
+    }
+
 }
\ No newline at end of file
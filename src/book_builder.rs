@@ -0,0 +1,215 @@
+use crate::ai::{Ai, BasicAi, Difficulty};
+use crate::app::APP_NAME;
+use crate::archive::ArchivedResult;
+use crate::custom_rules::preset_variants;
+use crate::opening_book::{OpeningBook, OpeningBooks};
+use crate::saved_game::parse_plays;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameOutcome::{Draw, Win};
+use hnefatafl::game::GameStatus::{Ongoing, Over};
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::play::Play;
+use hnefatafl::rules::Ruleset;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Self-play games are abandoned (and counted as draws) after this many plays.
+const MAX_SELF_PLAY_PLIES: usize = 300;
+
+pub(crate) const BOOK_USAGE: &str = "\
+Usage: hnefatafl-egui book <variant> [--self-play <games>] [--time <seconds>] [--difficulty <level>]
+                          [--games <games file>] [--output <book file>]
+
+Build an opening book for a preset variant, from games played by the AI against itself and/or from
+a file of games (one per line, with plays like \"d1-d4\" separated by whitespace or commas). Plays
+are weighted by how the game turned out for the side that made them. The book is merged into any
+existing book at the output path, which defaults to the directory the app loads opening books from.
+Self-play uses 1 second per move at Hard difficulty unless told otherwise.";
+
+/// The directory from which the app loads opening books built by the `book` subcommand.
+pub(crate) fn books_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|d| d.join("opening_books"))
+}
+
+/// A file name for data about `variant`, with the given extension.
+pub(crate) fn variant_file_name(variant: &str, extension: &str) -> String {
+    let stem: String = variant.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    format!("{stem}.{extension}")
+}
+
+/// Where a subcommand writes its output for `variant`: the `--output` path if one was given, or
+/// else the file named by [`variant_file_name`] in `dir`, the directory the app loads it from.
+pub(crate) fn output_path(
+    output: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    variant: &str,
+    extension: &str
+) -> Result<PathBuf, String> {
+    match output {
+        Some(path) => Ok(path),
+        None => Ok(dir
+            .ok_or("Could not find the app's data directory; use --output.".to_string())?
+            .join(variant_file_name(variant, extension)))
+    }
+}
+
+/// Write a subcommand's output to `path`, creating its directory if need be.
+pub(crate) fn write_output(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), String> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+    }
+    std::fs::write(path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+fn read_book(path: &Path) -> Result<OpeningBook, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("Could not parse {}: {e}", path.display()))
+}
+
+/// Load all opening books in [`books_dir`], by variant. Files that cannot be read are skipped.
+pub(crate) fn load_book_files() -> OpeningBooks {
+    let mut books = OpeningBooks::new();
+    let Some(entries) = books_dir().and_then(|d| std::fs::read_dir(d).ok()) else { return books };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue
+        }
+        match read_book(&path) {
+            Ok(book) => books.entry(book.variant.clone())
+                .or_insert_with(|| OpeningBook::new(book.variant.clone()))
+                .merge(&book),
+            Err(e) => log::warn!("{e}")
+        }
+    }
+    books
+}
+
+/// Parse the name of a difficulty level, ignoring case.
+pub(crate) fn parse_difficulty(name: &str) -> Result<Difficulty, String> {
+    Difficulty::ALL.into_iter()
+        .find(|d| d.name().eq_ignore_ascii_case(name))
+        .ok_or(format!("Unknown difficulty {name:?}."))
+}
+
+/// Parse plays like "d1-d4", separated by whitespace or commas.
+pub(crate) fn parse_play_list(text: &str) -> Result<Vec<Play>, String> {
+    let plays: Vec<String> = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|p| !p.is_empty())
+        .map(String::from)
+        .collect();
+    parse_plays(&plays)
+}
+
+/// Read a file of games, one per line (see [`parse_play_list`]), returning the line number of each
+/// game with its plays. Blank lines are skipped.
+pub(crate) fn read_games_file(path: &str) -> Result<Vec<(usize, Vec<Play>)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, line)| {
+            let plays = parse_play_list(line).map_err(|e| format!("Line {}: {e}", i + 1))?;
+            Ok((i + 1, plays))
+        })
+        .collect()
+}
+
+/// Have the AI play a game against itself, returning the plays made and the result (or `None` if
+/// the game was abandoned).
+pub(crate) fn self_play_game(
+    ruleset: Ruleset,
+    starting_board: &str,
+    time_to_play: Duration,
+    difficulty: Difficulty
+) -> Result<(Vec<Play>, Option<ArchivedResult>), String> {
+    let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    let mut attacker = BasicAi::new(game.logic, Attacker, time_to_play, difficulty);
+    let mut defender = BasicAi::new(game.logic, Defender, time_to_play, difficulty);
+    while game.state.status == Ongoing && game.play_history.len() < MAX_SELF_PLAY_PLIES {
+        let ai = if game.state.side_to_play == Attacker { &mut attacker } else { &mut defender };
+        let (vp, _) = ai.next_play(&game.state)
+            .map_err(|_| "The AI could not find a play.".to_string())?;
+        game.do_play(vp.play).map_err(|e| format!("The AI made an illegal play {vp}: {e:?}"))?;
+    }
+    Ok((game.play_history.iter().map(|r| r.play).collect(), result(&game)))
+}
+
+/// The result of `game`, or `None` if it is not over.
+fn result(game: &Game<LargeBasicBoardState>) -> Option<ArchivedResult> {
+    match game.state.status {
+        Over(Win(_, side)) => Some(ArchivedResult::Win(side.into())),
+        Over(Draw(_)) => Some(ArchivedResult::Draw),
+        Ongoing => None
+    }
+}
+
+/// The result of the game made up of `plays`, or `None` if it did not finish.
+fn game_result(ruleset: Ruleset, starting_board: &str, plays: &[Play]) -> Result<Option<ArchivedResult>, String> {
+    let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    for play in plays {
+        game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+    }
+    Ok(result(&game))
+}
+
+/// Run the `book` subcommand with the given arguments.
+pub(crate) fn run_book_command(args: &[String]) -> Result<(), String> {
+    let Some((variant, mut rest)) = args.split_first() else {
+        return Err(BOOK_USAGE.to_string())
+    };
+    let (ruleset, starting_board) = preset_variants().remove(variant.as_str())
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let mut self_play_games = 0u32;
+    let mut time_to_play = Duration::from_secs(1);
+    let mut difficulty = Difficulty::Hard;
+    let mut games_path: Option<&String> = None;
+    let mut output: Option<PathBuf> = None;
+    while let [flag, value, tail @ ..] = rest {
+        match flag.as_str() {
+            "--self-play" => self_play_games = value.parse()
+                .map_err(|_| format!("Invalid number of games {value:?}."))?,
+            "--time" => time_to_play = value.parse().map(Duration::from_secs_f64)
+                .map_err(|_| format!("Invalid time {value:?}."))?,
+            "--difficulty" => difficulty = parse_difficulty(value)?,
+            "--games" => games_path = Some(value),
+            "--output" => output = Some(PathBuf::from(value)),
+            _ => return Err(BOOK_USAGE.to_string())
+        }
+        rest = tail;
+    }
+    if !rest.is_empty() || (self_play_games == 0 && games_path.is_none()) {
+        return Err(BOOK_USAGE.to_string())
+    }
+    let output = output_path(output, books_dir(), variant, "json")?;
+
+    let mut book = if output.exists() { read_book(&output)? } else { OpeningBook::new(variant.as_str()) };
+    if let Some(path) = games_path {
+        for (line, plays) in read_games_file(path)? {
+            let result = game_result(ruleset, &starting_board, &plays)
+                .map_err(|e| format!("Line {line}: {e}"))?;
+            book.add_game(ruleset, &starting_board, &plays, result)?;
+        }
+    }
+    for i in 0..self_play_games {
+        let (plays, result) = self_play_game(ruleset, &starting_board, time_to_play, difficulty)?;
+        println!(
+            "Self-play game {} of {self_play_games}: {} plays, {}.",
+            i + 1,
+            plays.len(),
+            result.map_or("abandoned".to_string(), |r| r.describe())
+        );
+        book.add_game(ruleset, &starting_board, &plays, result)?;
+    }
+
+    let json = serde_json::to_string_pretty(&book).expect("Book should be serializable.");
+    write_output(&output, json)?;
+    println!("Wrote opening book with {} positions to {}.", book.positions.len(), output.display());
+    Ok(())
+}
@@ -0,0 +1,77 @@
+/// Reads the binary file formats used for tablebases and evaluation networks: magic bytes followed
+/// by little-endian fields.
+pub(crate) struct ByteReader<'a> {
+    rest: &'a [u8],
+    /// What kind of file is being read, for error messages (eg, "tablebase").
+    kind: &'static str
+}
+
+impl<'a> ByteReader<'a> {
+
+    /// Start reading `bytes`, which must begin with `magic`.
+    pub(crate) fn new(bytes: &'a [u8], magic: &[u8], kind: &'static str) -> Result<Self, String> {
+        let rest = bytes.strip_prefix(magic).ok_or(format!("Not a {kind} file."))?;
+        Ok(Self { rest, kind })
+    }
+
+    /// Split the next `n` bytes off the data.
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.rest.len() < n {
+            return Err(format!("The {} file is truncated.", self.kind))
+        }
+        let (taken, remaining) = self.rest.split_at(n);
+        self.rest = remaining;
+        Ok(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read `n` `f32`s.
+    pub(crate) fn f32s(&mut self, n: usize) -> Result<Vec<f32>, String> {
+        Ok(self.take(n * 4)?
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Read a string preceded by its length in bytes, as a `u16`.
+    pub(crate) fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| format!("Invalid string in {} file.", self.kind))
+    }
+
+    /// Check that all of the data has been read.
+    pub(crate) fn finish(self) -> Result<(), String> {
+        if self.rest.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unexpected data at the end of {} file.", self.kind))
+        }
+    }
+}
+
+/// The FNV-1a hash of `bytes`, which (unlike the standard library's hasher) is stable between
+/// builds, so can be saved in files.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
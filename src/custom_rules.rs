@@ -0,0 +1,181 @@
+use hnefatafl::pieces::PieceType::Soldier;
+use hnefatafl::pieces::{Piece, PieceSet};
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::preset::{boards, rules};
+use hnefatafl::rules::{KingAttack, KingStrength, RepetitionRule, Ruleset};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Key under which custom variants are saved in eframe's storage.
+pub(crate) const CUSTOM_VARIANTS_KEY: &str = "custom_variants";
+
+/// The preset variants, mapping each variant's name to its rules and starting board.
+pub(crate) fn preset_variants() -> HashMap<String, (Ruleset, String)> {
+    let mut variants: HashMap<String, (Ruleset, String)> = HashMap::default();
+    variants.insert("Copenhagen".to_string(), (rules::COPENHAGEN, boards::COPENHAGEN.to_string()));
+    variants.insert("Brandubh".to_string(), (rules::BRANDUBH, boards::BRANDUBH.to_string()));
+    variants.insert("Tablut".to_string(), (rules::TABLUT, boards::TABLUT.to_string()));
+    variants.insert("Magpie".to_string(), (rules::MAGPIE, boards::MAGPIE.to_string()));
+    variants
+}
+
+/// The preset variants plus the given custom variants. Custom variants whose base variant is
+/// unknown are ignored.
+pub(crate) fn all_variants(custom_variants: &[CustomVariant]) -> HashMap<String, (Ruleset, String)> {
+    let presets = preset_variants();
+    let mut variants = presets.clone();
+    for cv in custom_variants {
+        if let Some((base_rules, board)) = presets.get(&cv.base_variant) {
+            variants.insert(cv.name.clone(), (cv.rules.apply(*base_rules), board.clone()));
+        }
+    }
+    variants
+}
+
+/// The preset variant that the named variant is based on (which is the variant itself if it is a
+/// preset).
+pub(crate) fn base_variant(custom_variants: &[CustomVariant], name: &str) -> String {
+    custom_variants.iter()
+        .find(|v| v.name == name)
+        .map_or(name.to_string(), |v| v.base_variant.clone())
+}
+
+/// How many pieces are needed to capture the king.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) enum KingCapture {
+    /// Captured by two pieces, like a soldier.
+    Two,
+    /// Captured by being surrounded on all four sides.
+    Four,
+    /// Captured by four on or next to the throne, otherwise by two.
+    FourByThrone
+}
+
+/// The user-editable subset of a [`Ruleset`]. Everything else is taken from the preset variant
+/// that the custom variant is based on.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct CustomRules {
+    pub(crate) king_armed: bool,
+    pub(crate) king_capture: KingCapture,
+    /// Whether the king escapes to any edge tile (otherwise he must reach a corner).
+    pub(crate) edge_escape: bool,
+    pub(crate) shieldwall: bool,
+    /// Only meaningful with edge escape.
+    pub(crate) exit_fort: bool,
+    /// Whether the throne is hostile to (ie, can be used to capture) attackers.
+    pub(crate) throne_hostile_to_attackers: bool,
+    /// Whether the throne is hostile to defenders.
+    pub(crate) throne_hostile_to_defenders: bool,
+    /// Number of repetitions that ends the game, or `None` if repetition is not restricted.
+    pub(crate) repetitions: Option<usize>,
+    /// Whether the side that causes the repetition loses (otherwise the game is drawn).
+    pub(crate) repetition_is_loss: bool,
+    pub(crate) attacker_starts: bool
+}
+
+impl CustomRules {
+
+    /// Extract the editable rules from an existing ruleset.
+    pub(crate) fn from_ruleset(ruleset: &Ruleset) -> Self {
+        Self {
+            king_armed: ruleset.king_attack != KingAttack::Unarmed,
+            king_capture: match ruleset.king_strength {
+                KingStrength::Weak => KingCapture::Two,
+                KingStrength::Strong => KingCapture::Four,
+                KingStrength::StrongByThrone => KingCapture::FourByThrone
+            },
+            edge_escape: ruleset.edge_escape,
+            shieldwall: ruleset.shieldwall.is_some(),
+            exit_fort: ruleset.exit_fort,
+            throne_hostile_to_attackers: ruleset.hostility.throne.contains(Piece::new(Soldier, Attacker)),
+            throne_hostile_to_defenders: ruleset.hostility.throne.contains(Piece::new(Soldier, Defender)),
+            repetitions: ruleset.repetition_rule.map(|r| r.n_repetitions),
+            repetition_is_loss: ruleset.repetition_rule.is_some_and(|r| r.is_loss),
+            attacker_starts: ruleset.starting_side == Attacker
+        }
+    }
+
+    /// Check that the rules make sense together.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.exit_fort && !self.edge_escape {
+            return Err("Exit forts are only possible with edge escape.".to_string())
+        }
+        if self.repetitions.is_some_and(|n| n < 2) {
+            return Err("The repetition limit must be at least 2.".to_string())
+        }
+        Ok(())
+    }
+
+    /// Apply these rules on top of `base`, returning the resulting ruleset. Only rules that differ
+    /// from those of `base` are changed, so that `CustomRules::from_ruleset(&r).apply(r) == r`.
+    pub(crate) fn apply(&self, base: Ruleset) -> Ruleset {
+        let orig = Self::from_ruleset(&base);
+        let mut ruleset = base;
+        if self.king_armed != orig.king_armed {
+            ruleset.king_attack = if self.king_armed { KingAttack::Armed } else { KingAttack::Unarmed };
+        }
+        if self.king_capture != orig.king_capture {
+            ruleset.king_strength = match self.king_capture {
+                KingCapture::Two => KingStrength::Weak,
+                KingCapture::Four => KingStrength::Strong,
+                KingCapture::FourByThrone => KingStrength::StrongByThrone
+            };
+        }
+        ruleset.edge_escape = self.edge_escape;
+        if self.shieldwall != orig.shieldwall {
+            // Copenhagen's shieldwall rules are used if the base variant has none.
+            ruleset.shieldwall = if self.shieldwall { rules::COPENHAGEN.shieldwall } else { None };
+        }
+        ruleset.exit_fort = self.exit_fort && self.edge_escape;
+        let throne_hostility = (self.throne_hostile_to_attackers, self.throne_hostile_to_defenders);
+        if throne_hostility != (orig.throne_hostile_to_attackers, orig.throne_hostile_to_defenders) {
+            ruleset.hostility.throne = match throne_hostility {
+                (true, true) => PieceSet::all(),
+                (true, false) => PieceSet::from(Attacker),
+                (false, true) => PieceSet::from(Defender),
+                (false, false) => PieceSet::none()
+            };
+        }
+        if (self.repetitions, self.repetition_is_loss) != (orig.repetitions, orig.repetition_is_loss) {
+            ruleset.repetition_rule = self.repetitions.map(|n_repetitions| RepetitionRule {
+                n_repetitions,
+                is_loss: self.repetition_is_loss
+            });
+        }
+        ruleset.starting_side = if self.attacker_starts { Attacker } else { Defender };
+        ruleset
+    }
+}
+
+/// A named variant created by the user, saved between sessions.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct CustomVariant {
+    pub(crate) name: String,
+    /// The preset variant providing the board and any rules not covered by [`CustomRules`].
+    pub(crate) base_variant: String,
+    pub(crate) rules: CustomRules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_rules_round_trip() {
+        for (name, (ruleset, _)) in preset_variants() {
+            assert_eq!(CustomRules::from_ruleset(&ruleset).apply(ruleset), ruleset, "{name}");
+        }
+    }
+
+    #[test]
+    fn throne_hostility_round_trips() {
+        for (name, (ruleset, _)) in preset_variants() {
+            for (attackers, defenders) in [(true, true), (true, false), (false, true), (false, false)] {
+                let mut rules = CustomRules::from_ruleset(&ruleset);
+                rules.throne_hostile_to_attackers = attackers;
+                rules.throne_hostile_to_defenders = defenders;
+                assert_eq!(CustomRules::from_ruleset(&rules.apply(ruleset)), rules, "{name}");
+            }
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::board::{last_play_figure, piece_figure, TILE_COLORS};
+use crate::book_builder::parse_play_list;
+use crate::custom_rules::preset_variants;
+use eframe::epaint::Color32;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::Game;
+use hnefatafl::pieces::{Piece, PieceType, PlacedPiece, Side};
+use hnefatafl::play::{Play, PlayRecord};
+use hnefatafl::rules::Ruleset;
+use hnefatafl::tiles::Tile;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::Path;
+
+// Everything here is rendered in software, so exports work without a GPU (or a window).
+
+/// Side length of a tile in an SVG diagram.
+const SVG_TILE_PX: u32 = 48;
+/// Width of the margins containing coordinates in an SVG diagram.
+const SVG_MARGIN_PX: u32 = 24;
+/// Side length of a tile in a GIF frame.
+const GIF_TILE_PX: usize = 32;
+/// How long each position is shown in a GIF, in hundredths of a second.
+const GIF_FRAME_DELAY: u16 = 100;
+
+fn hex(color: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+/// The colour of a tile with no highlighting (as drawn by `Board`).
+fn tile_color<T: BoardState>(game: &Game<T>, tile: Tile) -> Color32 {
+    let special_tiles = &game.logic.board_geo.special_tiles;
+    if special_tiles.throne == tile {
+        TILE_COLORS.throne
+    } else if special_tiles.corners.contains(tile) {
+        TILE_COLORS.corner
+    } else {
+        TILE_COLORS.plain
+    }
+}
+
+/// The row and column at which a tile is drawn, taking into account whether the board is flipped.
+fn display_pos(tile: Tile, side_len: u8, flipped: bool) -> (u32, u32) {
+    if flipped {
+        ((side_len - 1 - tile.row) as u32, (side_len - 1 - tile.col) as u32)
+    } else {
+        (tile.row as u32, tile.col as u32)
+    }
+}
+
+/// Render the current position of `game` as an SVG diagram, with coordinates and the same
+/// last-play markers that are drawn on the board during play.
+pub(crate) fn position_svg<T: BoardState>(game: &Game<T>, flipped: bool) -> String {
+    let side_len = game.logic.board_geo.side_len;
+    let board_px = side_len as u32 * (SVG_TILE_PX + 1);
+    let size_px = board_px + SVG_MARGIN_PX;
+    let font_px = SVG_TILE_PX as f32 * 0.8;
+    let last_play = game.play_history.last();
+    let mut svg = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size_px}" height="{size_px}" viewBox="0 0 {size_px} {size_px}">"#
+    );
+    let _ = writeln!(svg, r#"<rect width="{size_px}" height="{size_px}" fill="white"/>"#);
+    for tile in game.logic.board_geo.iter_tiles() {
+        let (row, col) = display_pos(tile, side_len, flipped);
+        let x = SVG_MARGIN_PX + col * (SVG_TILE_PX + 1);
+        let y = row * (SVG_TILE_PX + 1);
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{x}" y="{y}" width="{SVG_TILE_PX}" height="{SVG_TILE_PX}" fill="{}"/>"#,
+            hex(tile_color(game, tile))
+        );
+        let figure = match game.state.board.get_piece(tile) {
+            Some(piece) => Some(piece_figure(piece)),
+            None => last_play.and_then(|r| last_play_figure(r, tile, flipped))
+        };
+        if let Some(fig) = figure {
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" font-size="{font_px}" text-anchor="middle" dominant-baseline="central">{fig}</text>"#,
+                x + SVG_TILE_PX / 2,
+                y + SVG_TILE_PX / 2
+            );
+        }
+    }
+    // Coordinates: columns are lettered along the bottom, rows numbered down the left side
+    for i in 0..side_len {
+        let (row, col) = display_pos(Tile::new(i, i), side_len, flipped);
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-size="14" text-anchor="middle" dominant-baseline="central">{}</text>"#,
+            SVG_MARGIN_PX + col * (SVG_TILE_PX + 1) + SVG_TILE_PX / 2,
+            board_px + SVG_MARGIN_PX / 2,
+            (b'a' + i) as char
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-size="14" text-anchor="middle" dominant-baseline="central">{}</text>"#,
+            SVG_MARGIN_PX / 2,
+            row * (SVG_TILE_PX + 1) + SVG_TILE_PX / 2,
+            i + 1
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The colours used in GIF frames. Each colour's index in this list is its index in the palette.
+const GIF_COLORS: [Color32; 8] = [
+    Color32::from_gray(100),
+    TILE_COLORS.plain,
+    TILE_COLORS.throne,
+    Color32::BLACK,
+    Color32::WHITE,
+    TILE_COLORS.en_prise,
+    TILE_COLORS.cursor,
+    TILE_COLORS.king_escape
+];
+const GRID: u8 = 0;
+const PLAIN: u8 = 1;
+const SPECIAL: u8 = 2;
+const BLACK: u8 = 3;
+const WHITE: u8 = 4;
+const CAPTURE: u8 = 5;
+const LAST_PLAY: u8 = 6;
+const KING: u8 = 7;
+
+/// An image made of palette indices, which GIF frames are drawn on.
+struct Canvas {
+    side_px: usize,
+    pixels: Vec<u8>
+}
+
+impl Canvas {
+
+    fn new(side_px: usize) -> Self {
+        Self { side_px, pixels: vec![GRID; side_px * side_px] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u8) {
+        if x < self.side_px && y < self.side_px {
+            self.pixels[y * self.side_px + x] = color;
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u8) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set(px, py, color);
+            }
+        }
+    }
+
+    /// Fill the ring centred on (`cx`, `cy`) between radii `inner` and `outer` (a disc if `inner`
+    /// is zero).
+    fn fill_ring(&mut self, cx: f32, cy: f32, inner: f32, outer: f32, color: u8) {
+        let (x0, x1) = ((cx - outer).floor() as usize, (cx + outer).ceil() as usize);
+        let (y0, y1) = ((cy - outer).floor() as usize, (cy + outer).ceil() as usize);
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let d = ((px as f32 + 0.5 - cx).powi(2) + (py as f32 + 0.5 - cy).powi(2)).sqrt();
+                if d >= inner && d <= outer {
+                    self.set(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Draw a line of the given thickness from (`x0`, `y0`) to (`x1`, `y1`).
+    fn line(&mut self, (x0, y0): (f32, f32), (x1, y1): (f32, f32), thickness: f32, color: u8) {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil() as usize;
+        for i in 0..=steps {
+            let t = i as f32 / steps.max(1) as f32;
+            self.fill_ring(x0 + (x1 - x0) * t, y0 + (y1 - y0) * t, 0.0, thickness / 2.0, color);
+        }
+    }
+}
+
+/// Draw a frame showing the current position of `game`. As there are no fonts to draw the board's
+/// figures with, pieces are drawn as discs, the tile the last piece moved from is marked with a
+/// dot, and captures with a cross.
+fn draw_frame<T: BoardState>(game: &Game<T>, flipped: bool) -> Canvas {
+    let side_len = game.logic.board_geo.side_len;
+    let tile_px = GIF_TILE_PX;
+    let mut canvas = Canvas::new(side_len as usize * (tile_px + 1) + 1);
+    let last_play: Option<&PlayRecord<T>> = game.play_history.last();
+    for tile in game.logic.board_geo.iter_tiles() {
+        let (row, col) = display_pos(tile, side_len, flipped);
+        let x = 1 + col as usize * (tile_px + 1);
+        let y = 1 + row as usize * (tile_px + 1);
+        let special = tile_color(game, tile) != TILE_COLORS.plain;
+        canvas.fill_rect(x, y, tile_px, tile_px, if special { SPECIAL } else { PLAIN });
+        let (cx, cy) = (x as f32 + tile_px as f32 / 2.0, y as f32 + tile_px as f32 / 2.0);
+        let r = tile_px as f32 * 0.4;
+        match game.state.board.get_piece(tile) {
+            Some(Piece { piece_type, side }) => {
+                let (fill, outline) = match side {
+                    Side::Attacker => (BLACK, BLACK),
+                    Side::Defender => (WHITE, BLACK)
+                };
+                canvas.fill_ring(cx, cy, 0.0, r, outline);
+                canvas.fill_ring(cx, cy, 0.0, r - 2.0, fill);
+                if piece_type == PieceType::King {
+                    canvas.fill_ring(cx, cy, 0.0, r * 0.45, KING);
+                }
+            },
+            None => if let Some(record) = last_play {
+                if record.effects.captures.into_iter().any(|p: PlacedPiece| p.tile == tile) {
+                    let d = r * 0.6;
+                    canvas.line((cx - d, cy - d), (cx + d, cy + d), 3.0, CAPTURE);
+                    canvas.line((cx - d, cy + d), (cx + d, cy - d), 3.0, CAPTURE);
+                } else if record.play.from == tile {
+                    canvas.fill_ring(cx, cy, 0.0, r * 0.3, LAST_PLAY);
+                }
+            }
+        }
+        if last_play.is_some_and(|r| r.play.to() == tile) {
+            // Outline the tile the last piece moved to
+            canvas.fill_rect(x, y, tile_px, 2, LAST_PLAY);
+            canvas.fill_rect(x, y + tile_px - 2, tile_px, 2, LAST_PLAY);
+            canvas.fill_rect(x, y, 2, tile_px, LAST_PLAY);
+            canvas.fill_rect(x + tile_px - 2, y, 2, tile_px, LAST_PLAY);
+        }
+    }
+    canvas
+}
+
+/// Render a game as an animated GIF, with one frame for the starting position and one for the
+/// position after each play.
+pub(crate) fn game_gif<T: BoardState>(
+    ruleset: Ruleset,
+    starting_board: &str,
+    plays: &[Play],
+    flipped: bool
+) -> Result<Vec<u8>, String> {
+    let mut game: Game<T> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    let palette: Vec<u8> = GIF_COLORS.iter().flat_map(|c| [c.r(), c.g(), c.b()]).collect();
+    let side_px = draw_frame(&game, flipped).side_px as u16;
+    let mut gif_bytes = vec![];
+    {
+        let mut encoder = gif::Encoder::new(&mut gif_bytes, side_px, side_px, &palette)
+            .map_err(|e| format!("Could not create GIF: {e}"))?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("Could not create GIF: {e}"))?;
+        for i in 0..=plays.len() {
+            if i > 0 {
+                let play = plays[i - 1];
+                game.do_play(play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+            }
+            let canvas = draw_frame(&game, flipped);
+            let mut frame = gif::Frame::from_indexed_pixels(side_px, side_px, canvas.pixels, None);
+            // Linger on the final position
+            frame.delay = if i == plays.len() { GIF_FRAME_DELAY * 3 } else { GIF_FRAME_DELAY };
+            encoder.write_frame(&frame).map_err(|e| format!("Could not write GIF frame: {e}"))?;
+        }
+    }
+    Ok(gif_bytes)
+}
+
+/// A version of `name` that is safe to use in a file name, with anything other than ASCII letters,
+/// digits, `_` and `-` replaced by `_`.
+pub(crate) fn sanitise_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// Export a game in the format implied by the extension of `path` (an SVG diagram of the final
+/// position for `.svg`, or an animation of the whole game for `.gif`).
+pub(crate) fn export_game<T: BoardState>(
+    ruleset: Ruleset,
+    starting_board: &str,
+    plays: &[Play],
+    flipped: bool,
+    path: &Path
+) -> Result<(), String> {
+    let bytes = match path.extension().and_then(|e| e.to_str()) {
+        Some("svg") => {
+            let mut game: Game<T> = Game::new(ruleset, starting_board)
+                .map_err(|e| format!("Could not create game: {e:?}"))?;
+            for play in plays {
+                game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+            }
+            position_svg(&game, flipped).into_bytes()
+        },
+        Some("gif") => game_gif::<T>(ruleset, starting_board, plays, flipped)?,
+        _ => return Err(format!("Cannot export to {}: use a .svg or .gif file.", path.display()))
+    };
+    std::fs::write(path, bytes).map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+pub(crate) const EXPORT_USAGE: &str = "\
+Usage: hnefatafl-egui export [--flip] <variant> <plays file> <output file>
+
+Export a game as an SVG diagram of the final position (if the output file ends in .svg) or as an
+animated GIF of the whole game (if it ends in .gif). The plays file contains plays like \"d1-d4\",
+separated by whitespace or commas; use - to read them from standard input. The variant must be one
+of the preset variants.";
+
+/// Run the `export` subcommand with the given arguments.
+pub(crate) fn run_export_command(args: &[String]) -> Result<(), String> {
+    let flipped = args.iter().any(|a| a == "--flip");
+    let positional: Vec<&String> = args.iter().filter(|a| *a != "--flip").collect();
+    let [variant, plays_path, output] = positional[..] else {
+        return Err(EXPORT_USAGE.to_string())
+    };
+    let (ruleset, starting_board) = preset_variants().remove(variant.as_str())
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let mut plays_text = String::new();
+    if plays_path == "-" {
+        std::io::stdin().read_to_string(&mut plays_text)
+    } else {
+        std::fs::File::open(plays_path).and_then(|mut f| f.read_to_string(&mut plays_text))
+    }.map_err(|e| format!("Could not read plays from {plays_path}: {e}"))?;
+    let plays = parse_play_list(&plays_text)?;
+    export_game::<LargeBasicBoardState>(ruleset, &starting_board, &plays, flipped, Path::new(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitise_file_name;
+
+    #[test]
+    fn file_names_are_sanitised() {
+        assert_eq!(sanitise_file_name("Custom position (Copenhagen rules)"), "Custom_position__Copenhagen_rules_");
+        assert_eq!(sanitise_file_name("../../etc/passwd"), "______etc_passwd");
+        assert_eq!(sanitise_file_name("Brandubh-2"), "Brandubh-2");
+    }
+}
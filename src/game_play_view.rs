@@ -1,126 +1,820 @@
-use crate::ai::{Ai, BasicAi};
+use crate::ai::{Ai, AiEngine, BasicAi, Difficulty, MctsAi};
+use crate::archive::{ArchivedGame, ArchivedResult};
 use crate::board::Board;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::network::{spawn_guest, spawn_remote_player, RemotePlayer};
+use crate::online::{OnlineClient, OnlinePlayer};
+use crate::opening_book::OpeningBook;
+use crate::saved_game::SavedGame;
+use crate::settings::Settings;
+use crate::neural_eval::EvalNetwork;
+use crate::tablebase::Tablebase;
 use eframe::emath::Align;
-use egui::Layout;
+use egui::{Layout, RichText};
 use hnefatafl::board::state::BoardState;
 use hnefatafl::game::state::GameState;
 use hnefatafl::game::Game;
 use hnefatafl::game::GameOutcome::{Draw, Win};
-use hnefatafl::game::GameStatus::Over;
+use hnefatafl::game::{DrawReason, WinReason};
+use hnefatafl::game::GameStatus::{Ongoing, Over};
 use hnefatafl::pieces;
-use hnefatafl::play::ValidPlay;
+use hnefatafl::play::{Play, ValidPlay};
 use hnefatafl::rules::Ruleset;
 use std::cmp::min;
+use std::sync::Arc;
 use std::time::Duration;
 #[cfg(not(target_arch = "wasm32"))]
 use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use wasm_thread as thread;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// Messages exchanged between the game and the opponent (the AI or a remote player), which runs on
+/// its own thread.
+pub(crate) enum Message<T: BoardState> {
+    /// Ask the opponent to play from the given state, which was reached by making the given play
+    /// (if any).
+    Request(GameState<T>, Option<Play>),
+    /// The opponent's play, the state it was made from, and any lines to log.
+    Response(ValidPlay, GameState<T>, Vec<String>),
+    /// The sender resigns.
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Information for the user, such as the state of the connection to a remote player.
+    Info(String),
+    /// We have joined a network game: the game as described by the host (including any plays
+    /// already made), and the side we play.
+    #[cfg(not(target_arch = "wasm32"))]
+    Joined(SavedGame, pieces::Side)
+}
+
+/// Who plays the side given by [`GameSetup::ai_side`].
+enum Opponent {
+    Ai,
+    #[cfg(not(target_arch = "wasm32"))]
+    Remote(RemotePlayer),
+    /// The host of the network game at this address, which we are joining. The game is not known
+    /// until we have connected.
+    #[cfg(not(target_arch = "wasm32"))]
+    Joining(String),
+    /// A player connected to a lobby server (or, if `ai_side` is `None`, the two players of a game
+    /// we are watching).
+    Online(OnlineClient)
+}
+
+/// Start a game with the given setup and make the given plays.
+fn new_game<T: BoardState>(setup: &GameSetup, plays: &[Play]) -> Result<Game<T>, String> {
+    let mut game: Game<T> = Game::new(setup.ruleset, &setup.starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    for play in plays {
+        game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+    }
+    Ok(game)
+}
+
+/// Describe in plain English why a game was won.
+fn describe_win(reason: &WinReason) -> &'static str {
+    match reason {
+        WinReason::KingEscaped => "the king escaped",
+        WinReason::KingCaptured => "the king was captured",
+        WinReason::ExitFort => "the king escaped to an exit fort",
+        WinReason::AllCaptured => "all of the losing side's pieces were captured",
+        WinReason::NoPlays => "the losing side had no legal moves left",
+        WinReason::Enclosed => "the defenders were completely surrounded",
+        WinReason::Repetition => "the losing side repeated the same position too often"
+    }
+}
+
+/// Describe in plain English why a game was drawn.
+fn describe_draw(reason: &DrawReason) -> &'static str {
+    match reason {
+        DrawReason::Repetition => "the same position was repeated too often",
+        DrawReason::NoPlays => "there were no legal moves left"
+    }
+}
+
+/// Format a duration as, eg, `1h 5m` or `3m 20s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m {}s", secs / 60, secs % 60)
+    }
+}
 
-enum Message<T: BoardState> {
-    Request(GameState<T>),
-    Response(ValidPlay, GameState<T>, Vec<String>)
+/// How a game ended other than by the rules of the variant.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Agreement {
+    Resigned(pieces::Side),
+    DrawAgreed,
+    DrawClaimed(DrawClaim)
 }
 
 pub(crate) enum GamePlayAction {
     UndoPlay,
     QuitGame,
-    QuitApp
+    QuitApp,
+    /// Start a new game with the same setup, with the sides swapped if `swap_sides` is true.
+    Rematch {
+        setup: GameSetup,
+        swap_sides: bool
+    },
+    /// Replay the finished game in the replay viewer.
+    Review
 }
 
+/// A draw claimed by the player to move, which ends the game whatever the opponent thinks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum DrawClaim {
+    /// The same position (with the same side to play) has occurred [`CLAIM_REPETITIONS`] times.
+    Repetition,
+    /// No piece has been captured in the last [`CLAIM_PLAYS_WITHOUT_CAPTURE`] plays.
+    NoCaptures
+}
+
+/// How many times a position must occur before a draw can be claimed.
+const CLAIM_REPETITIONS: usize = 3;
+/// How many plays without a capture must be made before a draw can be claimed.
+const CLAIM_PLAYS_WITHOUT_CAPTURE: usize = 100;
+
+impl DrawClaim {
+    fn describe(&self) -> String {
+        match self {
+            DrawClaim::Repetition => format!("position repeated {CLAIM_REPETITIONS} times"),
+            DrawClaim::NoCaptures => format!("no captures in {CLAIM_PLAYS_WITHOUT_CAPTURE} plays")
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct GameSetup {
     pub(crate) ruleset: Ruleset,
+    /// The preset variant the ruleset is based on, used to reconstruct the ruleset when a saved
+    /// game is loaded.
+    pub(crate) base_variant: String,
     pub(crate) ruleset_name: String,
     pub(crate) starting_board: String,
-    pub(crate) ai_side: pieces::Side,
-    pub(crate) ai_time: Duration
+    /// The side the AI (or remote player, in a network game) plays as, or `None` if humans play
+    /// both sides on this device.
+    pub(crate) ai_side: Option<pieces::Side>,
+    pub(crate) ai_time: Duration,
+    pub(crate) ai_difficulty: Difficulty,
+    pub(crate) ai_engine: AiEngine,
+    /// Book of opening plays for the AI to use, if there is one for the variant.
+    pub(crate) opening_book: Option<Arc<OpeningBook>>,
+    /// Endgame tablebase for the AI to use, if there is one for the variant.
+    pub(crate) tablebase: Option<Arc<Tablebase>>,
+    /// Network for the AI to evaluate positions with, if one is in use for the variant.
+    pub(crate) network: Option<Arc<EvalNetwork>>
 }
 
 pub(crate) struct GamePlayView<T: BoardState> {
+    setup: GameSetup,
     game: Game<T>,
     board_ui: Board<T>,
-    ai_side: pieces::Side,
+    ai_side: Option<pieces::Side>,
     ai_sender: std::sync::mpsc::Sender<Message<T>>,
     ai_receiver: std::sync::mpsc::Receiver<Message<T>>,
-    log_lines: Vec<String>
+    log_lines: Vec<String>,
+    /// Text entered in the move entry box.
+    move_input: String,
+    /// A play entered via the move entry box, to be made on the next update.
+    typed_play: Option<Play>,
+    /// The number of plays in the game, and how it was ended by agreement (if it was), when it was
+    /// last saved.
+    last_saved: (usize, Option<Agreement>),
+    /// Whether the opponent is a remote player rather than the AI.
+    remote: bool,
+    /// Relays plays to and from the server in an online game.
+    online: Option<Box<OnlinePlayer<T>>>,
+    /// Whether we are watching an online game rather than playing in it.
+    spectating: bool,
+    /// Whether the opponent can be sent messages. This is false until a joined network game has
+    /// been described by the host, and once the opponent's thread has gone.
+    connected: bool,
+    /// Set if the game was ended by resignation or agreeing a draw.
+    agreement: Option<Agreement>,
+    /// Whether the opponent has offered a draw which has not yet been answered.
+    draw_offered: bool,
+    /// Whether we have offered the opponent a draw which has not yet been answered.
+    draw_offer_pending: bool,
+    /// Whether to show the result window once the game is over (until it is closed).
+    show_result: bool,
+    /// When the game was started (or resumed).
+    started: Instant,
+    /// How long the game lasted (since it was started or resumed), once it is over.
+    finished_after: Option<Duration>,
+    /// If this game was started from a position in an archived game, the index of that game in the
+    /// archive.
+    branch_of: Option<usize>,
+    /// Identifies the game in the archive once it is finished.
+    id: u64
 }
 
 impl<T: BoardState + Send + 'static> GamePlayView<T> where T::BitField: Send  {
-    pub(crate) fn new(setup: GameSetup) -> Self {
-        let game: Game<T> = Game::new(setup.ruleset, &setup.starting_board).unwrap();
-        let board = Board::new(&game, setup.ai_side.other());
+    pub(crate) fn new(setup: GameSetup, settings: &Settings) -> Self {
+        Self::with_plays(setup, settings, &[], Opponent::Ai).unwrap()
+    }
+
+    /// Start a network game, in which `remote` plays `setup.ai_side`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn networked(
+        setup: GameSetup,
+        settings: &Settings,
+        plays: &[Play],
+        remote: RemotePlayer
+    ) -> Result<Self, String> {
+        Self::with_plays(setup, settings, plays, Opponent::Remote(remote))
+    }
+
+    /// Join the network game hosted at `address`, at the position the host has reached. Until the
+    /// host has described the game, `setup` is shown and nothing can be played.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn join(mut setup: GameSetup, address: String, settings: &Settings) -> Self {
+        // The remote player is to play, so that we keep checking for the host's reply.
+        setup.ai_side = Some(setup.ruleset.starting_side);
+        let mut view = Self::with_plays(setup, settings, &[], Opponent::Joining(address))
+            .expect("Setup should be a valid starting position.");
+        // The game is described once it has been joined
+        view.log_lines.clear();
+        view
+    }
+
+    /// Start playing the network game described by the host, in which we play `side`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_joined(&mut self, saved: &SavedGame, side: pieces::Side) -> Result<(), String> {
+        let mut setup = saved.setup()?;
+        setup.ai_side = Some(side.other());
+        let plays = saved.plays()?;
+        let game = new_game(&setup, &plays)?;
+        let mut board = Board::new(&game, Some(side));
+        board.flipped = self.board_ui.flipped;
+        board.animate_moves = self.board_ui.animate_moves;
+        self.ai_side = setup.ai_side;
+        self.setup = setup;
+        self.game = game;
+        self.board_ui = board;
+        self.last_saved = (plays.len(), None);
+        self.started = Instant::now();
+        self.connected = true;
+        let mut lines = self.start_message();
+        self.log_lines.append(&mut lines);
+        Ok(())
+    }
+
+    /// Play (as `side`) or watch (if `side` is `None`) an online game, at the position reached so
+    /// far.
+    pub(crate) fn online(
+        client: OnlineClient,
+        game: &SavedGame,
+        side: Option<pieces::Side>,
+        settings: &Settings
+    ) -> Result<Self, String> {
+        let mut setup = game.setup()?;
+        setup.ai_side = side.map(|s| s.other());
+        Self::with_plays(setup, settings, &game.plays()?, Opponent::Online(client))
+    }
+
+    /// Rebuild a saved game by replaying its plays. `setup` is the saved game's setup, with any
+    /// opening book or tablebase for the AI.
+    pub(crate) fn resume(setup: GameSetup, saved: &SavedGame, settings: &Settings) -> Result<Self, String> {
+        let mut view = Self::with_plays(setup, settings, &saved.plays()?, Opponent::Ai)?;
+        if saved.id != 0 {
+            view.id = saved.id;
+        }
+        Ok(view)
+    }
+
+    /// Start a game from the position reached by making `plays` in the archived game with index
+    /// `archive_index`.
+    pub(crate) fn branch(
+        archive_index: usize,
+        setup: GameSetup,
+        settings: &Settings,
+        plays: &[Play]
+    ) -> Result<Self, String> {
+        let mut view = Self::with_plays(setup, settings, plays, Opponent::Ai)?;
+        view.branch_of = Some(archive_index);
+        Ok(view)
+    }
+
+    /// Start a game from the given setup against the given opponent, and make the given plays.
+    fn with_plays(
+        setup: GameSetup,
+        settings: &Settings,
+        plays: &[Play],
+        opponent: Opponent
+    ) -> Result<Self, String> {
+        let game: Game<T> = new_game(&setup, plays)?;
+        let mut board = Board::new(&game, setup.ai_side.map(|s| s.other()));
+        board.flipped = settings.flip_board;
+        board.animate_moves = settings.animate_moves;
         let (g2ai_tx, g2ai_rx) = std::sync::mpsc::channel::<Message<T>>();
         let (ai2g_tx, ai2g_rx) = std::sync::mpsc::channel::<Message<T>>();
-        thread::spawn(move || {
-            let mut ai = BasicAi::new(game.logic, setup.ai_side, setup.ai_time);
-            loop {
-                if let Ok(Message::Request(state)) = g2ai_rx.recv() {
-                    if let Ok((play, lines)) = ai.next_play(&state) {
-                        // Don't panic if we can't send the response, it probably just means that
-                        // the user has quit the game
-                        let _ = ai2g_tx.send(Message::Response(play, state, lines));
-                        //ctx.request_repaint()
+        let remote = !matches!(opponent, Opponent::Ai);
+        let spectating = matches!(opponent, Opponent::Online(_)) && setup.ai_side.is_none();
+        #[cfg(not(target_arch = "wasm32"))]
+        let connected = !matches!(opponent, Opponent::Joining(_));
+        #[cfg(target_arch = "wasm32")]
+        let connected = true;
+        board.read_only = spectating;
+        let mut online = None;
+        match (setup.ai_side, opponent) {
+            (Some(ai_side), Opponent::Ai) => {
+                let logic = game.logic;
+                let ai_time = setup.ai_time;
+                let ai_difficulty = setup.ai_difficulty;
+                let ai_engine = setup.ai_engine;
+                let opening_book = setup.opening_book.clone();
+                let tablebase = setup.tablebase.clone();
+                let network = setup.network.clone();
+                thread::spawn(move || {
+                    let mut ai: Box<dyn Ai<BoardState = T>> = match ai_engine {
+                        AiEngine::AlphaBeta => Box::new(
+                            BasicAi::new(logic, ai_side, ai_time, ai_difficulty)
+                                .with_opening_book(opening_book)
+                                .with_tablebase(tablebase)
+                                .with_network(network)
+                        ),
+                        AiEngine::Mcts(config) => Box::new(MctsAi::new(logic, ai_side, ai_time, config))
+                    };
+                    loop {
+                        match g2ai_rx.recv() {
+                            Ok(Message::Request(state, _)) => {
+                                if let Ok((play, lines)) = ai.next_play(&state) {
+                                    // Don't panic if we can't send the response, it probably just
+                                    // means that the user has quit the game
+                                    let _ = ai2g_tx.send(Message::Response(play, state, lines));
+                                    //ctx.request_repaint()
+                                }
+                            },
+                            Ok(Message::OfferDraw) => {
+                                let reply = if ai.accepts_draw() {
+                                    Message::AcceptDraw
+                                } else {
+                                    Message::DeclineDraw
+                                };
+                                let _ = ai2g_tx.send(reply);
+                            },
+                            Ok(_) => {},
+                            Err(_) => break
+                        }
                     }
-                } else {
-                    break
-                }
-            }
-        });
-        if setup.ai_side == setup.ruleset.starting_side {
-            let _ = g2ai_tx.send(Message::Request(game.state));
+                });
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            (Some(_), Opponent::Remote(remote_player)) =>
+                spawn_remote_player(remote_player, setup.clone(), plays.to_vec(), ai2g_tx, g2ai_rx),
+            #[cfg(not(target_arch = "wasm32"))]
+            (_, Opponent::Joining(address)) => {
+                board.read_only = true;
+                spawn_guest(address, ai2g_tx, g2ai_rx);
+            },
+            (ai_side, Opponent::Online(client)) => {
+                // The online player keeps its own copy of the game, to check the plays it receives.
+                let online_game: Game<T> = new_game(&setup, plays)?;
+                let side = ai_side.map(|s| s.other());
+                online = Some(Box::new(OnlinePlayer::new(client, online_game, side, ai2g_tx, g2ai_rx)));
+            },
+            _ => {}
         }
-        let log_lines = vec![
-            format!(
-                "Game is {:?}. AI plays as {:?}, human plays as {:?}. {:?} to play first.",
-                setup.ruleset_name,
-                setup.ai_side,
-                setup.ai_side.other(),
-                setup.ruleset.starting_side
-            )
-        ];
-        Self {
+        if game.state.status == Ongoing && setup.ai_side == Some(game.state.side_to_play) {
+            let _ = g2ai_tx.send(Message::Request(game.state, None));
+        }
+        let mut view = Self {
+            ai_side: setup.ai_side,
+            setup,
+            last_saved: (plays.len(), None),
+            remote,
+            online,
+            spectating,
+            connected,
+            agreement: None,
+            draw_offered: false,
+            draw_offer_pending: false,
+            show_result: true,
+            started: Instant::now(),
+            finished_after: None,
             game,
             board_ui: board,
-            ai_side: setup.ai_side,
             ai_sender: g2ai_tx,
             ai_receiver: ai2g_rx,
-            log_lines
+            log_lines: vec![],
+            move_input: String::new(),
+            typed_play: None,
+            branch_of: None,
+            id: rand::random()
+        };
+        view.log_lines = view.start_message();
+        Ok(view)
+    }
 
+    /// Lines describing the game, to log when it starts.
+    fn start_message(&self) -> Vec<String> {
+        let opponent_name = if self.remote { "Remote player" } else { "AI" };
+        let players = match self.setup.ai_side {
+            None if self.spectating => "Watching an online game".to_string(),
+            Some(ai_side) => format!(
+                "{opponent_name} plays as {:?}, human plays as {:?}", ai_side, ai_side.other()
+            ),
+            None => "Human plays both sides".to_string()
+        };
+        let mut lines = vec![
+            format!(
+                "Game is {:?}. {}. {:?} to play first.",
+                self.setup.ruleset_name,
+                players,
+                self.setup.ruleset.starting_side
+            )
+        ];
+        let n_plays = self.game.play_history.len();
+        if n_plays > 0 {
+            lines.push(format!(
+                "Starting from the position after {} plays. {:?} to play.",
+                n_plays,
+                self.game.state.side_to_play
+            ));
         }
+        lines
     }
 
-    fn handle_play(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, board_side_px: f32) {
-        if let Ok(Message::Response(ai_play, state, mut lines)) = self.ai_receiver.try_recv() {
-            self.log_lines.append(&mut lines);
-            if state == self.game.state {
-                let play_res = self.game.logic.do_valid_play(ai_play, state);
-                self.game.state_history.push(play_res.new_state);
-                self.game.state = play_res.new_state;
-                self.game.play_history.push(play_res.record);
-                self.log_lines.push(format!("{:?} played {}", self.ai_side, ai_play));
+    /// Whether plays have been made or undone (or the game has been ended by agreement) since this
+    /// was last called.
+    pub(crate) fn changed_since_save(&mut self) -> bool {
+        let current = (self.game.play_history.len(), self.agreement);
+        let changed = current != self.last_saved;
+        self.last_saved = current;
+        changed
+    }
+
+    /// Whether the game is over, either by the rules of the variant or by agreement.
+    fn is_over(&self) -> bool {
+        self.game.state.status != Ongoing || self.agreement.is_some()
+    }
+
+    /// End the game by resignation or agreeing a draw.
+    fn end_by_agreement(&mut self, agreement: Agreement) {
+        self.agreement = Some(agreement);
+        self.draw_offered = false;
+        self.draw_offer_pending = false;
+        self.board_ui.read_only = true;
+        self.board_ui.take_premove();
+    }
+
+    /// The description of the game in a form that can be saved.
+    fn to_saved_game(&self) -> SavedGame {
+        let mut saved = SavedGame::new(&self.setup, self.plays().into_iter());
+        saved.remote = self.remote;
+        saved.id = self.id;
+        saved
+    }
+
+    /// The index in the archive of the game this game was started from, if any.
+    pub(crate) fn branch_of(&self) -> Option<usize> {
+        self.branch_of
+    }
+
+    /// The ID of the game, which is also the ID of the [`SavedGame`] it is archived as.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// All plays made so far.
+    pub(crate) fn plays(&self) -> Vec<Play> {
+        self.game.play_history.iter().map(|r| r.play).collect()
+    }
+
+    /// The game in a form that can be saved and resumed, or `None` if the game is over (or is a
+    /// network game, which cannot be resumed).
+    pub(crate) fn saved_game(&self) -> Option<SavedGame> {
+        if self.is_over() || self.remote {
+            return None
+        }
+        Some(self.to_saved_game())
+    }
+
+    /// The game in a form that can be added to the archive, or `None` if the game is not over.
+    pub(crate) fn archived_game(&self) -> Option<ArchivedGame> {
+        if self.spectating {
+            return None
+        }
+        let game = self.to_saved_game();
+        match (self.agreement, self.game.state.status) {
+            (Some(Agreement::Resigned(side)), _) => Some(ArchivedGame::with_result(
+                game,
+                ArchivedResult::Win(side.other().into()),
+                "Resignation".to_string()
+            )),
+            (Some(Agreement::DrawAgreed), _) =>
+                Some(ArchivedGame::with_result(game, ArchivedResult::Draw, "Agreement".to_string())),
+            (Some(Agreement::DrawClaimed(claim)), _) => Some(ArchivedGame::with_result(
+                game,
+                ArchivedResult::Draw,
+                format!("Claimed ({})", claim.describe())
+            )),
+            (None, Over(outcome)) => Some(ArchivedGame::new(game, outcome)),
+            (None, Ongoing) => None
+        }
+    }
+
+    /// Send a message to the opponent (if there is one). If the opponent's thread has exited (eg,
+    /// because joining a network game failed), the opponent is treated as disconnected.
+    fn send_to_opponent(&mut self, msg: Message<T>) {
+        if self.ai_side.is_some() && self.ai_sender.send(msg).is_err() {
+            log::warn!("Could not send message to the opponent.");
+            self.connected = false;
+        }
+    }
+
+    /// Ask the AI (if there is one) to play from the current state, which was reached by making
+    /// `play` (if given).
+    fn request_ai_play(&mut self, play: Option<Play>) {
+        self.send_to_opponent(Message::Request(self.game.state, play));
+    }
+
+    /// Make a play on behalf of the human and ask the AI to respond. Returns whether the play was
+    /// legal.
+    fn do_human_play(&mut self, play: Play) -> bool {
+        if self.agreement.is_some() {
+            return false
+        }
+        let side = self.game.state.side_to_play;
+        match self.game.do_play(play) {
+            Ok(_) => {
+                self.log_lines.push(format!("{:?} played {}", side, play));
+                self.request_ai_play(Some(play));
+                true
+            },
+            Err(e) => {
+                self.log_lines.push(format!("Cannot play {play}: {e:?}."));
+                false
             }
         }
-        if let Some(human_play) = self.board_ui.update(&self.game, ctx, ui, board_side_px) {
-            self.game.do_play(human_play).unwrap();
-            self.log_lines.push(format!("{:?} played {}", self.ai_side.other(), human_play));
-            self.ai_sender.send(Message::Request(self.game.state))
-                .expect("Failed to send request");
-        }
-        if let Over(outcome) = self.game.state.status {
-            let over_msg = match outcome {
-                Win(reason, side) =>
-                    format!("{side:?} has won ({reason:?})."),
-                Draw(reason) =>
-                    format!("Draw ({reason:?}).")
-            };
+    }
+
+    /// Handle a message from the opponent.
+    fn handle_message(&mut self, msg: Message<T>) {
+        match msg {
+            Message::Response(ai_play, state, mut lines) => {
+                self.log_lines.append(&mut lines);
+                if state == self.game.state && self.agreement.is_none() {
+                    let play_res = self.game.logic.do_valid_play(ai_play, state);
+                    self.game.state_history.push(play_res.new_state);
+                    self.game.state = play_res.new_state;
+                    self.game.play_history.push(play_res.record);
+                    self.log_lines.push(format!("{:?} played {}", state.side_to_play, ai_play));
+                    if let Some(premove) = self.board_ui.take_premove() {
+                        if self.game.state.status == Ongoing && !self.do_human_play(premove) {
+                            self.log_lines.push(format!("Premove {premove} discarded."));
+                        }
+                    }
+                }
+            },
+            Message::Resign => {
+                if let (Some(side), false) = (self.ai_side, self.is_over()) {
+                    self.end_by_agreement(Agreement::Resigned(side));
+                }
+            },
+            Message::OfferDraw => {
+                if !self.is_over() {
+                    self.draw_offered = true;
+                    self.log_lines.push("Opponent offers a draw.".to_string());
+                }
+            },
+            Message::AcceptDraw => {
+                if self.draw_offer_pending && !self.is_over() {
+                    self.end_by_agreement(Agreement::DrawAgreed);
+                }
+            },
+            Message::DeclineDraw => {
+                self.draw_offer_pending = false;
+                self.log_lines.push("Opponent declined the draw.".to_string());
+            },
+            Message::Info(info) => self.log_lines.push(info),
+            #[cfg(not(target_arch = "wasm32"))]
+            Message::Joined(game, side) => {
+                if let Err(e) = self.start_joined(&game, side) {
+                    self.log_lines.push(format!("Could not start the game: {e}"));
+                }
+            },
+            Message::Request(..) => {}
+        }
+    }
+
+    fn handle_play(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, board_side_px: f32) {
+        while let Ok(msg) = self.ai_receiver.try_recv() {
+            self.handle_message(msg);
+        }
+        let board_play = self.board_ui.update(&self.game, ctx, ui, board_side_px);
+        if let Some(human_play) = board_play.or(self.typed_play.take()) {
+            self.do_human_play(human_play);
+        }
+        if let Some(over_msg) = self.result() {
+            if self.finished_after.is_none() {
+                self.finished_after = Some(self.started.elapsed());
+                // Nothing more can be played, so don't let the board look as if it can.
+                self.board_ui.read_only = true;
+                self.board_ui.take_premove();
+            }
             if self.log_lines.last().is_some_and(|m| m != over_msg.as_str()) {
                 self.log_lines.push(over_msg);
             }
         }
     }
+
+    /// The winner (or `None` if the game was drawn) and why the game ended, or `None` if the game
+    /// is not over.
+    fn outcome(&self) -> Option<(Option<pieces::Side>, String)> {
+        match (self.agreement, self.game.state.status) {
+            (Some(Agreement::Resigned(side)), _) =>
+                Some((Some(side.other()), format!("{side:?} resigned"))),
+            (Some(Agreement::DrawAgreed), _) => Some((None, "the players agreed a draw".to_string())),
+            (Some(Agreement::DrawClaimed(claim)), _) =>
+                Some((None, format!("a draw was claimed ({})", claim.describe()))),
+            (None, Over(Win(reason, side))) => Some((Some(side), describe_win(&reason).to_string())),
+            (None, Over(Draw(reason))) => Some((None, describe_draw(&reason).to_string())),
+            (None, Ongoing) => None
+        }
+    }
+
+    /// A description of the result, or `None` if the game is not over.
+    fn result(&self) -> Option<String> {
+        let (winner, reason) = self.outcome()?;
+        Some(match winner {
+            Some(side) => format!("{side:?} has won: {reason}."),
+            None => format!("Draw: {reason}.")
+        })
+    }
+
+    /// The draw the player to move could claim, if any. Draws can't be claimed in network games,
+    /// where they must be agreed.
+    fn available_claim(&self) -> Option<DrawClaim> {
+        if self.remote || self.is_over() || Some(self.game.state.side_to_play) == self.ai_side {
+            return None
+        }
+        let state = &self.game.state;
+        let occurrences = self.game.state_history.iter()
+            .filter(|s| s.board == state.board && s.side_to_play == state.side_to_play)
+            .count();
+        if occurrences >= CLAIM_REPETITIONS {
+            Some(DrawClaim::Repetition)
+        } else if state.plays_since_capture >= CLAIM_PLAYS_WITHOUT_CAPTURE {
+            Some(DrawClaim::NoCaptures)
+        } else {
+            None
+        }
+    }
     
+    /// Export the game to a file in the current directory: the current position as an SVG diagram
+    /// if `ext` is `svg`, or the whole game as an animated GIF if it is `gif`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export(&mut self, ext: &str) {
+        let plays = self.plays();
+        let path = std::path::PathBuf::from(format!(
+            "{}-{}-plays.{ext}",
+            crate::export::sanitise_file_name(&self.setup.ruleset_name),
+            plays.len()
+        ));
+        let result = crate::export::export_game::<T>(
+            self.setup.ruleset,
+            &self.setup.starting_board,
+            &plays,
+            self.board_ui.flipped,
+            &path
+        );
+        self.log_lines.push(match result {
+            Ok(()) => format!("Exported game to {}.", path.display()),
+            Err(e) => e
+        });
+    }
+
+    /// Parse the contents of the move entry box (eg, `d1-d4`) and queue the play if it is the
+    /// human's turn.
+    fn submit_typed_play(&mut self) {
+        let input = self.move_input.trim().to_string();
+        self.move_input.clear();
+        if input.is_empty() {
+            return
+        }
+        if self.is_over() || self.spectating || Some(self.game.state.side_to_play) == self.ai_side {
+            self.log_lines.push("It is not your turn.".to_string());
+            return
+        }
+        match input.parse::<Play>() {
+            Ok(play) => self.typed_play = Some(play),
+            Err(_) => self.log_lines.push(format!(
+                "Could not understand move {input:?}. Enter moves like \"d1-d4\"."
+            ))
+        }
+    }
+
+    /// Buttons for resigning, for offering, answering or claiming draws.
+    fn agreement_ui(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Resign").clicked() {
+            let side = self.ai_side.map_or(self.game.state.side_to_play, |s| s.other());
+            self.send_to_opponent(Message::Resign);
+            self.end_by_agreement(Agreement::Resigned(side));
+        } else if let Some(claim) = self.available_claim() {
+            if ui.button("Claim draw").on_hover_text(claim.describe()).clicked() {
+                self.end_by_agreement(Agreement::DrawClaimed(claim));
+            }
+        }
+        if self.is_over() {
+            return
+        }
+        if self.ai_side.is_none() {
+            // Both players are at this device, so can agree a draw between themselves.
+            if ui.button("Agree draw").clicked() {
+                self.end_by_agreement(Agreement::DrawAgreed);
+            }
+        } else if self.draw_offered {
+            if ui.button("Accept draw").clicked() {
+                self.send_to_opponent(Message::AcceptDraw);
+                self.end_by_agreement(Agreement::DrawAgreed);
+            }
+            if ui.button("Decline draw").clicked() {
+                self.send_to_opponent(Message::DeclineDraw);
+                self.draw_offered = false;
+            }
+        } else if ui.add_enabled(!self.draw_offer_pending, egui::Button::new("Offer draw")).clicked() {
+            self.send_to_opponent(Message::OfferDraw);
+            self.draw_offer_pending = true;
+            self.log_lines.push("Draw offered.".to_string());
+        }
+    }
+
+    /// A modal dialog summarising the finished game, with options for what to do next. It can be
+    /// closed to look at the final position.
+    fn result_ui(&mut self, ctx: &egui::Context) -> Option<GamePlayAction> {
+        let (winner, mut reason) = self.outcome()?;
+        if !self.show_result {
+            return None
+        }
+        let headline = match (winner, self.ai_side) {
+            (None, _) => "Draw".to_string(),
+            // Tell a lone human player how they did, unless they are only watching.
+            (Some(side), Some(ai_side)) if !self.spectating && side == ai_side =>
+                format!("{side:?} wins. You lost."),
+            (Some(side), Some(_)) if !self.spectating => format!("{side:?} wins. You won!"),
+            (Some(side), _) => format!("{side:?} wins")
+        };
+        if let Some(first) = reason.get(..1) {
+            reason.replace_range(..1, &first.to_uppercase());
+        }
+        let mut summary = format!("{} plays", self.game.play_history.len());
+        if let Some(duration) = self.finished_after {
+            summary.push_str(&format!(" in {}", format_duration(duration)));
+        }
+        let mut action = None;
+        let response = egui::Modal::new(egui::Id::new("game_over")).show(ctx, |ui| {
+            ui.label(RichText::new(headline).heading());
+            ui.label(format!("{reason}."));
+            ui.label(summary);
+            ui.separator();
+            ui.horizontal(|ui| {
+                // Network games are set up again through the lobby or by hosting.
+                if !self.remote {
+                    if ui.button("Rematch").clicked() {
+                        action = Some(GamePlayAction::Rematch { setup: self.setup.clone(), swap_sides: false });
+                    }
+                    let can_swap = self.ai_side.is_some();
+                    if ui.add_enabled(can_swap, egui::Button::new("Rematch with sides swapped")).clicked() {
+                        action = Some(GamePlayAction::Rematch { setup: self.setup.clone(), swap_sides: true });
+                    }
+                }
+                if ui.add_enabled(!self.spectating, egui::Button::new("Review game")).clicked() {
+                    action = Some(GamePlayAction::Review);
+                }
+                let back = if self.branch_of.is_some() { "Back to replay" } else { "Back to setup" };
+                if ui.button(back).clicked() {
+                    action = Some(GamePlayAction::QuitGame);
+                }
+                if ui.button("Close").clicked() {
+                    self.show_result = false;
+                }
+            });
+        });
+        if response.should_close() {
+            self.show_result = false;
+        }
+        action
+    }
+
     pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<GamePlayAction> {
         let mut action: Option<GamePlayAction> = None;
+        if self.remote {
+            // Keep checking for messages from the remote player, even when it is our turn.
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        if let Some(online) = &mut self.online {
+            online.pump();
+        }
         let total_space = ctx.screen_rect();
         // Bottom panel (with logs and buttons) gets 25% of screen height
         let bottom_panel_height = total_space.max.y * 0.25;
@@ -141,10 +835,41 @@ impl<T: BoardState + Send + 'static> GamePlayView<T> where T::BitField: Send  {
                     if ui.button("Quit app").clicked() {
                         action = Some(GamePlayAction::QuitApp)
                     }
-                    let undo_button = ui.button("Undo move");
+                    // Plays cannot be taken back in network games, or once a game has been resigned
+                    // or drawn
+                    let can_undo = !self.remote && self.agreement.is_none();
+                    let undo_button = ui.add_enabled(can_undo, egui::Button::new("Undo move"));
                     if undo_button.clicked() {
                         action = Some(GamePlayAction::UndoPlay);
                     }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    for ext in ["svg", "gif"] {
+                        if ui.button(format!("Export {}", ext.to_uppercase())).clicked() {
+                            self.export(ext);
+                        }
+                    }
+                    ui.separator();
+                    ui.label("Move:");
+                    let move_entry = ui.add(
+                        egui::TextEdit::singleline(&mut self.move_input)
+                            .hint_text("e.g. d1-d4")
+                            .desired_width(80.0)
+                    );
+                    let entered = move_entry.lost_focus()
+                        && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if ui.button("Play").clicked() || entered {
+                        self.submit_typed_play();
+                    }
+                });
+                if !self.spectating && self.connected && !self.is_over() {
+                    ui.horizontal(|ui| self.agreement_ui(ui));
+                }
+                ui.horizontal(|ui| {
+                    let overlays = &mut self.board_ui.overlays;
+                    ui.label("Show:");
+                    ui.checkbox(&mut overlays.en_prise, "Pieces under threat");
+                    ui.checkbox(&mut overlays.king_escapes, "King escape routes");
+                    ui.checkbox(&mut overlays.capture_dests, "Capturing moves");
                 });
                 ui.vertical(|ui| {
                     egui::ScrollArea::vertical().auto_shrink([false, true])
@@ -160,10 +885,17 @@ impl<T: BoardState + Send + 'static> GamePlayView<T> where T::BitField: Send  {
         egui::CentralPanel::default().show(&ctx, |ui| {
             self.handle_play(ctx, ui, central_panel_side);
         });
+        if let Some(a) = self.result_ui(ctx) {
+            action = Some(a);
+        }
         if let Some(GamePlayAction::UndoPlay) = action {
+            self.board_ui.take_premove();
             self.game.undo_last_play();
-            self.ai_sender.send(Message::Request(self.game.state))
-                .expect("Failed to send request");
+            // The game may no longer be over.
+            self.board_ui.read_only = self.spectating;
+            self.finished_after = None;
+            self.show_result = true;
+            self.request_ai_play(None);
 
         }
         action
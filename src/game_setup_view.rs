@@ -1,13 +1,43 @@
+use crate::ai::{AiEngine, Difficulty, MctsConfig, PlayoutPolicy, DEFAULT_EVAL_CUTOFF};
+use crate::custom_rules::{base_variant, preset_variants, CustomRules, CustomVariant, KingCapture};
 use crate::game_play_view::GameSetup;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::network::DEFAULT_PORT;
+use crate::saved_game::SavedGame;
+use crate::settings::Settings;
 use hnefatafl::pieces;
 use hnefatafl::rules::Ruleset;
 use std::collections::HashMap;
 use std::time::Duration;
 use egui::RichText;
-use hnefatafl::preset::{boards, rules};
 
 pub(crate) enum GameSetupAction {
     StartGame(GameSetup),
+    /// Open the lobby of online games, offering to create a game with the given setup.
+    PlayOnline(GameSetup),
+    /// Resume the saved unfinished game.
+    ResumeGame,
+    EditPosition(Vec<CustomVariant>),
+    /// The list of custom variants has changed and should be saved.
+    SaveCustomVariants(Vec<CustomVariant>),
+    /// Host a network game with the given setup (in which the remote player plays
+    /// `setup.ai_side`), listening for the opponent on the given port.
+    #[cfg(not(target_arch = "wasm32"))]
+    HostGame {
+        setup: GameSetup,
+        port: u16
+    },
+    /// Join the network game hosted at the given address (`host:port`). The setup is shown until
+    /// the host has described the game.
+    #[cfg(not(target_arch = "wasm32"))]
+    JoinGame {
+        setup: GameSetup,
+        address: String
+    },
+    ViewArchive,
+    /// Solve puzzles generated from AI games.
+    ViewPuzzles,
+    ViewSettings,
     ViewAbout,
     Quit
 }
@@ -16,8 +46,34 @@ pub(crate) struct GameSetupView {
     variants: HashMap<String, (Ruleset, String)>,
     ai_sides: HashMap<String, pieces::Side>,
     ai_time: u8,
+    ai_difficulty: Difficulty,
+    ai_engine: AiEngine,
     selected_variant: String,
     selected_ai_side: String,
+    /// Variants created by the user (which are also included in `variants`).
+    custom_variants: Vec<CustomVariant>,
+    /// Rules being edited in the custom rules panel.
+    custom_rules: CustomRules,
+    /// The variant that `custom_rules` was initialised from.
+    custom_rules_source: String,
+    /// Name to save the custom rules under.
+    custom_name: String,
+    /// Result of the last attempt to save or delete a custom variant.
+    custom_message: Option<String>,
+    /// Description of the unfinished game that can be resumed, if any.
+    resumable_game: Option<String>,
+    /// The side played by the host of a network game.
+    #[cfg(not(target_arch = "wasm32"))]
+    host_side: pieces::Side,
+    /// Port to listen on when hosting a network game.
+    #[cfg(not(target_arch = "wasm32"))]
+    host_port: u16,
+    /// Address of the host of the network game to join.
+    #[cfg(not(target_arch = "wasm32"))]
+    join_address: String,
+    /// Result of the last attempt to host or join a network game.
+    #[cfg(not(target_arch = "wasm32"))]
+    network_message: Option<String>
 }
 
 impl GameSetupView {
@@ -33,7 +89,248 @@ impl GameSetupView {
         side_keys.sort();
         let selected_ai_side = side_keys.first().expect("No sides provided.").clone();
 
-        Self { variants, ai_sides, ai_time: 5, selected_variant, selected_ai_side }
+        let custom_rules = CustomRules::from_ruleset(&variants[&selected_variant].0);
+        Self {
+            variants,
+            ai_sides,
+            ai_time: 5,
+            ai_difficulty: Difficulty::default(),
+            ai_engine: AiEngine::default(),
+            custom_rules_source: selected_variant.clone(),
+            selected_variant,
+            selected_ai_side,
+            custom_variants: vec![],
+            custom_rules,
+            custom_name: String::new(),
+            custom_message: None,
+            resumable_game: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            host_side: pieces::Side::Attacker,
+            #[cfg(not(target_arch = "wasm32"))]
+            host_port: DEFAULT_PORT,
+            #[cfg(not(target_arch = "wasm32"))]
+            join_address: format!("localhost:{DEFAULT_PORT}"),
+            #[cfg(not(target_arch = "wasm32"))]
+            network_message: None
+        }
+    }
+
+    /// Create a view offering the preset variants as well as the given custom variants, with the
+    /// selections remembered in `settings`, and offering to resume `saved_game` if given.
+    pub(crate) fn from_saved(
+        custom_variants: &[CustomVariant],
+        settings: &Settings,
+        saved_game: Option<&SavedGame>
+    ) -> Self {
+        let mut view = Self::default();
+        view.resumable_game = saved_game.map(|g| format!("{}, {} plays", g.ruleset_name, g.plays.len()));
+        for cv in custom_variants {
+            view.add_custom_variant(cv.clone());
+        }
+        if let Some(variant) = settings.variant.as_ref().filter(|v| view.variants.contains_key(*v)) {
+            view.selected_variant = variant.clone();
+        }
+        if let Some(side) = settings.ai_side.as_ref().filter(|s| view.ai_sides.contains_key(*s)) {
+            view.selected_ai_side = side.clone();
+        }
+        view.ai_time = settings.ai_time;
+        view.ai_difficulty = settings.ai_difficulty;
+        view.ai_engine = settings.ai_engine;
+        view
+    }
+
+    /// Record the current selections in `settings` so they can be restored later.
+    pub(crate) fn remember(&self, settings: &mut Settings) {
+        settings.variant = Some(self.selected_variant.clone());
+        settings.ai_side = Some(self.selected_ai_side.clone());
+        settings.ai_time = self.ai_time;
+        settings.ai_difficulty = self.ai_difficulty;
+        settings.ai_engine = self.ai_engine;
+    }
+
+    /// Add a custom variant to the list of available variants, replacing any custom variant with
+    /// the same name. Variants whose base variant is unknown are ignored.
+    fn add_custom_variant(&mut self, cv: CustomVariant) {
+        let Some((base_rules, board)) = self.variants.get(&cv.base_variant).cloned() else {
+            log::warn!("Ignoring custom variant {:?} with unknown base {:?}.", cv.name, cv.base_variant);
+            return
+        };
+        self.variants.insert(cv.name.clone(), (cv.rules.apply(base_rules), board));
+        self.custom_variants.retain(|v| v.name != cv.name);
+        self.custom_variants.push(cv);
+    }
+
+    /// Show the result of an attempt to host or join a network game.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_network_message(&mut self, msg: String) {
+        self.network_message = Some(msg);
+    }
+
+    /// The setup for a game of the selected variant, in which the AI plays `ai_side`.
+    fn selected_setup(&self, ai_side: Option<pieces::Side>) -> GameSetup {
+        let ruleset_name = self.selected_variant.clone();
+        let (ruleset, starting_board) = self.variants[&ruleset_name].clone();
+        GameSetup {
+            ruleset,
+            base_variant: self.selected_base_variant(),
+            ruleset_name,
+            starting_board,
+            ai_side,
+            ai_time: Duration::from_secs(self.ai_time as u64),
+            ai_difficulty: self.ai_difficulty,
+            ai_engine: self.ai_engine,
+            opening_book: None,
+            tablebase: None,
+            network: None
+        }
+    }
+
+    /// The preset variant that the selected variant is based on.
+    fn selected_base_variant(&self) -> String {
+        base_variant(&self.custom_variants, &self.selected_variant)
+    }
+
+    fn custom_rules_ui(&mut self, ui: &mut egui::Ui) -> Option<GameSetupAction> {
+        let mut action = None;
+        if self.custom_rules_source != self.selected_variant {
+            // Selected variant has changed, so start editing from its rules
+            self.custom_rules = CustomRules::from_ruleset(&self.variants[&self.selected_variant].0);
+            self.custom_rules_source = self.selected_variant.clone();
+            self.custom_message = None;
+        }
+        ui.label(format!("Based on {}.", self.selected_base_variant()));
+        let rules = &mut self.custom_rules;
+        egui::Grid::new("custom_rules_grid").show(ui, |ui| {
+            ui.label("King:");
+            ui.checkbox(&mut rules.king_armed, "Armed (can capture)");
+            ui.end_row();
+            ui.label("King captured by:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut rules.king_capture, KingCapture::Two, "Two");
+                ui.radio_value(&mut rules.king_capture, KingCapture::Four, "Four");
+                ui.radio_value(&mut rules.king_capture, KingCapture::FourByThrone, "Four at throne, else two");
+            });
+            ui.end_row();
+            ui.label("King escapes to:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut rules.edge_escape, false, "Corner");
+                ui.radio_value(&mut rules.edge_escape, true, "Edge");
+            });
+            ui.end_row();
+            ui.label("Special captures:");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut rules.shieldwall, "Shieldwall");
+                ui.add_enabled(rules.edge_escape, egui::Checkbox::new(&mut rules.exit_fort, "Exit forts"));
+            });
+            ui.end_row();
+            ui.label("Throne hostile to:");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut rules.throne_hostile_to_attackers, "Attackers");
+                ui.checkbox(&mut rules.throne_hostile_to_defenders, "Defenders");
+            });
+            ui.end_row();
+            ui.label("Repetition:");
+            ui.horizontal(|ui| {
+                let mut limited = rules.repetitions.is_some();
+                ui.checkbox(&mut limited, "Limit to");
+                let mut n = rules.repetitions.unwrap_or(3);
+                ui.add_enabled(limited, egui::DragValue::new(&mut n).range(2..=10));
+                rules.repetitions = limited.then_some(n);
+                ui.add_enabled(limited, egui::Checkbox::new(&mut rules.repetition_is_loss, "Repeating side loses"));
+            });
+            ui.end_row();
+            ui.label("First to play:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut rules.attacker_starts, true, "Attacker");
+                ui.radio_value(&mut rules.attacker_starts, false, "Defender");
+            });
+            ui.end_row();
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut self.custom_name);
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save variant").clicked() {
+                let name = self.custom_name.trim().to_string();
+                let is_preset = self.variants.contains_key(&name)
+                    && !self.custom_variants.iter().any(|v| v.name == name);
+                let result = if name.is_empty() {
+                    Err("Please enter a name for the variant.".to_string())
+                } else if is_preset {
+                    Err(format!("{name:?} is the name of a preset variant."))
+                } else {
+                    self.custom_rules.validate()
+                };
+                match result {
+                    Ok(()) => {
+                        self.add_custom_variant(CustomVariant {
+                            name: name.clone(),
+                            base_variant: self.selected_base_variant(),
+                            rules: self.custom_rules
+                        });
+                        self.selected_variant = name.clone();
+                        self.custom_rules_source = name.clone();
+                        self.custom_message = Some(format!("Saved variant {name:?}."));
+                        action = Some(GameSetupAction::SaveCustomVariants(self.custom_variants.clone()));
+                    },
+                    Err(e) => self.custom_message = Some(e)
+                }
+            }
+            let selected_is_custom = self.custom_variants.iter().any(|v| v.name == self.selected_variant);
+            if ui.add_enabled(selected_is_custom, egui::Button::new("Delete variant")).clicked() {
+                let name = self.selected_variant.clone();
+                self.custom_variants.retain(|v| v.name != name);
+                self.variants.remove(&name);
+                let mut variant_keys: Vec<&String> = self.variants.keys().collect();
+                variant_keys.sort();
+                self.selected_variant = variant_keys[0].clone();
+                self.custom_message = Some(format!("Deleted variant {name:?}."));
+                action = Some(GameSetupAction::SaveCustomVariants(self.custom_variants.clone()));
+            }
+        });
+        if let Some(msg) = &self.custom_message {
+            ui.label(msg);
+        }
+        action
+    }
+
+    /// Controls for hosting or joining a game against a player on another device. The host's
+    /// selected variant is played.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn network_ui(&mut self, ui: &mut egui::Ui) -> Option<GameSetupAction> {
+        let mut action = None;
+        egui::Grid::new("network_grid").show(ui, |ui| {
+            ui.label("Play as:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.host_side, pieces::Side::Attacker, "Attacker");
+                ui.radio_value(&mut self.host_side, pieces::Side::Defender, "Defender");
+            });
+            ui.end_row();
+            ui.label("Port:");
+            ui.add(egui::DragValue::new(&mut self.host_port).range(1024..=u16::MAX));
+            ui.end_row();
+            if ui.button("Host game").clicked() {
+                action = Some(GameSetupAction::HostGame {
+                    setup: self.selected_setup(Some(self.host_side.other())),
+                    port: self.host_port
+                });
+            }
+            ui.end_row();
+            ui.label("Host address:");
+            ui.text_edit_singleline(&mut self.join_address);
+            ui.end_row();
+            if ui.button("Join game").clicked() {
+                action = Some(GameSetupAction::JoinGame {
+                    setup: self.selected_setup(None),
+                    address: self.join_address.trim().to_string()
+                });
+            }
+            ui.end_row();
+        });
+        if let Some(msg) = &self.network_message {
+            ui.label(msg);
+        }
+        action
     }
 
     pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<GameSetupAction> {
@@ -42,6 +339,15 @@ impl GameSetupView {
             ui.label(RichText::new("Set up new game").heading());
         });
         egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(desc) = &self.resumable_game {
+                ui.horizontal(|ui| {
+                    if ui.button("Resume game").clicked() {
+                        action = Some(GameSetupAction::ResumeGame);
+                    }
+                    ui.label(format!("Unfinished game: {desc}"));
+                });
+                ui.separator();
+            }
             egui::Grid::new("game_setup_grid").show(ui, |ui| {
                 ui.label("Variant:");
                 egui::ComboBox::from_id_salt("variant")
@@ -64,16 +370,33 @@ impl GameSetupView {
                 ui.label("AI time per move:");
                 ui.add(egui::Slider::new(&mut self.ai_time, 1..=60));
                 ui.end_row();
+                ui.label("AI engine:");
+                engine_ui(ui, "ai_engine", &mut self.ai_engine);
+                ui.end_row();
+                ui.label("AI difficulty:");
+                ui.add_enabled_ui(self.ai_engine == AiEngine::AlphaBeta, |ui| {
+                    difficulty_combo_box(ui, "ai_difficulty", &mut self.ai_difficulty);
+                });
+                ui.end_row();
                 if ui.button("Start game").clicked() {
-                    let ruleset_name = self.selected_variant.clone();
-                    let (ruleset, starting_board) = self.variants[&ruleset_name].clone();
-                    action = Some(GameSetupAction::StartGame(GameSetup {
-                            ruleset,
-                            ruleset_name,
-                            starting_board,
-                            ai_side: self.ai_sides[&self.selected_ai_side],
-                            ai_time: Duration::from_secs(self.ai_time as u64),
-                    }));
+                    let ai_side = Some(self.ai_sides[&self.selected_ai_side]);
+                    action = Some(GameSetupAction::StartGame(self.selected_setup(ai_side)));
+                }
+                if ui.button("Play online").clicked() {
+                    let ai_side = Some(self.ai_sides[&self.selected_ai_side]);
+                    action = Some(GameSetupAction::PlayOnline(self.selected_setup(ai_side)));
+                }
+                if ui.button("Position editor").clicked() {
+                    action = Some(GameSetupAction::EditPosition(self.custom_variants.clone()));
+                }
+                if ui.button("Game archive").clicked() {
+                    action = Some(GameSetupAction::ViewArchive)
+                }
+                if ui.button("Puzzles").clicked() {
+                    action = Some(GameSetupAction::ViewPuzzles)
+                }
+                if ui.button("Settings").clicked() {
+                    action = Some(GameSetupAction::ViewSettings)
                 }
                 if ui.button("About").clicked() {
                     action = Some(GameSetupAction::ViewAbout)
@@ -83,6 +406,17 @@ impl GameSetupView {
                     action = Some(GameSetupAction::Quit);
                 }
             });
+            egui::CollapsingHeader::new("Custom rules").show(ui, |ui| {
+                if let Some(a) = self.custom_rules_ui(ui) {
+                    action = Some(a);
+                }
+            });
+            #[cfg(not(target_arch = "wasm32"))]
+            egui::CollapsingHeader::new("Network game").show(ui, |ui| {
+                if let Some(a) = self.network_ui(ui) {
+                    action = Some(a);
+                }
+            });
         });
         action
     }
@@ -91,11 +425,7 @@ impl GameSetupView {
 
 impl Default for GameSetupView {
     fn default() -> Self {
-        let mut variants: HashMap<String, (Ruleset, String)> = HashMap::default();
-        variants.insert("Copenhagen".to_string(), (rules::COPENHAGEN, boards::COPENHAGEN.to_string()));
-        variants.insert("Brandubh".to_string(), (rules::BRANDUBH, boards::BRANDUBH.to_string()));
-        variants.insert("Tablut".to_string(), (rules::TABLUT, boards::TABLUT.to_string()));
-        variants.insert("Magpie".to_string(), (rules::MAGPIE, boards::MAGPIE.to_string()));
+        let variants = preset_variants();
 
         let mut sides: HashMap<String, pieces::Side> = HashMap::default();
         sides.insert("Attacker".to_string(), pieces::Side::Attacker);
@@ -103,4 +433,50 @@ impl Default for GameSetupView {
 
         Self::new(variants, sides)
     }
-}
\ No newline at end of file
+}
+/// A combo box for choosing the AI's difficulty.
+pub(crate) fn difficulty_combo_box(ui: &mut egui::Ui, id_salt: &str, difficulty: &mut Difficulty) {
+    egui::ComboBox::from_id_salt(id_salt)
+        .selected_text(difficulty.name())
+        .show_ui(ui, |combo_box| {
+            for d in Difficulty::ALL {
+                combo_box.selectable_value(difficulty, d, d.name());
+            }
+        });
+}
+
+/// A combo box for choosing the AI's engine and, for Monte Carlo tree search, controls for its
+/// settings.
+pub(crate) fn engine_ui(ui: &mut egui::Ui, id_salt: &str, engine: &mut AiEngine) {
+    ui.vertical(|ui| {
+        egui::ComboBox::from_id_salt(id_salt)
+            .selected_text(engine.name())
+            .show_ui(ui, |combo_box| {
+                combo_box.selectable_value(engine, AiEngine::AlphaBeta, AiEngine::AlphaBeta.name());
+                let is_mcts = matches!(engine, AiEngine::Mcts(_));
+                if combo_box.selectable_label(is_mcts, "Monte Carlo tree search").clicked() && !is_mcts {
+                    *engine = AiEngine::Mcts(MctsConfig::default());
+                }
+            });
+        if let AiEngine::Mcts(config) = engine {
+            ui.horizontal(|ui| {
+                ui.label("Playouts:");
+                for policy in [PlayoutPolicy::Random, PlayoutPolicy::Heuristic] {
+                    ui.radio_value(&mut config.playout_policy, policy, policy.name());
+                }
+            });
+            ui.horizontal(|ui| {
+                let mut cutoff = config.eval_cutoff.is_some();
+                let mut plies = config.eval_cutoff.unwrap_or(DEFAULT_EVAL_CUTOFF);
+                ui.checkbox(&mut cutoff, "Evaluate playouts after")
+                    .on_hover_text("Stop each playout after this many plays and score it with the \
+                        evaluation function, instead of playing it to the end.");
+                ui.add_enabled(cutoff, egui::DragValue::new(&mut plies).range(1..=200));
+                ui.label("plays");
+                config.eval_cutoff = cutoff.then_some(plies);
+            });
+            ui.add(egui::Slider::new(&mut config.exploration, 0.1..=3.0).text("Exploration"));
+            ui.checkbox(&mut config.reuse_tree, "Reuse search tree between plays");
+        }
+    });
+}
@@ -1,9 +1,26 @@
 #![cfg(target_arch = "wasm32")]
 
 mod ai;
+mod analysis_view;
+mod annotation;
+mod archive;
+mod archive_view;
 mod board;
+mod byte_reader;
+mod custom_rules;
 mod game_play_view;
 mod game_setup_view;
+mod lobby_view;
+mod neural_eval;
+mod online;
+mod opening_book;
+mod position_editor_view;
+mod puzzle;
+mod puzzle_view;
+mod replay_view;
+mod saved_game;
+mod settings;
+mod tablebase;
 mod app;
 
 use wasm_bindgen::prelude::*;
@@ -64,4 +81,4 @@ impl WebHandle {
     pub fn panic_callstack(&self) -> Option<String> {
         self.runner.panic_summary().map(|s| s.callstack())
     }
-}
\ No newline at end of file
+}
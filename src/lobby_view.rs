@@ -0,0 +1,188 @@
+use crate::game_play_view::GameSetup;
+use crate::online::{ClientMessage, LobbyGame, OnlineClient, ServerMessage};
+use crate::saved_game::SavedGame;
+use egui::RichText;
+use hnefatafl::pieces;
+use std::time::Duration;
+
+pub(crate) enum LobbyAction {
+    /// Play or watch an online game. `game` includes any plays already made, and `side` is the side
+    /// we play, or `None` if we are watching.
+    Start {
+        client: OnlineClient,
+        game: SavedGame,
+        side: Option<pieces::Side>
+    },
+    /// Return to the game setup screen, remembering the server URL.
+    Back(String)
+}
+
+/// Lists the games open on a lobby server, and lets the user create, join or watch one.
+pub(crate) struct LobbyView {
+    /// URL of the lobby server.
+    server_url: String,
+    /// The connection to the server, if connected.
+    client: Option<OnlineClient>,
+    games: Vec<LobbyGame>,
+    /// The setup of the game to create, chosen in the game setup screen.
+    setup: GameSetup,
+    /// The side to play in the game we create.
+    host_side: pieces::Side,
+    /// The ID of the game we created, if it is waiting for an opponent.
+    waiting: Option<u64>,
+    /// The last error, if any.
+    message: Option<String>
+}
+
+impl LobbyView {
+
+    pub(crate) fn new(server_url: String, setup: GameSetup) -> Self {
+        Self {
+            server_url,
+            client: None,
+            games: vec![],
+            host_side: setup.ai_side.map_or(pieces::Side::Attacker, |s| s.other()),
+            setup,
+            waiting: None,
+            message: None
+        }
+    }
+
+    fn send(&mut self, msg: ClientMessage) {
+        if let Some(client) = &mut self.client {
+            client.send(&msg);
+        }
+    }
+
+    fn connect(&mut self) {
+        match OnlineClient::connect(self.server_url.trim()) {
+            Ok(client) => {
+                self.client = Some(client);
+                self.message = None;
+                self.send(ClientMessage::ListGames);
+            },
+            Err(e) => self.message = Some(e)
+        }
+    }
+
+    /// Handle messages from the server, returning an action if a game has started.
+    fn handle_messages(&mut self) -> Option<LobbyAction> {
+        loop {
+            let msg = match self.client.as_mut()?.poll() {
+                Ok(Some(msg)) => msg,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.client = None;
+                    self.waiting = None;
+                    self.message = Some(e);
+                    return None
+                }
+            };
+            match msg {
+                ServerMessage::Games { games } => self.games = games,
+                ServerMessage::Created { id } => self.waiting = Some(id),
+                ServerMessage::Started { game, side, .. } => return Some(LobbyAction::Start {
+                    client: self.client.take()?,
+                    game,
+                    side: side.map(pieces::Side::from)
+                }),
+                ServerMessage::Error { reason } => self.message = Some(reason),
+                _ => {}
+            }
+        }
+    }
+
+    fn games_ui(&mut self, ui: &mut egui::Ui) {
+        if self.games.is_empty() {
+            ui.label("No games yet.");
+            return
+        }
+        let mut to_send = None;
+        egui::Grid::new("lobby_games_grid").striped(true).show(ui, |ui| {
+            for heading in ["Game", "Variant", "Host plays", "Status", "Spectators", ""] {
+                ui.label(RichText::new(heading).strong());
+            }
+            ui.end_row();
+            for g in &self.games {
+                ui.label(g.id.to_string());
+                ui.label(&g.ruleset_name);
+                ui.label(format!("{:?}", pieces::Side::from(g.host_side)));
+                ui.label(if g.started { "In progress" } else { "Waiting for opponent" });
+                ui.label(g.spectators.to_string());
+                ui.horizontal(|ui| {
+                    let can_join = !g.started && self.waiting.is_none();
+                    if ui.add_enabled(can_join, egui::Button::new("Join")).clicked() {
+                        to_send = Some(ClientMessage::JoinGame { id: g.id });
+                    }
+                    if ui.add_enabled(self.waiting.is_none(), egui::Button::new("Watch")).clicked() {
+                        to_send = Some(ClientMessage::Watch { id: g.id });
+                    }
+                });
+                ui.end_row();
+            }
+        });
+        if let Some(msg) = to_send {
+            self.send(msg);
+        }
+    }
+
+    fn create_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(id) = self.waiting {
+                ui.label(format!("Waiting for an opponent to join game {id}..."));
+                if ui.button("Cancel").clicked() {
+                    self.send(ClientMessage::Leave);
+                    self.waiting = None;
+                }
+                return
+            }
+            ui.label(format!("New {} game, playing as:", self.setup.ruleset_name));
+            ui.radio_value(&mut self.host_side, pieces::Side::Attacker, "Attacker");
+            ui.radio_value(&mut self.host_side, pieces::Side::Defender, "Defender");
+            if ui.button("Create game").clicked() {
+                let mut game = SavedGame::new(&self.setup, std::iter::empty());
+                game.ai_side = Some(self.host_side.other().into());
+                game.remote = true;
+                self.send(ClientMessage::CreateGame { game, host_side: self.host_side.into() });
+            }
+        });
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<LobbyAction> {
+        let mut action = self.handle_messages();
+        if self.client.is_some() {
+            // Keep checking for messages from the server.
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.label(RichText::new("Online games").heading());
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if ui.button("Back").clicked() {
+                action = Some(LobbyAction::Back(self.server_url.clone()));
+            }
+            ui.horizontal(|ui| {
+                ui.label("Server:");
+                ui.add_enabled(self.client.is_none(), egui::TextEdit::singleline(&mut self.server_url));
+                if self.client.is_none() {
+                    if ui.button("Connect").clicked() {
+                        self.connect();
+                    }
+                } else if ui.button("Refresh").clicked() {
+                    self.send(ClientMessage::ListGames);
+                }
+            });
+            if let Some(msg) = &self.message {
+                ui.label(msg);
+            }
+            if self.client.is_some() {
+                ui.separator();
+                self.create_ui(ui);
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| self.games_ui(ui));
+            }
+        });
+        action
+    }
+
+}
@@ -1,17 +1,65 @@
 #![cfg(not(target_arch = "wasm32"))]
 
-use crate::app::MyApp;
+use crate::app::{MyApp, APP_NAME};
 
 mod ai;
+mod analysis_view;
+mod annotation;
+mod archive;
+mod archive_view;
 mod board;
+mod byte_reader;
+mod book_builder;
+mod custom_rules;
+mod export;
 mod game_play_view;
 mod game_setup_view;
+mod lobby_view;
+mod network;
+mod neural_eval;
+mod neural_trainer;
+mod online;
+mod opening_book;
+mod perft;
+mod position_editor_view;
+mod puzzle;
+mod puzzle_generator;
+mod puzzle_view;
+mod replay_view;
+mod saved_game;
+mod server;
+mod settings;
+mod tablebase;
+mod tablebase_builder;
+mod tournament;
 mod app;
 
+/// Runs a command-line subcommand with the arguments that follow its name.
+type Command = fn(&[String]) -> Result<(), String>;
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command: Option<Command> = match args.first().map(String::as_str) {
+        Some("export") => Some(export::run_export_command),
+        Some("book") => Some(book_builder::run_book_command),
+        Some("tablebase") => Some(tablebase_builder::run_tablebase_command),
+        Some("train") => Some(neural_trainer::run_train_command),
+        Some("perft") => Some(perft::run_perft_command),
+        Some("tournament") => Some(tournament::run_tournament_command),
+        Some("puzzles") => Some(puzzle_generator::run_puzzles_command),
+        Some("serve") => Some(server::run_server_command),
+        _ => None
+    };
+    if let Some(command) = command {
+        if let Err(e) = command(&args[1..]) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return
+    }
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
-        "Tafl egui demo",
+        APP_NAME,
         native_options,
         Box::new(|cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
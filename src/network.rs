@@ -0,0 +1,641 @@
+use crate::game_play_view::{GameSetup, Message};
+use crate::saved_game::{parse_plays, SavedGame, SavedSide};
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::Game;
+use hnefatafl::pieces;
+use hnefatafl::play::Play;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Version of the protocol below. Both ends must use the same version.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+/// The port games are hosted on by default.
+pub(crate) const DEFAULT_PORT: u16 = 7878;
+/// How long to wait for each message during the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for a message before checking for messages from the game.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often a guest tries to reconnect after losing its connection.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Messages exchanged between the two ends of a network game, each sent as a line of JSON.
+///
+/// Both ends start by sending `Hello`. The host then describes the game with `Game`, and the guest
+/// answers with `Accept` or `Reject`. After that, either end may send any of the remaining
+/// messages. The same handshake is used when reconnecting, so that the ends can check that they
+/// agree on the plays made so far.
+#[derive(Serialize, Deserialize, Debug)]
+enum NetMessage {
+    Hello {
+        version: u32
+    },
+    /// The game being played, including any plays already made, and the side the guest plays.
+    Game {
+        game: SavedGame,
+        guest_side: SavedSide
+    },
+    /// The guest agrees to play the game. `plays` are the plays the guest knows about (which is
+    /// none, except when reconnecting).
+    Accept {
+        plays: Vec<String>
+    },
+    Reject {
+        reason: String
+    },
+    Play {
+        play: String
+    },
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw
+}
+
+/// A connection to the other end, over which messages are sent as lines of JSON.
+pub(crate) struct Connection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    /// Bytes of a message that has only partly been received.
+    buf: Vec<u8>
+}
+
+impl Connection {
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), stream, buf: vec![] })
+    }
+
+    fn send(&mut self, msg: &NetMessage) -> io::Result<()> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        self.stream.write_all(&line)
+    }
+
+    /// Return the next message if one arrives within [`POLL_INTERVAL`].
+    fn poll(&mut self) -> io::Result<Option<NetMessage>> {
+        // If the read times out, any bytes already read stay in `buf` for the next call.
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(_) if self.buf.ends_with(b"\n") => {
+                let msg = serde_json::from_slice(&self.buf);
+                self.buf.clear();
+                Ok(Some(msg?))
+            },
+            Ok(_) => Ok(None),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Wait up to `timeout` for the next message.
+    fn recv(&mut self, timeout: Duration) -> Result<NetMessage, String> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(msg) = self.poll().map_err(|e| format!("Connection error: {e}"))? {
+                return Ok(msg)
+            }
+        }
+        Err("Timed out waiting for opponent.".to_string())
+    }
+
+    /// Exchange `Hello` messages, checking that both ends use the same protocol version.
+    fn hello(&mut self) -> Result<(), String> {
+        self.send(&NetMessage::Hello { version: PROTOCOL_VERSION })
+            .map_err(|e| format!("Connection error: {e}"))?;
+        match self.recv(HANDSHAKE_TIMEOUT)? {
+            NetMessage::Hello { version } if version == PROTOCOL_VERSION => Ok(()),
+            NetMessage::Hello { version } => Err(format!(
+                "Opponent uses protocol version {version}, but this app uses version {PROTOCOL_VERSION}."
+            )),
+            other => Err(format!("Unexpected message from opponent: {other:?}"))
+        }
+    }
+}
+
+/// How this end takes part in a network game.
+enum Role {
+    /// We are hosting the game, and the guest connects (or reconnects) to this listener.
+    Host(TcpListener),
+    /// We joined the game hosted at this address.
+    Guest(SocketAddr)
+}
+
+/// The remote player in a network game.
+pub(crate) struct RemotePlayer {
+    role: Role,
+    /// The connection to the remote player, if currently connected.
+    conn: Option<Connection>
+}
+
+/// Start listening for a guest to connect on `port`.
+pub(crate) fn host(port: u16) -> Result<RemotePlayer, String> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .map_err(|e| format!("Could not listen on port {port}: {e}"))?;
+    // The listener is polled, so that the game can still be played (or quit) while waiting.
+    listener.set_nonblocking(true).map_err(|e| format!("Could not listen on port {port}: {e}"))?;
+    Ok(RemotePlayer { role: Role::Host(listener), conn: None })
+}
+
+/// Connect to `address` and carry out a guest's side of the handshake, telling the host which
+/// plays we already know about.
+fn connect(address: SocketAddr, plays: &[Play]) -> Result<(Connection, SavedGame, SavedSide), String> {
+    let stream = TcpStream::connect_timeout(&address, HANDSHAKE_TIMEOUT)
+        .map_err(|e| format!("Could not connect to {address}: {e}"))?;
+    let mut conn = Connection::new(stream).map_err(|e| format!("Connection error: {e}"))?;
+    conn.hello()?;
+    let NetMessage::Game { game, guest_side } = conn.recv(HANDSHAKE_TIMEOUT)? else {
+        return Err("Opponent did not describe the game.".to_string())
+    };
+    let checked = game.setup().and_then(|_| game.plays());
+    let reply = match &checked {
+        Ok(_) => NetMessage::Accept { plays: plays.iter().map(|p| p.to_string()).collect() },
+        Err(e) => NetMessage::Reject { reason: e.clone() }
+    };
+    conn.send(&reply).map_err(|e| format!("Connection error: {e}"))?;
+    checked?;
+    Ok((conn, game, guest_side))
+}
+
+/// Join the game hosted at `address` (`host:port`), returning the remote player along with a
+/// description of the game and the side we play. This waits for the host, so should not be called
+/// from the UI thread.
+fn join(address: &str) -> Result<(RemotePlayer, SavedGame, pieces::Side), String> {
+    let address = address.to_socket_addrs()
+        .map_err(|e| format!("Invalid address {address:?}: {e}"))?
+        .next()
+        .ok_or(format!("Could not resolve {address:?}."))?;
+    let (conn, game, side) = connect(address, &[])?;
+    Ok((RemotePlayer { role: Role::Guest(address), conn: Some(conn) }, game, side.into()))
+}
+
+/// Start a thread that plays on behalf of the remote player in a game with the given setup (in
+/// which the remote player plays `setup.ai_side`), starting after `plays` have been made. Like the
+/// AI, it receives requests from the game on `from_game` and responds on `to_game`.
+pub(crate) fn spawn_remote_player<T: BoardState + Send + 'static>(
+    remote: RemotePlayer,
+    setup: GameSetup,
+    plays: Vec<Play>,
+    to_game: Sender<Message<T>>,
+    from_game: Receiver<Message<T>>
+) where T::BitField: Send {
+    thread::spawn(move || {
+        if let Role::Host(listener) = &remote.role {
+            if let Ok(addr) = listener.local_addr() {
+                let _ = to_game.send(Message::Info(format!(
+                    "Waiting for opponent to connect on port {}.", addr.port()
+                )));
+            }
+        }
+        RemoteWorker::new(remote, setup, &plays, to_game).run(from_game);
+    });
+}
+
+/// Start a thread that joins the game hosted at `address` (`host:port`) and then plays on behalf of
+/// the host, like [`spawn_remote_player`]. Progress is reported to the game with `Message::Info`,
+/// and once the host has described the game it is passed on with `Message::Joined`.
+pub(crate) fn spawn_guest<T: BoardState + Send + 'static>(
+    address: String,
+    to_game: Sender<Message<T>>,
+    from_game: Receiver<Message<T>>
+) where T::BitField: Send {
+    thread::spawn(move || {
+        let _ = to_game.send(Message::Info(format!("Connecting to {address}.")));
+        let joined = join(&address).and_then(|(remote, game, side)| {
+            let mut setup = game.setup()?;
+            setup.ai_side = Some(side.other());
+            Ok((remote, game.plays()?, setup, game, side))
+        });
+        match joined {
+            Ok((remote, plays, setup, game, side)) => {
+                let _ = to_game.send(Message::Joined(game, side));
+                RemoteWorker::new(remote, setup, &plays, to_game).run(from_game);
+            },
+            Err(e) => {
+                let _ = to_game.send(Message::Info(format!("Could not join game: {e}")));
+            }
+        }
+    });
+}
+
+/// How the plays known to each end compare after reconnecting.
+enum Sync {
+    /// Both ends know the same plays.
+    Same,
+    /// The remote end has made a play we did not receive.
+    RemoteAhead(Play),
+    /// The remote end did not receive the last play we made.
+    LocalAhead(Play),
+    Diverged
+}
+
+fn sync(local: &[Play], remote: &[Play]) -> Sync {
+    if local == remote {
+        Sync::Same
+    } else if remote.len() == local.len() + 1 && remote.starts_with(local) {
+        Sync::RemoteAhead(remote[local.len()])
+    } else if local.len() == remote.len() + 1 && local.starts_with(remote) {
+        Sync::LocalAhead(local[remote.len()])
+    } else {
+        Sync::Diverged
+    }
+}
+
+/// Plays on behalf of the remote player, relaying messages between the game and the connection
+/// to the other end. Runs on its own thread, like the AI.
+struct RemoteWorker<T: BoardState> {
+    role: Role,
+    setup: GameSetup,
+    /// Our copy of the game, used to check the remote player's plays.
+    game: Game<T>,
+    remote_side: pieces::Side,
+    conn: Option<Connection>,
+    /// Messages to send once the connection is re-established.
+    unsent: Vec<NetMessage>,
+    /// Whether the ends have disagreed about the game, so that there is no point reconnecting.
+    failed: bool,
+    last_connect_attempt: Option<Instant>,
+    to_game: Sender<Message<T>>
+}
+
+impl<T: BoardState> RemoteWorker<T> {
+
+    fn new(remote: RemotePlayer, setup: GameSetup, plays: &[Play], to_game: Sender<Message<T>>) -> Self {
+        let mut game: Game<T> = Game::new(setup.ruleset, &setup.starting_board)
+            .expect("Game should already have been checked.");
+        for play in plays {
+            game.do_play(*play).expect("Plays should already have been checked.");
+        }
+        Self {
+            remote_side: setup.ai_side.expect("Remote player should have a side."),
+            role: remote.role,
+            conn: remote.conn,
+            unsent: vec![],
+            setup,
+            game,
+            failed: false,
+            last_connect_attempt: None,
+            to_game
+        }
+    }
+
+    fn info(&self, msg: String) {
+        let _ = self.to_game.send(Message::Info(msg));
+    }
+
+    fn plays(&self) -> Vec<Play> {
+        self.game.play_history.iter().map(|r| r.play).collect()
+    }
+
+    /// Send a message to the other end, dropping the connection if that fails. If there is no
+    /// connection, the message is sent once there is one again.
+    fn send(&mut self, msg: NetMessage) {
+        match self.conn.as_mut().map(|conn| conn.send(&msg)) {
+            Some(Ok(())) => {},
+            Some(Err(e)) => {
+                self.unsent.push(msg);
+                self.connection_lost(e);
+            },
+            None => self.unsent.push(msg)
+        }
+    }
+
+    fn connection_lost(&mut self, e: io::Error) {
+        self.conn = None;
+        let waiting = match self.role {
+            Role::Host(_) => "Waiting for opponent to reconnect.",
+            Role::Guest(_) => "Trying to reconnect."
+        };
+        self.info(format!("Lost connection to opponent ({e}). {waiting}"));
+    }
+
+    /// Handle a message from the game.
+    fn handle_local(&mut self, msg: Message<T>) {
+        let net_msg = match msg {
+            Message::Request(_, Some(play)) => {
+                if let Err(e) = self.game.do_play(play) {
+                    self.info(format!("Could not record play {play}: {e:?}"));
+                    return
+                }
+                NetMessage::Play { play: play.to_string() }
+            },
+            Message::Resign => NetMessage::Resign,
+            Message::OfferDraw => NetMessage::OfferDraw,
+            Message::AcceptDraw => NetMessage::AcceptDraw,
+            Message::DeclineDraw => NetMessage::DeclineDraw,
+            _ => return
+        };
+        self.send(net_msg);
+    }
+
+    /// Check and make a play received from the remote player, and pass it on to the game.
+    fn remote_play(&mut self, play: Play) {
+        let state = self.game.state;
+        let valid = if state.side_to_play == self.remote_side {
+            self.game.logic.validate_play(play, &state).map_err(|e| format!("{e:?}"))
+        } else {
+            Err("not their turn".to_string())
+        };
+        match valid {
+            Ok(vp) => {
+                let _ = self.game.do_play(play);
+                let _ = self.to_game.send(Message::Response(vp, state, vec![]));
+            },
+            Err(e) => self.info(format!("Opponent made an invalid play {play} ({e}); ignoring it."))
+        }
+    }
+
+    /// Handle a message from the other end.
+    fn handle_remote(&mut self, msg: NetMessage) {
+        let to_game = match msg {
+            NetMessage::Play { play } => {
+                match play.parse::<Play>() {
+                    Ok(play) => self.remote_play(play),
+                    Err(e) => self.info(format!("Could not understand opponent's play {play:?}: {e:?}"))
+                }
+                return
+            },
+            NetMessage::Resign => Message::Resign,
+            NetMessage::OfferDraw => Message::OfferDraw,
+            NetMessage::AcceptDraw => Message::AcceptDraw,
+            NetMessage::DeclineDraw => Message::DeclineDraw,
+            other => {
+                self.info(format!("Unexpected message from opponent: {other:?}"));
+                return
+            }
+        };
+        let _ = self.to_game.send(to_game);
+    }
+
+    /// Carry out the host's side of the handshake with a newly connected guest, returning the plays
+    /// the guest knows about.
+    fn host_handshake(&self, conn: &mut Connection) -> Result<Vec<Play>, String> {
+        conn.hello()?;
+        let game = SavedGame::new(&self.setup, self.plays().into_iter());
+        conn.send(&NetMessage::Game { game, guest_side: self.remote_side.into() })
+            .map_err(|e| format!("Connection error: {e}"))?;
+        match conn.recv(HANDSHAKE_TIMEOUT)? {
+            NetMessage::Accept { plays } => parse_plays(&plays),
+            NetMessage::Reject { reason } => Err(format!("Opponent rejected the game: {reason}")),
+            other => Err(format!("Unexpected message from opponent: {other:?}"))
+        }
+    }
+
+    /// Try to (re)establish the connection, without waiting if there is nothing to connect to.
+    fn try_connect(&mut self) {
+        let result = match &self.role {
+            Role::Host(listener) => match listener.accept() {
+                Ok((stream, _)) => Connection::new(stream)
+                    .map_err(|e| format!("Connection error: {e}"))
+                    .and_then(|mut conn| Ok((self.host_handshake(&mut conn)?, conn))),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    return
+                },
+                Err(e) => Err(format!("Could not accept connection: {e}"))
+            },
+            Role::Guest(address) => {
+                if self.last_connect_attempt.is_some_and(|t| t.elapsed() < RECONNECT_INTERVAL) {
+                    thread::sleep(POLL_INTERVAL);
+                    return
+                }
+                self.last_connect_attempt = Some(Instant::now());
+                connect(*address, &self.plays())
+                    .and_then(|(conn, game, _)| Ok((game.plays()?, conn)))
+            }
+        };
+        match result {
+            Ok((remote_plays, conn)) => {
+                self.conn = Some(conn);
+                self.info("Opponent connected.".to_string());
+                self.sync_plays(&remote_plays);
+                // Plays have just been brought up to date, so only the other messages are resent.
+                for msg in std::mem::take(&mut self.unsent) {
+                    if !matches!(msg, NetMessage::Play { .. }) {
+                        self.send(msg);
+                    }
+                }
+            },
+            Err(e) => self.info(e)
+        }
+    }
+
+    /// Bring both ends up to date after (re)connecting.
+    fn sync_plays(&mut self, remote_plays: &[Play]) {
+        match sync(&self.plays(), remote_plays) {
+            Sync::Same => {},
+            Sync::RemoteAhead(play) => self.remote_play(play),
+            Sync::LocalAhead(play) => self.send(NetMessage::Play { play: play.to_string() }),
+            Sync::Diverged => {
+                self.conn = None;
+                self.unsent.clear();
+                self.failed = true;
+                self.info("This game and the opponent's game have diverged; cannot continue.".to_string());
+            }
+        }
+    }
+
+    fn run(mut self, from_game: Receiver<Message<T>>) {
+        loop {
+            match from_game.recv_timeout(POLL_INTERVAL) {
+                Ok(msg) => self.handle_local(msg),
+                Err(RecvTimeoutError::Timeout) => {},
+                // The game has been quit
+                Err(RecvTimeoutError::Disconnected) => break
+            }
+            if self.failed {
+                continue
+            }
+            match self.conn.as_mut().map(|c| c.poll()) {
+                Some(Ok(Some(msg))) => self.handle_remote(msg),
+                Some(Ok(None)) => {},
+                Some(Err(e)) => self.connection_lost(e),
+                None => self.try_connect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hnefatafl::aliases::LargeBasicBoardState;
+    use hnefatafl::pieces::Side::{Attacker, Defender};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    type State = LargeBasicBoardState;
+
+    /// How long to wait for a message before failing, which allows for reconnecting.
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    /// A Brandubh game, in which the remote player plays `remote_side`.
+    fn setup(remote_side: pieces::Side) -> GameSetup {
+        let mut setup = SavedGame::preset("Brandubh").setup().unwrap();
+        setup.ai_side = Some(remote_side);
+        setup
+    }
+
+    /// One end of a game, as seen by the game: its own copy of the game, and the channels to and
+    /// from the thread playing for the other end.
+    struct End {
+        game: Game<State>,
+        to_remote: Sender<Message<State>>,
+        from_remote: Receiver<Message<State>>
+    }
+
+    impl End {
+
+        fn new(setup: &GameSetup, to_remote: Sender<Message<State>>, from_remote: Receiver<Message<State>>) -> Self {
+            let game = Game::new(setup.ruleset, &setup.starting_board).unwrap();
+            Self { game, to_remote, from_remote }
+        }
+
+        /// Wait for the first message (other than `Info`) from the remote player.
+        fn recv(&self) -> Message<State> {
+            let deadline = Instant::now() + TIMEOUT;
+            while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                match self.from_remote.recv_timeout(timeout) {
+                    Ok(Message::Info(_)) => {},
+                    Ok(msg) => return msg,
+                    Err(e) => panic!("No message from remote player: {e}")
+                }
+            }
+            panic!("Timed out waiting for remote player.")
+        }
+
+        /// Wait for an `Info` message containing `text`.
+        fn wait_for_info(&self, text: &str) {
+            let deadline = Instant::now() + TIMEOUT;
+            while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                if let Ok(Message::Info(info)) = self.from_remote.recv_timeout(timeout) {
+                    if info.contains(text) {
+                        return
+                    }
+                }
+            }
+            panic!("Timed out waiting for {text:?}.")
+        }
+
+        fn play(&mut self, play: &str) {
+            let play: Play = play.parse().unwrap();
+            self.game.do_play(play).unwrap();
+            self.to_remote.send(Message::Request(self.game.state, Some(play))).unwrap();
+        }
+
+        /// Wait for the remote player's next play, which should be `expected`.
+        fn expect_play(&mut self, expected: &str) {
+            match self.recv() {
+                Message::Response(vp, _, _) => {
+                    assert_eq!(vp.play.to_string(), expected);
+                    self.game.do_play(vp.play).unwrap();
+                },
+                _ => panic!("Expected {expected} from remote player.")
+            }
+        }
+    }
+
+    /// Host a game on a free port, with the host playing as attacker, returning the host's end and
+    /// the port.
+    fn start_host() -> (End, u16) {
+        let remote = host(0).unwrap();
+        let Role::Host(listener) = &remote.role else { unreachable!() };
+        let port = listener.local_addr().unwrap().port();
+        let setup = setup(Defender);
+        let (to_worker, worker_rx) = channel();
+        let (worker_tx, from_worker) = channel();
+        spawn_remote_player(remote, setup.clone(), vec![], worker_tx, worker_rx);
+        (End::new(&setup, to_worker, from_worker), port)
+    }
+
+    /// Join the game hosted at `address`, returning the guest's end once the host has described
+    /// the game.
+    fn start_guest(address: String) -> End {
+        let (to_worker, worker_rx) = channel();
+        let (worker_tx, from_worker) = channel();
+        spawn_guest(address, worker_tx, worker_rx);
+        let mut end = End::new(&setup(Attacker), to_worker, from_worker);
+        match end.recv() {
+            Message::Joined(game, side) => {
+                assert_eq!(side, Defender);
+                for play in game.plays().unwrap() {
+                    end.game.do_play(play).unwrap();
+                }
+            },
+            _ => panic!("Expected the host to describe the game.")
+        }
+        end
+    }
+
+    /// Forward connections from a local port to `port`, returning the local port and the
+    /// forwarded streams, which can be shut down to break the connection.
+    fn start_proxy(port: u16) -> (u16, Arc<Mutex<Vec<TcpStream>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_port = listener.local_addr().unwrap().port();
+        let streams: Arc<Mutex<Vec<TcpStream>>> = Arc::default();
+        let proxy_streams = streams.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let Ok(client) = client else { break };
+                let Ok(server) = TcpStream::connect(("127.0.0.1", port)) else { break };
+                for (mut from, mut to) in [
+                    (client.try_clone().unwrap(), server.try_clone().unwrap()),
+                    (server.try_clone().unwrap(), client.try_clone().unwrap())
+                ] {
+                    thread::spawn(move || {
+                        let _ = io::copy(&mut from, &mut to);
+                        let _ = to.shutdown(std::net::Shutdown::Both);
+                    });
+                }
+                proxy_streams.lock().unwrap().extend([client, server]);
+            }
+        });
+        (proxy_port, streams)
+    }
+
+    #[test]
+    fn plays_are_exchanged_until_resignation() {
+        let (mut host, port) = start_host();
+        let mut guest = start_guest(format!("127.0.0.1:{port}"));
+        host.play("d1-c1");
+        guest.expect_play("d1-c1");
+        guest.play("d3-c3");
+        host.expect_play("d3-c3");
+        host.play("c1-b1");
+        guest.expect_play("c1-b1");
+        guest.to_remote.send(Message::Resign).unwrap();
+        assert!(matches!(host.recv(), Message::Resign));
+    }
+
+    #[test]
+    fn dropped_guest_reconnects_and_resyncs() {
+        let (mut host, port) = start_host();
+        let (proxy_port, streams) = start_proxy(port);
+        let mut guest = start_guest(format!("127.0.0.1:{proxy_port}"));
+        host.play("d1-c1");
+        guest.expect_play("d1-c1");
+        guest.play("d3-c3");
+        host.expect_play("d3-c3");
+
+        // Break the connection, and play while the guest is reconnecting.
+        for stream in streams.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        host.wait_for_info("Lost connection");
+        host.play("c1-b1");
+        host.to_remote.send(Message::OfferDraw).unwrap();
+
+        // The play is resent when the guest reconnects, followed by the draw offer.
+        guest.expect_play("c1-b1");
+        assert!(matches!(guest.recv(), Message::OfferDraw));
+        guest.to_remote.send(Message::Resign).unwrap();
+        assert!(matches!(host.recv(), Message::Resign));
+    }
+}
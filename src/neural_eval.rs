@@ -0,0 +1,161 @@
+use crate::byte_reader::ByteReader;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::state::GameState;
+use hnefatafl::pieces::PieceType::King;
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Identifies an evaluation network file (and the version of its format).
+const MAGIC: &[u8; 8] = b"TAFLNN1\n";
+/// Multiplier converting the network's output (the log-odds of the attacker winning) into a score
+/// on the same scale as the handcrafted evaluation.
+pub(crate) const NETWORK_SCORE_SCALE: f32 = 100.0;
+/// The number of input planes: attacking soldiers, defending soldiers and the king.
+const PLANES: usize = 3;
+
+/// Evaluation networks by variant name.
+pub(crate) type EvalNetworks = BTreeMap<String, Arc<EvalNetwork>>;
+
+/// The number of inputs of a network for a board of the given side length: one per tile for each
+/// plane, plus one that is set when the defender is to play.
+pub(crate) fn input_count(side_len: u8) -> usize {
+    PLANES * side_len as usize * side_len as usize + 1
+}
+
+/// The indices of the inputs that are set for the position in `state`, on a board of the given
+/// side length. All other inputs are zero.
+pub(crate) fn active_inputs<T: BoardState>(state: &GameState<T>, side_len: u8) -> Vec<usize> {
+    let n_tiles = side_len as usize * side_len as usize;
+    let board = &state.board;
+    let mut inputs: Vec<usize> = [Attacker, Defender].into_iter()
+        .flat_map(|side| board.occupied_by_side(side))
+        .map(|t| {
+            let piece = board.get_piece(t).expect("There should be a piece here.");
+            let plane = if piece.piece_type == King { 2 } else if piece.side == Attacker { 0 } else { 1 };
+            plane * n_tiles + t.row as usize * side_len as usize + t.col as usize
+        })
+        .collect();
+    if state.side_to_play == Defender {
+        inputs.push(PLANES * n_tiles);
+    }
+    inputs
+}
+
+/// Clipped ReLU, the activation function of the hidden layers.
+pub(crate) fn crelu(x: f32) -> f32 {
+    x.clamp(0.0, 1.0)
+}
+
+/// A small fully connected network (two hidden layers with clipped ReLU activations) that
+/// estimates the log-odds of the attacker winning from a position. As in NNUE, the inputs are
+/// sparse, so the first layer is computed by adding up the weights of the set inputs only.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct EvalNetwork {
+    /// The name of the variant the network was trained on.
+    pub(crate) variant: String,
+    pub(crate) side_len: u8,
+    pub(crate) hidden1: usize,
+    pub(crate) hidden2: usize,
+    /// First layer weights, `hidden1` for each input.
+    pub(crate) w1: Vec<f32>,
+    pub(crate) b1: Vec<f32>,
+    /// Second layer weights, `hidden1` for each unit of the second hidden layer.
+    pub(crate) w2: Vec<f32>,
+    pub(crate) b2: Vec<f32>,
+    /// Output layer weights, one for each unit of the second hidden layer.
+    pub(crate) w3: Vec<f32>,
+    pub(crate) b3: f32
+}
+
+impl EvalNetwork {
+
+    /// Create a network with small random weights.
+    pub(crate) fn new(variant: impl Into<String>, side_len: u8, hidden1: usize, hidden2: usize, rng: &mut impl Rng) -> Self {
+        let mut weights = |n: usize, fan_in: usize| -> Vec<f32> {
+            let bound = (1.0 / fan_in as f32).sqrt();
+            (0..n).map(|_| rng.gen_range(-bound..=bound)).collect()
+        };
+        // Only a few dozen inputs are set at once, so scale the first layer for that fan-in.
+        let w1 = weights(input_count(side_len) * hidden1, 32);
+        let w2 = weights(hidden1 * hidden2, hidden1);
+        let w3 = weights(hidden2, hidden2);
+        Self {
+            variant: variant.into(),
+            side_len,
+            hidden1,
+            hidden2,
+            w1,
+            b1: vec![0.0; hidden1],
+            w2,
+            b2: vec![0.0; hidden2],
+            w3,
+            b3: 0.0
+        }
+    }
+
+    /// The outputs of both hidden layers and of the network, for the given set inputs.
+    pub(crate) fn forward(&self, inputs: &[usize]) -> (Vec<f32>, Vec<f32>, f32) {
+        let mut acc = self.b1.clone();
+        for i in inputs {
+            let column = &self.w1[i * self.hidden1..(i + 1) * self.hidden1];
+            acc.iter_mut().zip(column).for_each(|(a, w)| *a += w);
+        }
+        let h1: Vec<f32> = acc.into_iter().map(crelu).collect();
+        let h2: Vec<f32> = self.b2.iter()
+            .enumerate()
+            .map(|(j, b)| {
+                let row = &self.w2[j * self.hidden1..(j + 1) * self.hidden1];
+                crelu(b + row.iter().zip(&h1).map(|(w, x)| w * x).sum::<f32>())
+            })
+            .collect();
+        let out = self.b3 + self.w3.iter().zip(&h2).map(|(w, x)| w * x).sum::<f32>();
+        (h1, h2, out)
+    }
+
+    /// Evaluate the position in `state` on the scale of the handcrafted evaluation: higher is
+    /// better for the attacker.
+    pub(crate) fn evaluate<T: BoardState>(&self, state: &GameState<T>) -> i32 {
+        let (_, _, out) = self.forward(&active_inputs(state, self.side_len));
+        (out * NETWORK_SCORE_SCALE) as i32
+    }
+
+    /// Encode the network in its on-disk format: a header (magic bytes, variant name, board size
+    /// and layer sizes) followed by the weights and biases of each layer as little-endian `f32`s.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            32 + self.variant.len() + 4 * (self.w1.len() + self.w2.len() + self.w3.len() + self.hidden1 + self.hidden2)
+        );
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.variant.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.variant.as_bytes());
+        bytes.push(self.side_len);
+        bytes.extend_from_slice(&(self.hidden1 as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden2 as u16).to_le_bytes());
+        for layer in [&self.w1, &self.b1, &self.w2, &self.b2, &self.w3] {
+            for x in layer {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.b3.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a network encoded by [`EvalNetwork::to_bytes`].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(bytes, MAGIC, "network")?;
+        let variant = reader.string()?;
+        let side_len = reader.u8()?;
+        let hidden1 = reader.u16()? as usize;
+        let hidden2 = reader.u16()? as usize;
+        let w1 = reader.f32s(input_count(side_len) * hidden1)?;
+        let b1 = reader.f32s(hidden1)?;
+        let w2 = reader.f32s(hidden1 * hidden2)?;
+        let b2 = reader.f32s(hidden2)?;
+        let w3 = reader.f32s(hidden2)?;
+        let b3 = reader.f32s(1)?[0];
+        reader.finish()?;
+        Ok(Self { variant, side_len, hidden1, hidden2, w1, b1, w2, b2, w3, b3 })
+    }
+}
@@ -0,0 +1,264 @@
+use crate::ai::Difficulty;
+use crate::app::APP_NAME;
+use crate::archive::ArchivedResult;
+use crate::book_builder::{output_path, parse_difficulty, self_play_game, write_output};
+use crate::custom_rules::preset_variants;
+use crate::neural_eval::{active_inputs, input_count, EvalNetwork, EvalNetworks};
+use crate::saved_game::SavedSide;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::Game;
+use hnefatafl::pieces::Side::Attacker;
+use hnefatafl::play::Play;
+use hnefatafl::rules::Ruleset;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Units in the second hidden layer of networks trained by the `train` subcommand.
+const HIDDEN2: usize = 32;
+/// Number of positions in each batch of training.
+const BATCH_SIZE: usize = 32;
+/// One in this many positions is held back to measure how well the network generalises.
+const VALIDATION_EVERY: usize = 10;
+
+pub(crate) const TRAIN_USAGE: &str = "\
+Usage: hnefatafl-egui train <variant> [--games <games>] [--time <seconds>] [--difficulty <level>]
+                           [--epochs <epochs>] [--hidden <units>] [--learning-rate <rate>]
+                           [--output <network file>]
+
+Train a neural network to evaluate positions in a preset variant, on the CPU, from games played by
+the AI against itself. Each position is labelled with the result of its game. Training continues
+from any network for the same board size at the output path, which defaults to the directory the
+app loads networks from. By default, 20 games are played at 0.2 seconds per move at Medium
+difficulty (whose mistakes vary the games), and the network (with 64 units in its first hidden
+layer) is trained for 20 epochs at a learning rate of 0.05.";
+
+/// The directory from which the app loads networks trained by the `train` subcommand.
+pub(crate) fn networks_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|d| d.join("networks"))
+}
+
+fn read_network(path: &Path) -> Result<EvalNetwork, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    EvalNetwork::from_bytes(&bytes).map_err(|e| format!("Could not load {}: {e}", path.display()))
+}
+
+/// Load all networks in [`networks_dir`], by variant. Files that cannot be read are skipped.
+pub(crate) fn load_network_files() -> EvalNetworks {
+    let mut networks = EvalNetworks::new();
+    let Some(entries) = networks_dir().and_then(|d| std::fs::read_dir(d).ok()) else { return networks };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_none_or(|ext| ext != "nn") {
+            continue
+        }
+        match read_network(&path) {
+            Ok(network) => {
+                networks.insert(network.variant.clone(), Arc::new(network));
+            },
+            Err(e) => log::warn!("{e}")
+        }
+    }
+    networks
+}
+
+/// A position to train on: the inputs that are set, and the result of the game it was reached in
+/// (1 for an attacker win, 0 for a defender win, 0.5 for a draw or unfinished game).
+struct Sample {
+    inputs: Vec<usize>,
+    target: f32
+}
+
+/// Training samples for every position reached in the game made up of `plays`.
+fn game_samples(
+    ruleset: Ruleset,
+    starting_board: &str,
+    plays: &[Play],
+    result: Option<ArchivedResult>
+) -> Result<Vec<Sample>, String> {
+    let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    let side_len = game.logic.board_geo.side_len;
+    let target = match result {
+        Some(ArchivedResult::Win(winner)) if winner == SavedSide::from(Attacker) => 1.0,
+        Some(ArchivedResult::Win(_)) => 0.0,
+        Some(ArchivedResult::Draw) | None => 0.5
+    };
+    let mut samples = vec![];
+    for play in plays {
+        samples.push(Sample { inputs: active_inputs(&game.state, side_len), target });
+        game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+    }
+    Ok(samples)
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// The cross-entropy loss of predicting `p` when the result is `target`.
+fn loss(p: f32, target: f32) -> f32 {
+    let p = p.clamp(1e-6, 1.0 - 1e-6);
+    -(target * p.ln() + (1.0 - target) * (1.0 - p).ln())
+}
+
+/// The derivative of the clipped ReLU, given its output.
+fn crelu_grad(y: f32) -> f32 {
+    if y > 0.0 && y < 1.0 { 1.0 } else { 0.0 }
+}
+
+/// The mean loss of `network` over `samples`.
+fn mean_loss(network: &EvalNetwork, samples: &[&Sample]) -> f32 {
+    let total: f32 = samples.iter()
+        .map(|s| loss(sigmoid(network.forward(&s.inputs).2), s.target))
+        .sum();
+    total / samples.len().max(1) as f32
+}
+
+/// Update `network` by one step of gradient descent on `batch`, by backpropagation.
+fn train_batch(network: &mut EvalNetwork, batch: &[&Sample], learning_rate: f32) {
+    let (h1_len, h2_len) = (network.hidden1, network.hidden2);
+    let mut g_w1 = vec![0.0f32; network.w1.len()];
+    let mut g_b1 = vec![0.0f32; h1_len];
+    let mut g_w2 = vec![0.0f32; network.w2.len()];
+    let mut g_b2 = vec![0.0f32; h2_len];
+    let mut g_w3 = vec![0.0f32; h2_len];
+    let mut g_b3 = 0.0f32;
+    for sample in batch {
+        let (h1, h2, out) = network.forward(&sample.inputs);
+        let d_out = sigmoid(out) - sample.target;
+        g_b3 += d_out;
+        let mut d1 = vec![0.0f32; h1_len];
+        for j in 0..h2_len {
+            g_w3[j] += d_out * h2[j];
+            let d2 = d_out * network.w3[j] * crelu_grad(h2[j]);
+            if d2 == 0.0 {
+                continue
+            }
+            g_b2[j] += d2;
+            let row = j * h1_len;
+            for i in 0..h1_len {
+                g_w2[row + i] += d2 * h1[i];
+                d1[i] += d2 * network.w2[row + i];
+            }
+        }
+        for i in 0..h1_len {
+            d1[i] *= crelu_grad(h1[i]);
+            g_b1[i] += d1[i];
+        }
+        for input in &sample.inputs {
+            let column = input * h1_len;
+            for i in 0..h1_len {
+                g_w1[column + i] += d1[i];
+            }
+        }
+    }
+    let step = learning_rate / batch.len() as f32;
+    let apply = |weights: &mut [f32], grads: &[f32]| {
+        weights.iter_mut().zip(grads).for_each(|(w, g)| *w -= step * g);
+    };
+    apply(&mut network.w1, &g_w1);
+    apply(&mut network.b1, &g_b1);
+    apply(&mut network.w2, &g_w2);
+    apply(&mut network.b2, &g_b2);
+    apply(&mut network.w3, &g_w3);
+    network.b3 -= step * g_b3;
+}
+
+/// Run the `train` subcommand with the given arguments.
+pub(crate) fn run_train_command(args: &[String]) -> Result<(), String> {
+    let Some((variant, mut rest)) = args.split_first() else {
+        return Err(TRAIN_USAGE.to_string())
+    };
+    let (ruleset, starting_board) = preset_variants().remove(variant.as_str())
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let mut games = 20u32;
+    let mut time_to_play = Duration::from_secs_f64(0.2);
+    let mut difficulty = Difficulty::Medium;
+    let mut epochs = 20u32;
+    let mut hidden = 64usize;
+    let mut learning_rate = 0.05f32;
+    let mut output: Option<PathBuf> = None;
+    while let [flag, value, tail @ ..] = rest {
+        match flag.as_str() {
+            "--games" => games = value.parse()
+                .map_err(|_| format!("Invalid number of games {value:?}."))?,
+            "--time" => time_to_play = value.parse().map(Duration::from_secs_f64)
+                .map_err(|_| format!("Invalid time {value:?}."))?,
+            "--difficulty" => difficulty = parse_difficulty(value)?,
+            "--epochs" => epochs = value.parse()
+                .map_err(|_| format!("Invalid number of epochs {value:?}."))?,
+            "--hidden" => hidden = value.parse().ok().filter(|h| (1..=u16::MAX as usize).contains(h))
+                .ok_or(format!("Invalid number of hidden units {value:?}."))?,
+            "--learning-rate" => learning_rate = value.parse()
+                .map_err(|_| format!("Invalid learning rate {value:?}."))?,
+            "--output" => output = Some(PathBuf::from(value)),
+            _ => return Err(TRAIN_USAGE.to_string())
+        }
+        rest = tail;
+    }
+    if !rest.is_empty() || games == 0 {
+        return Err(TRAIN_USAGE.to_string())
+    }
+    let output = output_path(output, networks_dir(), variant, "nn")?;
+
+    let side_len = Game::<LargeBasicBoardState>::new(ruleset, &starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?
+        .logic.board_geo.side_len;
+    let mut rng = SmallRng::from_entropy();
+    let mut network = if output.exists() {
+        let network = read_network(&output)?;
+        if network.side_len != side_len {
+            return Err(format!("{} is for a board of a different size.", output.display()))
+        }
+        println!("Continuing to train {}.", output.display());
+        network
+    } else {
+        EvalNetwork::new(variant.as_str(), side_len, hidden, HIDDEN2, &mut rng)
+    };
+    network.variant = variant.clone();
+
+    let mut samples = vec![];
+    for i in 0..games {
+        let (plays, result) = self_play_game(ruleset, &starting_board, time_to_play, difficulty)?;
+        println!(
+            "Self-play game {} of {games}: {} plays, {}.",
+            i + 1,
+            plays.len(),
+            result.map_or("abandoned".to_string(), |r| r.describe())
+        );
+        samples.extend(game_samples(ruleset, &starting_board, &plays, result)?);
+    }
+    let mut training: Vec<&Sample> = vec![];
+    let mut validation: Vec<&Sample> = vec![];
+    for (i, sample) in samples.iter().enumerate() {
+        if i % VALIDATION_EVERY == 0 {
+            validation.push(sample);
+        } else {
+            training.push(sample);
+        }
+    }
+    println!(
+        "Training on {} positions ({} inputs), validating on {}.",
+        training.len(),
+        input_count(side_len),
+        validation.len()
+    );
+    for epoch in 1..=epochs {
+        training.shuffle(&mut rng);
+        for batch in training.chunks(BATCH_SIZE) {
+            train_batch(&mut network, batch, learning_rate);
+        }
+        println!(
+            "Epoch {epoch}: training loss {:.4}, validation loss {:.4}.",
+            mean_loss(&network, &training),
+            mean_loss(&network, &validation)
+        );
+    }
+
+    write_output(&output, network.to_bytes())?;
+    println!("Wrote network to {}.", output.display());
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use crate::game_play_view::Message;
+use crate::saved_game::{SavedGame, SavedSide};
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::Game;
+use hnefatafl::pieces;
+use hnefatafl::play::Play;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io, net::TcpStream, time::Duration};
+#[cfg(not(target_arch = "wasm32"))]
+use tungstenite::client::IntoClientRequest;
+#[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::{closure::Closure, JsCast};
+
+/// The server the lobby connects to by default (a server started locally with the `serve`
+/// subcommand).
+pub(crate) const DEFAULT_SERVER_URL: &str = "ws://localhost:8787";
+
+/// A game listed in the lobby.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct LobbyGame {
+    pub(crate) id: u64,
+    pub(crate) ruleset_name: String,
+    /// The side played by the player who created the game.
+    pub(crate) host_side: SavedSide,
+    /// Whether an opponent has joined.
+    pub(crate) started: bool,
+    pub(crate) spectators: usize
+}
+
+/// Messages sent by a client to the server, each as a WebSocket text message containing JSON.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum ClientMessage {
+    /// Ask for the list of games in the lobby.
+    ListGames,
+    /// Create a game (which may already have some plays), in which we play `host_side`.
+    CreateGame {
+        game: SavedGame,
+        host_side: SavedSide
+    },
+    /// Play against the creator of the game with the given ID.
+    JoinGame {
+        id: u64
+    },
+    /// Watch the game with the given ID.
+    Watch {
+        id: u64
+    },
+    Play {
+        play: String
+    },
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    /// Leave the current game (as a player or spectator).
+    Leave
+}
+
+/// Messages sent by the server to a client.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum ServerMessage {
+    /// The games in the lobby, sent when asked for and whenever the list changes.
+    Games {
+        games: Vec<LobbyGame>
+    },
+    /// A game we asked to create is waiting for an opponent.
+    Created {
+        id: u64
+    },
+    /// We are now playing or watching a game. `game` includes any plays already made, and `side`
+    /// is the side we play, or `None` if we are watching.
+    Started {
+        id: u64,
+        game: SavedGame,
+        side: Option<SavedSide>
+    },
+    /// A player has made a play, which the server has checked is legal.
+    Played {
+        play: String
+    },
+    Resigned {
+        side: SavedSide
+    },
+    DrawOffered {
+        side: SavedSide
+    },
+    DrawAccepted,
+    DrawDeclined,
+    /// The player of the given side has left the game.
+    Left {
+        side: SavedSide
+    },
+    Error {
+        reason: String
+    }
+}
+
+/// A connection to the lobby server.
+pub(crate) struct OnlineClient {
+    /// Boxed, as tungstenite's buffers make the socket large.
+    #[cfg(not(target_arch = "wasm32"))]
+    socket: Box<tungstenite::WebSocket<TcpStream>>,
+    #[cfg(target_arch = "wasm32")]
+    socket: web_sys::WebSocket,
+    /// Text of messages (or an error) received by the socket's callbacks and not yet polled.
+    #[cfg(target_arch = "wasm32")]
+    inbox: Rc<RefCell<VecDeque<Result<String, String>>>>,
+    /// Keeps the socket's callbacks alive for as long as the client.
+    #[cfg(target_arch = "wasm32")]
+    _callbacks: (Closure<dyn FnMut(web_sys::MessageEvent)>, Closure<dyn FnMut(web_sys::CloseEvent)>),
+    /// Messages waiting to be sent once the connection is open.
+    outbox: VecDeque<String>
+}
+
+impl OnlineClient {
+
+    /// Connect to the server at `url` (eg, `ws://localhost:8787`).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn connect(url: &str) -> Result<Self, String> {
+        let request = url.into_client_request().map_err(|e| format!("Invalid server URL {url:?}: {e}"))?;
+        let host = request.uri().host().unwrap_or("localhost").to_string();
+        let port = request.uri().port_u16().unwrap_or(80);
+        let err = |e: &dyn std::fmt::Display| format!("Could not connect to {url}: {e}");
+        let stream = TcpStream::connect((host.as_str(), port)).map_err(|e| err(&e))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10))).map_err(|e| err(&e))?;
+        let (socket, _) = tungstenite::client(request, stream).map_err(|e| err(&e))?;
+        // Polled from the UI thread, so must never block.
+        socket.get_ref().set_nonblocking(true).map_err(|e| err(&e))?;
+        Ok(Self { socket: Box::new(socket), outbox: VecDeque::new() })
+    }
+
+    /// Connect to the server at `url` (eg, `ws://localhost:8787`). The connection opens in the
+    /// background; messages sent before then are queued.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn connect(url: &str) -> Result<Self, String> {
+        let socket = web_sys::WebSocket::new(url).map_err(|e| format!("Could not connect to {url}: {e:?}"))?;
+        let inbox = Rc::new(RefCell::new(VecDeque::new()));
+        let on_message = {
+            let inbox = inbox.clone();
+            Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |e: web_sys::MessageEvent| {
+                if let Some(text) = e.data().as_string() {
+                    inbox.borrow_mut().push_back(Ok(text));
+                }
+            })
+        };
+        let on_close = {
+            let inbox = inbox.clone();
+            Closure::<dyn FnMut(web_sys::CloseEvent)>::new(move |_: web_sys::CloseEvent| {
+                inbox.borrow_mut().push_back(Err("Connection to server closed.".to_string()));
+            })
+        };
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        Ok(Self { socket, inbox, _callbacks: (on_message, on_close), outbox: VecDeque::new() })
+    }
+
+    pub(crate) fn send(&mut self, msg: &ClientMessage) {
+        self.outbox.push_back(serde_json::to_string(msg).expect("Message should be serializable."));
+    }
+
+    /// Send any queued messages that can be sent without blocking. Anything that cannot be sent
+    /// yet is kept (in `outbox`, or in the socket's write buffer) and sent by a later flush.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn flush(&mut self) -> Result<(), String> {
+        while let Some(text) = self.outbox.front() {
+            match self.socket.write(tungstenite::Message::Text(text.clone())) {
+                Ok(()) => {},
+                // The message has been buffered, but the socket is full, so the rest must wait.
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.outbox.pop_front();
+                    break
+                },
+                // The message could not be buffered, so it stays queued.
+                Err(tungstenite::Error::WriteBufferFull(_)) => break,
+                Err(e) => return Err(format!("Connection error: {e}"))
+            }
+            self.outbox.pop_front();
+        }
+        match self.socket.flush() {
+            Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            other => other.map_err(|e| format!("Connection error: {e}"))
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn flush(&mut self) -> Result<(), String> {
+        if self.socket.ready_state() != web_sys::WebSocket::OPEN {
+            return Ok(())
+        }
+        while let Some(text) = self.outbox.pop_front() {
+            self.socket.send_with_str(&text).map_err(|e| format!("Connection error: {e:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// The text of the next message received, if any, without blocking.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn next_text(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.socket.read() {
+                Ok(tungstenite::Message::Text(text)) => return Ok(Some(text)),
+                Ok(tungstenite::Message::Close(_)) => return Err("Connection to server closed.".to_string()),
+                Ok(_) => {},
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(format!("Connection error: {e}"))
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn next_text(&mut self) -> Result<Option<String>, String> {
+        self.inbox.borrow_mut().pop_front().transpose()
+    }
+
+    /// Send any queued messages, and return the next message received (if any), without blocking.
+    pub(crate) fn poll(&mut self) -> Result<Option<ServerMessage>, String> {
+        self.flush()?;
+        match self.next_text()? {
+            Some(text) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| format!("Invalid message from server: {e}")),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for OnlineClient {
+    fn drop(&mut self) {
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
+
+/// Plays on behalf of the opponent in an online game, relaying messages between the game and the
+/// server. Unlike the AI, this runs on the UI thread (the browser's WebSocket cannot be moved to
+/// another thread), so [`OnlinePlayer::pump`] must be called regularly.
+pub(crate) struct OnlinePlayer<T: BoardState> {
+    client: OnlineClient,
+    /// Our copy of the game, used to validate the plays received.
+    game: Game<T>,
+    /// The side we play, or `None` if we are watching.
+    side: Option<pieces::Side>,
+    to_game: Sender<Message<T>>,
+    from_game: Receiver<Message<T>>,
+    /// Set once the connection has failed, after which nothing more is sent or received.
+    failed: bool
+}
+
+impl<T: BoardState> OnlinePlayer<T> {
+
+    pub(crate) fn new(
+        client: OnlineClient,
+        game: Game<T>,
+        side: Option<pieces::Side>,
+        to_game: Sender<Message<T>>,
+        from_game: Receiver<Message<T>>
+    ) -> Self {
+        Self { client, game, side, to_game, from_game, failed: false }
+    }
+
+    fn info(&self, msg: String) {
+        let _ = self.to_game.send(Message::Info(msg));
+    }
+
+    /// Send messages from the game to the server and pass on messages from the server to the game.
+    pub(crate) fn pump(&mut self) {
+        if self.failed {
+            return
+        }
+        while let Ok(msg) = self.from_game.try_recv() {
+            let to_send = match msg {
+                Message::Request(_, Some(play)) => {
+                    // Our own play, which has already been checked by the game.
+                    let _ = self.game.do_play(play);
+                    ClientMessage::Play { play: play.to_string() }
+                },
+                Message::Resign => ClientMessage::Resign,
+                Message::OfferDraw => ClientMessage::OfferDraw,
+                Message::AcceptDraw => ClientMessage::AcceptDraw,
+                Message::DeclineDraw => ClientMessage::DeclineDraw,
+                _ => continue
+            };
+            self.client.send(&to_send);
+        }
+        loop {
+            match self.client.poll() {
+                Ok(Some(msg)) => self.handle_server_message(msg),
+                Ok(None) => break,
+                Err(e) => {
+                    self.info(e);
+                    self.failed = true;
+                    break
+                }
+            }
+        }
+    }
+
+    fn handle_server_message(&mut self, msg: ServerMessage) {
+        let to_game = match msg {
+            ServerMessage::Played { play } => {
+                let state = self.game.state;
+                let checked = play.parse::<Play>()
+                    .map_err(|e| format!("{e:?}"))
+                    .and_then(|p| self.game.logic.validate_play(p, &state).map_err(|e| format!("{e:?}")));
+                match checked {
+                    Ok(vp) => {
+                        let _ = self.game.do_play(vp.play);
+                        Message::Response(vp, state, vec![])
+                    },
+                    Err(e) => Message::Info(format!("Received invalid play {play} ({e}).")),
+                }
+            },
+            // Players are told about the opponent resigning; spectators are just told what happened.
+            ServerMessage::Resigned { .. } if self.side.is_some() => Message::Resign,
+            ServerMessage::Resigned { side } =>
+                Message::Info(format!("{:?} resigned.", pieces::Side::from(side))),
+            ServerMessage::DrawOffered { .. } if self.side.is_some() => Message::OfferDraw,
+            ServerMessage::DrawOffered { side } =>
+                Message::Info(format!("{:?} offered a draw.", pieces::Side::from(side))),
+            ServerMessage::DrawAccepted if self.side.is_some() => Message::AcceptDraw,
+            ServerMessage::DrawAccepted => Message::Info("Draw agreed.".to_string()),
+            ServerMessage::DrawDeclined if self.side.is_some() => Message::DeclineDraw,
+            ServerMessage::DrawDeclined => Message::Info("Draw declined.".to_string()),
+            ServerMessage::Left { side } =>
+                Message::Info(format!("{:?} left the game.", pieces::Side::from(side))),
+            ServerMessage::Error { reason } => Message::Info(format!("Server error: {reason}")),
+            ServerMessage::Games { .. } | ServerMessage::Created { .. } | ServerMessage::Started { .. } => return
+        };
+        let _ = self.to_game.send(to_game);
+    }
+}
+
+impl<T: BoardState> Drop for OnlinePlayer<T> {
+    fn drop(&mut self) {
+        // Best effort, so that the opponent is told straight away rather than when the connection
+        // times out.
+        self.client.send(&ClientMessage::Leave);
+        let _ = self.client.flush();
+    }
+}
@@ -0,0 +1,148 @@
+use crate::archive::{ArchivedGame, ArchivedResult};
+use crate::saved_game::SavedSide;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::Game;
+use hnefatafl::pieces::PieceType::King;
+use hnefatafl::pieces::Side::Attacker;
+use hnefatafl::play::{Play, ValidPlay};
+use hnefatafl::rules::Ruleset;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Key under which the opening books built from the archive are saved in eframe's storage.
+pub(crate) const OPENING_BOOKS_KEY: &str = "opening_books";
+/// Only the first this many plays of each game are added to a book.
+pub(crate) const MAX_BOOK_PLIES: usize = 16;
+/// Weight added to a play each time it is made by the side that went on to win the game.
+const WIN_WEIGHT: u32 = 3;
+/// Weight added to a play each time it is made in a drawn (or unfinished) game.
+const DRAW_WEIGHT: u32 = 2;
+/// Weight added to a play each time it is made by the side that went on to lose the game.
+const LOSS_WEIGHT: u32 = 1;
+
+/// Opening books by variant name.
+pub(crate) type OpeningBooks = BTreeMap<String, OpeningBook>;
+
+/// A play recommended by an opening book.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct BookMove {
+    /// The play, in the format accepted by `Play::from_str` (eg, `d1-d4`).
+    pub(crate) play: String,
+    /// How likely the play is to be chosen, relative to the other plays in the same position.
+    pub(crate) weight: u32
+}
+
+/// Candidate plays for positions reached early in games of one variant.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub(crate) struct OpeningBook {
+    /// The name of the variant the book is for.
+    pub(crate) variant: String,
+    /// Candidate plays, keyed by position (see [`position_key`]).
+    pub(crate) positions: BTreeMap<String, Vec<BookMove>>
+}
+
+/// A string identifying the position in `state`: the piece on each tile (or `.` if empty),
+/// followed by the side to play.
+pub(crate) fn position_key<T: BoardState>(logic: &GameLogic<T>, state: &GameState<T>) -> String {
+    let mut key: String = logic.board_geo.iter_tiles()
+        .map(|t| match state.board.get_piece(t) {
+            None => '.',
+            Some(p) if p.piece_type == King => 'k',
+            Some(p) if p.side == Attacker => 't',
+            Some(_) => 'd'
+        })
+        .collect();
+    key.push(if state.side_to_play == Attacker { 'a' } else { 'd' });
+    key
+}
+
+impl OpeningBook {
+
+    pub(crate) fn new(variant: impl Into<String>) -> Self {
+        Self { variant: variant.into(), positions: BTreeMap::new() }
+    }
+
+    /// Add `weight` to `play` in the position identified by `key`.
+    fn add_play(&mut self, key: String, play: String, weight: u32) {
+        let moves = self.positions.entry(key).or_default();
+        if let Some(m) = moves.iter_mut().find(|m| m.play == play) {
+            m.weight += weight;
+        } else {
+            moves.push(BookMove { play, weight });
+        }
+    }
+
+    /// Add the opening plays of a game to the book, weighting each play by how the game turned out
+    /// for the side that made it. `result` is `None` if the game did not finish.
+    pub(crate) fn add_game(
+        &mut self,
+        ruleset: Ruleset,
+        starting_board: &str,
+        plays: &[Play],
+        result: Option<ArchivedResult>
+    ) -> Result<(), String> {
+        let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        for play in plays.iter().take(MAX_BOOK_PLIES) {
+            let key = position_key(&game.logic, &game.state);
+            let weight = match result {
+                Some(ArchivedResult::Win(winner)) if winner == SavedSide::from(game.state.side_to_play) =>
+                    WIN_WEIGHT,
+                Some(ArchivedResult::Win(_)) => LOSS_WEIGHT,
+                Some(ArchivedResult::Draw) | None => DRAW_WEIGHT
+            };
+            game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+            self.add_play(key, play.to_string(), weight);
+        }
+        Ok(())
+    }
+
+    /// Add the candidate plays of `other` to the book, adding their weights together.
+    pub(crate) fn merge(&mut self, other: &OpeningBook) {
+        for (key, moves) in &other.positions {
+            for m in moves {
+                self.add_play(key.clone(), m.play.clone(), m.weight);
+            }
+        }
+    }
+
+    /// Choose a play for `state` at random from the book's candidates, weighted by their weights.
+    /// Returns `None` if the position is not in the book (or none of its plays are legal).
+    pub(crate) fn choose<T: BoardState>(
+        &self,
+        logic: &GameLogic<T>,
+        state: &GameState<T>,
+        rng: &mut impl Rng
+    ) -> Option<ValidPlay> {
+        let moves = self.positions.get(&position_key(logic, state))?;
+        let candidates: Vec<(ValidPlay, u32)> = moves.iter()
+            .filter_map(|m| {
+                let play: Play = m.play.parse().ok()?;
+                logic.validate_play(play, state).ok().map(|vp| (vp, m.weight))
+            })
+            .collect();
+        let dist = WeightedIndex::new(candidates.iter().map(|(_, w)| *w)).ok()?;
+        Some(candidates[dist.sample(rng)].0)
+    }
+}
+
+/// Build opening books for every variant played in `archive`. Games that cannot be replayed are
+/// skipped.
+pub(crate) fn books_from_archive(archive: &[ArchivedGame]) -> OpeningBooks {
+    let mut books = OpeningBooks::new();
+    for g in archive {
+        let Ok(setup) = g.game.setup() else { continue };
+        let Ok(plays) = g.game.plays() else { continue };
+        let book = books.entry(setup.ruleset_name.clone())
+            .or_insert_with(|| OpeningBook::new(setup.ruleset_name.clone()));
+        if let Err(e) = book.add_game(setup.ruleset, &setup.starting_board, &plays, Some(g.result)) {
+            log::warn!("Skipping archived game in opening book: {e}");
+        }
+    }
+    books
+}
@@ -0,0 +1,189 @@
+use crate::custom_rules::preset_variants;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::play::ValidPlay;
+use hnefatafl::rules::Ruleset;
+use std::time::Instant;
+
+/// Reference leaf counts for the preset variants, checked by `perft check`.
+const PERFT_REFERENCE: &str = include_str!("perft_reference.txt");
+
+pub(crate) const PERFT_USAGE: &str = "\
+Usage: hnefatafl-egui perft <variant> <depth> [--divide] [--validate]
+       hnefatafl-egui perft check
+       hnefatafl-egui perft record <depth>
+
+Count the positions reached after exactly <depth> plays from the starting position of a preset
+variant, by generating every play with the same move generation the AI uses. Games that end before
+then contribute no positions. With --divide, the count is broken down by first play; with
+--validate, every generated play is also checked with the rules' play validation.
+
+`perft check` compares the counts for every preset variant with the reference table checked into
+the repository (src/perft_reference.txt), failing if any differ. `perft record` prints the counts
+for every preset variant up to the given depth, in the format of the reference table.";
+
+/// Count the leaf nodes of the play tree from `state` to `depth` plays. Every play is made (rather
+/// than counting the plays at the last level), so that making plays is exercised too. If
+/// `validate` is set, every generated play is checked against `GameLogic::validate_play`.
+fn perft<T: BoardState>(logic: &GameLogic<T>, state: &GameState<T>, depth: u8, validate: bool) -> Result<u64, String> {
+    if depth == 0 {
+        return Ok(1)
+    }
+    if state.status != Ongoing {
+        return Ok(0)
+    }
+    let mut nodes = 0;
+    for vp in plays(logic, state, validate)? {
+        nodes += perft(logic, &logic.do_valid_play(vp, *state).new_state, depth - 1, validate)?;
+    }
+    Ok(nodes)
+}
+
+/// Every play available to the side to play in `state`, optionally checking each with
+/// `GameLogic::validate_play`.
+fn plays<T: BoardState>(logic: &GameLogic<T>, state: &GameState<T>, validate: bool) -> Result<Vec<ValidPlay>, String> {
+    let mut plays = vec![];
+    for t in state.board.occupied_by_side(state.side_to_play) {
+        let tile_plays = logic.iter_plays(t, state)
+            .map_err(|e| format!("Could not iterate plays from {t:?}: {e:?}"))?;
+        for vp in tile_plays {
+            if validate {
+                logic.validate_play(vp.play, state)
+                    .map_err(|e| format!("Generated play {vp} is invalid: {e:?}"))?;
+            }
+            plays.push(vp);
+        }
+    }
+    Ok(plays)
+}
+
+fn starting_game(ruleset: Ruleset, starting_board: &str) -> Result<Game<LargeBasicBoardState>, String> {
+    Game::new(ruleset, starting_board).map_err(|e| format!("Could not create game: {e:?}"))
+}
+
+/// Parse the reference table into `(variant, depth, nodes)` entries.
+fn reference_values() -> Result<Vec<(String, u8, u64)>, String> {
+    PERFT_REFERENCE.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| match l.split_whitespace().collect::<Vec<_>>()[..] {
+            [variant, depth, nodes] => Ok((
+                variant.to_string(),
+                depth.parse().map_err(|_| format!("Invalid depth in reference line {l:?}."))?,
+                nodes.parse().map_err(|_| format!("Invalid node count in reference line {l:?}."))?
+            )),
+            _ => Err(format!("Invalid reference line {l:?}."))
+        })
+        .collect()
+}
+
+/// Print the number of leaf nodes at `depth`, with the time taken and speed.
+fn run_perft(variant: &str, depth: u8, divide: bool, validate: bool) -> Result<(), String> {
+    let (ruleset, starting_board) = preset_variants().remove(variant)
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let game = starting_game(ruleset, &starting_board)?;
+    let start = Instant::now();
+    let nodes = if divide && depth > 0 {
+        let mut divided: Vec<(String, u64)> = vec![];
+        for vp in plays(&game.logic, &game.state, validate)? {
+            let state = game.logic.do_valid_play(vp, game.state).new_state;
+            divided.push((vp.to_string(), perft(&game.logic, &state, depth - 1, validate)?));
+        }
+        divided.sort();
+        for (play, n) in &divided {
+            println!("{play}: {n}");
+        }
+        println!();
+        divided.iter().map(|(_, n)| n).sum()
+    } else {
+        perft(&game.logic, &game.state, depth, validate)?
+    };
+    let secs = start.elapsed().as_secs_f64();
+    println!("{variant} depth {depth}: {nodes} nodes in {secs:.3}s ({:.0} nodes/s).", nodes as f64 / secs.max(1e-9));
+    Ok(())
+}
+
+/// Compare the counts for every entry in the reference table.
+fn check_reference() -> Result<(), String> {
+    let reference = reference_values()?;
+    if reference.is_empty() {
+        return Err("The reference table is empty; see `perft record`.".to_string())
+    }
+    let variants = preset_variants();
+    let mut failures = 0;
+    for (variant, depth, expected) in &reference {
+        let (ruleset, starting_board) = variants.get(variant)
+            .ok_or(format!("Unknown variant {variant:?} in reference table."))?;
+        let game = starting_game(*ruleset, starting_board)?;
+        let start = Instant::now();
+        let nodes = perft(&game.logic, &game.state, *depth, true)?;
+        let secs = start.elapsed().as_secs_f64();
+        if nodes == *expected {
+            println!("{variant} depth {depth}: {nodes} nodes, ok ({secs:.3}s).");
+        } else {
+            println!("{variant} depth {depth}: {nodes} nodes, expected {expected}.");
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{failures} of {} perft counts differ from the reference.", reference.len()))
+    }
+    Ok(())
+}
+
+/// Print the counts for every preset variant up to `max_depth`, as reference table lines.
+fn record_reference(max_depth: u8) -> Result<(), String> {
+    let mut variants: Vec<(String, (Ruleset, String))> = preset_variants().into_iter().collect();
+    variants.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (variant, (ruleset, starting_board)) in variants {
+        let game = starting_game(ruleset, &starting_board)?;
+        for depth in 1..=max_depth {
+            println!("{variant} {depth} {}", perft(&game.logic, &game.state, depth, true)?);
+        }
+    }
+    Ok(())
+}
+
+/// Run the `perft` subcommand with the given arguments.
+pub(crate) fn run_perft_command(args: &[String]) -> Result<(), String> {
+    let parse_depth = |d: &String| d.parse::<u8>().map_err(|_| format!("Invalid depth {d:?}."));
+    match args {
+        [cmd] if cmd == "check" => check_reference(),
+        [cmd, depth] if cmd == "record" => record_reference(parse_depth(depth)?),
+        [variant, depth, flags @ ..] => {
+            let mut divide = false;
+            let mut validate = false;
+            for flag in flags {
+                match flag.as_str() {
+                    "--divide" => divide = true,
+                    "--validate" => validate = true,
+                    _ => return Err(PERFT_USAGE.to_string())
+                }
+            }
+            run_perft(variant, parse_depth(depth)?, divide, validate)
+        },
+        _ => Err(PERFT_USAGE.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_match_reference() {
+        let variants = preset_variants();
+        let reference = reference_values().unwrap();
+        assert!(!reference.is_empty());
+        for (variant, depth, expected) in reference {
+            let (ruleset, starting_board) = &variants[&variant];
+            let game = starting_game(*ruleset, starting_board).unwrap();
+            let nodes = perft(&game.logic, &game.state, depth, true).unwrap();
+            assert_eq!(nodes, expected, "{variant} depth {depth}");
+        }
+    }
+}
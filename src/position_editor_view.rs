@@ -0,0 +1,414 @@
+use crate::board::{FIGURES, TILE_COLORS};
+use crate::custom_rules::{all_variants, base_variant, CustomVariant};
+use crate::ai::{AiEngine, Difficulty};
+use crate::game_play_view::GameSetup;
+use crate::game_setup_view::{difficulty_combo_box, engine_ui};
+use eframe::epaint::Color32;
+use egui::{Align2, FontId, RichText, Vec2};
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::board::geometry::BoardGeometry;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Over;
+use hnefatafl::pieces;
+use hnefatafl::pieces::PieceType::{King, Soldier};
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::pieces::{Piece, KING};
+use hnefatafl::rules::Ruleset;
+use hnefatafl::tiles::Tile;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Board sizes that can be edited. Limited by the size of `LargeBasicBoardState`.
+const BOARD_SIZES: [u8; 3] = [7, 9, 11];
+
+pub(crate) enum PositionEditorAction {
+    StartGame(GameSetup),
+    /// Analyse the position with the AI instead of playing it.
+    Analyse(GameSetup),
+    Back
+}
+
+/// The piece (or lack of piece) that will be placed on a tile when it is clicked.
+#[derive(Copy, Clone, PartialEq)]
+enum Brush {
+    Attacker,
+    Defender,
+    King,
+    Erase
+}
+
+impl Brush {
+    fn piece(&self) -> Option<Piece> {
+        match self {
+            Brush::Attacker => Some(Piece::new(Soldier, Attacker)),
+            Brush::Defender => Some(Piece::new(Soldier, Defender)),
+            Brush::King => Some(KING),
+            Brush::Erase => None
+        }
+    }
+}
+
+/// Convert a grid of pieces to the board string format accepted by `Game::new`, eg,
+/// `3t3/3t3/3T3/ttTKTtt/3T3/3t3/3t3`.
+pub(crate) fn grid_to_board_string(grid: &[Vec<Option<Piece>>]) -> String {
+    let rows: Vec<String> = grid.iter().map(|row| {
+        let mut s = String::new();
+        let mut empty = 0;
+        for tile in row {
+            if let Some(piece) = tile {
+                if empty > 0 {
+                    s.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                s.push(match piece {
+                    Piece { piece_type: King, .. } => 'K',
+                    Piece { side: Defender, .. } => 'T',
+                    Piece { side: Attacker, .. } => 't'
+                });
+            } else {
+                empty += 1;
+            }
+        }
+        if empty > 0 {
+            s.push_str(&empty.to_string());
+        }
+        s
+    }).collect();
+    rows.join("/")
+}
+
+/// Parse a board string (see [`grid_to_board_string`]) into a grid of pieces.
+pub(crate) fn board_string_to_grid(board: &str) -> Result<Vec<Vec<Option<Piece>>>, String> {
+    let mut grid = vec![];
+    for row_str in board.trim().split('/') {
+        let mut row = vec![];
+        let mut empty = 0usize;
+        for c in row_str.chars() {
+            if let Some(d) = c.to_digit(10) {
+                empty = empty * 10 + d as usize;
+                continue
+            }
+            row.extend(std::iter::repeat_n(None, empty));
+            empty = 0;
+            row.push(Some(match c {
+                't' => Piece::new(Soldier, Attacker),
+                'T' => Piece::new(Soldier, Defender),
+                'K' => KING,
+                _ => return Err(format!("Unexpected character {c:?} in board string."))
+            }));
+        }
+        row.extend(std::iter::repeat_n(None, empty));
+        grid.push(row);
+    }
+    let side_len = grid.len();
+    if grid.iter().any(|row| row.len() != side_len) {
+        return Err("Board is not square.".to_string())
+    }
+    if !BOARD_SIZES.contains(&(side_len as u8)) {
+        return Err(format!("Unsupported board size {side_len}."))
+    }
+    Ok(grid)
+}
+
+pub(crate) struct PositionEditorView {
+    variants: HashMap<String, (Ruleset, String)>,
+    custom_variants: Vec<CustomVariant>,
+    /// The variant whose rules will be used for the game.
+    selected_variant: String,
+    grid: Vec<Vec<Option<Piece>>>,
+    brush: Brush,
+    side_to_play: pieces::Side,
+    /// The side the AI will play as, or `None` for a game between two humans.
+    ai_side: Option<pieces::Side>,
+    ai_time: u8,
+    ai_difficulty: Difficulty,
+    ai_engine: AiEngine,
+    /// Board string shown in (and importable from) the text box.
+    board_string: String,
+    /// Result of the last validation, import or export.
+    message: Option<String>
+}
+
+impl PositionEditorView {
+
+    pub(crate) fn new(custom_variants: Vec<CustomVariant>) -> Self {
+        let variants = all_variants(&custom_variants);
+        let mut variant_keys: Vec<String> = variants.keys().cloned().collect();
+        variant_keys.sort();
+        let selected_variant = variant_keys.first().expect("No variants provided.").clone();
+        let side_len = board_string_to_grid(&variants[&selected_variant].1)
+            .map_or(BOARD_SIZES[0], |g| g.len() as u8);
+        Self {
+            variants,
+            custom_variants,
+            selected_variant,
+            grid: Self::empty_grid(side_len),
+            brush: Brush::Attacker,
+            side_to_play: Attacker,
+            ai_side: None,
+            ai_time: 5,
+            ai_difficulty: Difficulty::default(),
+            ai_engine: AiEngine::default(),
+            board_string: String::new(),
+            message: None
+        }
+    }
+
+    fn empty_grid(side_len: u8) -> Vec<Vec<Option<Piece>>> {
+        vec![vec![None; side_len as usize]; side_len as usize]
+    }
+
+    fn side_len(&self) -> u8 {
+        self.grid.len() as u8
+    }
+
+    /// Place the current brush's piece on the given tile. Placing a piece on a tile that already
+    /// contains the same piece removes it. There can only be one king, so placing a king removes
+    /// any existing one.
+    fn paint(&mut self, row: usize, col: usize) {
+        let piece = self.brush.piece();
+        if piece.is_some() && self.grid[row][col] == piece {
+            self.grid[row][col] = None;
+            return
+        }
+        if piece == Some(KING) {
+            for tile in self.grid.iter_mut().flatten() {
+                if *tile == Some(KING) {
+                    *tile = None;
+                }
+            }
+        }
+        self.grid[row][col] = piece;
+    }
+
+    /// The ruleset to use for the game, with the starting side set to the chosen side to play.
+    fn ruleset(&self) -> Ruleset {
+        let mut ruleset = self.variants[&self.selected_variant].0;
+        ruleset.starting_side = self.side_to_play;
+        ruleset
+    }
+
+    /// Check that the position can be played, returning the board string if so.
+    fn validate(&self) -> Result<String, String> {
+        let pieces: Vec<Piece> = self.grid.iter().flatten().filter_map(|p| *p).collect();
+        let n_kings = pieces.iter().filter(|p| **p == KING).count();
+        if n_kings != 1 {
+            return Err(format!("There must be exactly one king (found {n_kings})."))
+        }
+        if !pieces.iter().any(|p| p.side == Attacker) {
+            return Err("There must be at least one attacker.".to_string())
+        }
+        let board = grid_to_board_string(&self.grid);
+        let game = Game::<LargeBasicBoardState>::new(self.ruleset(), &board)
+            .map_err(|e| format!("Invalid position: {e:?}"))?;
+        if let Over(outcome) = game.state.status {
+            return Err(format!("Game is already over ({outcome:?})."))
+        }
+        Ok(board)
+    }
+
+    fn draw_grid(&mut self, ui: &mut egui::Ui, board_side_px: f32) {
+        let side_len = self.side_len() as usize;
+        let tile_len_px = (board_side_px - side_len as f32) / side_len as f32;
+        let origin = ui.max_rect().min;
+        let mut clicked: Option<(usize, usize)> = None;
+        let special_tiles = BoardGeometry::new(self.side_len()).special_tiles;
+        for row in 0..side_len {
+            for col in 0..side_len {
+                let top_left = origin + Vec2::new(
+                    (tile_len_px + 1.0) * col as f32,
+                    (tile_len_px + 1.0) * row as f32
+                );
+                let rect = egui::Rect::from_min_size(top_left, Vec2::splat(tile_len_px));
+                let response = ui.allocate_rect(rect, egui::Sense::click());
+                if response.clicked() {
+                    clicked = Some((row, col));
+                }
+                let tile = Tile::new(row as u8, col as u8);
+                let color = if special_tiles.throne == tile {
+                    TILE_COLORS.throne
+                } else if special_tiles.corners.contains(tile) {
+                    TILE_COLORS.corner
+                } else {
+                    TILE_COLORS.plain
+                };
+                let painter = ui.painter();
+                painter.rect_filled(rect, 0.0, color);
+                if let Some(piece) = self.grid[row][col] {
+                    let fig = match piece {
+                        Piece { piece_type: King, .. } => FIGURES.king,
+                        Piece { side: Defender, .. } => FIGURES.white_soldier,
+                        Piece { side: Attacker, .. } => FIGURES.black_soldier
+                    };
+                    painter.text(
+                        rect.center(),
+                        Align2::CENTER_CENTER,
+                        fig,
+                        FontId::proportional(tile_len_px * 0.9),
+                        Color32::BLACK
+                    );
+                }
+            }
+        }
+        if let Some((row, col)) = clicked {
+            self.paint(row, col);
+            self.message = None;
+        }
+    }
+
+    /// Set up a game from the position being edited, if it is valid.
+    fn game_setup(&self) -> Result<GameSetup, String> {
+        let starting_board = self.validate()?;
+        Ok(GameSetup {
+            ruleset: self.ruleset(),
+            base_variant: base_variant(&self.custom_variants, &self.selected_variant),
+            ruleset_name: format!("Custom position ({} rules)", self.selected_variant),
+            starting_board,
+            ai_side: self.ai_side,
+            ai_time: Duration::from_secs(self.ai_time as u64),
+            ai_difficulty: self.ai_difficulty,
+            ai_engine: self.ai_engine,
+            opening_book: None,
+            tablebase: None,
+            network: None
+        })
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<PositionEditorAction> {
+        let mut action: Option<PositionEditorAction> = None;
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.label(RichText::new("Position editor").heading());
+        });
+        egui::SidePanel::right("editor_controls").show(ctx, |ui| {
+            egui::Grid::new("editor_grid").show(ui, |ui| {
+                ui.label("Rules:");
+                egui::ComboBox::from_id_salt("editor_variant")
+                    .selected_text(&self.selected_variant)
+                    .show_ui(ui, |combo_box| {
+                        for k in self.variants.keys() {
+                            combo_box.selectable_value(&mut self.selected_variant, k.clone(), k.as_str());
+                        }
+                    });
+                ui.end_row();
+                ui.label("Board size:");
+                let mut side_len = self.side_len();
+                egui::ComboBox::from_id_salt("editor_size")
+                    .selected_text(format!("{side_len}x{side_len}"))
+                    .show_ui(ui, |combo_box| {
+                        for n in BOARD_SIZES {
+                            combo_box.selectable_value(&mut side_len, n, format!("{n}x{n}"));
+                        }
+                    });
+                if side_len != self.side_len() {
+                    self.grid = Self::empty_grid(side_len);
+                }
+                ui.end_row();
+                ui.label("Place:");
+                ui.vertical(|ui| {
+                    ui.radio_value(&mut self.brush, Brush::Attacker, "Attacker");
+                    ui.radio_value(&mut self.brush, Brush::Defender, "Defender");
+                    ui.radio_value(&mut self.brush, Brush::King, "King");
+                    ui.radio_value(&mut self.brush, Brush::Erase, "Erase");
+                });
+                ui.end_row();
+                ui.label("Side to play:");
+                egui::ComboBox::from_id_salt("editor_side_to_play")
+                    .selected_text(format!("{:?}", self.side_to_play))
+                    .show_ui(ui, |combo_box| {
+                        combo_box.selectable_value(&mut self.side_to_play, Attacker, "Attacker");
+                        combo_box.selectable_value(&mut self.side_to_play, Defender, "Defender");
+                    });
+                ui.end_row();
+                ui.label("AI side:");
+                egui::ComboBox::from_id_salt("editor_ai_side")
+                    .selected_text(match self.ai_side {
+                        Some(side) => format!("{side:?}"),
+                        None => "None (two players)".to_string()
+                    })
+                    .show_ui(ui, |combo_box| {
+                        combo_box.selectable_value(&mut self.ai_side, None, "None (two players)");
+                        combo_box.selectable_value(&mut self.ai_side, Some(Attacker), "Attacker");
+                        combo_box.selectable_value(&mut self.ai_side, Some(Defender), "Defender");
+                    });
+                ui.end_row();
+                ui.label("AI time per move:");
+                ui.add_enabled(self.ai_side.is_some(), egui::Slider::new(&mut self.ai_time, 1..=60));
+                ui.end_row();
+                ui.label("AI engine:");
+                ui.add_enabled_ui(self.ai_side.is_some(), |ui| {
+                    engine_ui(ui, "editor_ai_engine", &mut self.ai_engine);
+                });
+                ui.end_row();
+                ui.label("AI difficulty:");
+                ui.add_enabled_ui(self.ai_side.is_some() && self.ai_engine == AiEngine::AlphaBeta, |ui| {
+                    difficulty_combo_box(ui, "editor_ai_difficulty", &mut self.ai_difficulty);
+                });
+                ui.end_row();
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    self.grid = Self::empty_grid(self.side_len());
+                    self.message = None;
+                }
+                if ui.button("Load variant start").clicked() {
+                    match board_string_to_grid(&self.variants[&self.selected_variant].1) {
+                        Ok(grid) => self.grid = grid,
+                        Err(e) => self.message = Some(e)
+                    }
+                }
+            });
+            ui.label("Board string:");
+            ui.add(egui::TextEdit::multiline(&mut self.board_string).desired_rows(2));
+            ui.horizontal(|ui| {
+                if ui.button("Export").clicked() {
+                    self.board_string = grid_to_board_string(&self.grid);
+                    ui.ctx().copy_text(self.board_string.clone());
+                    self.message = Some("Board string copied to clipboard.".to_string());
+                }
+                if ui.button("Import").clicked() {
+                    match board_string_to_grid(&self.board_string) {
+                        Ok(grid) => {
+                            self.grid = grid;
+                            self.message = None;
+                        },
+                        Err(e) => self.message = Some(e)
+                    }
+                }
+                if ui.button("Validate").clicked() {
+                    self.message = Some(match self.validate() {
+                        Ok(_) => "Position is valid.".to_string(),
+                        Err(e) => e
+                    });
+                }
+            });
+            if let Some(msg) = &self.message {
+                ui.label(msg);
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Start game").clicked() {
+                    match self.game_setup() {
+                        Ok(setup) => action = Some(PositionEditorAction::StartGame(setup)),
+                        Err(e) => self.message = Some(e)
+                    }
+                }
+                if ui.button("Analyse").clicked() {
+                    match self.game_setup() {
+                        Ok(setup) => action = Some(PositionEditorAction::Analyse(setup)),
+                        Err(e) => self.message = Some(e)
+                    }
+                }
+                if ui.button("Back").clicked() {
+                    action = Some(PositionEditorAction::Back);
+                }
+            });
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let available = ui.available_size();
+            self.draw_grid(ui, available.x.min(available.y));
+        });
+        action
+    }
+
+}
@@ -0,0 +1,347 @@
+use crate::ai::all_plays;
+use crate::byte_reader::fnv1a;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::GameOutcome::Win;
+use hnefatafl::game::GameStatus::{Ongoing, Over};
+use hnefatafl::game::WinReason;
+use hnefatafl::pieces::Side;
+use hnefatafl::play::ValidPlay;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Key under which the user's progress through the puzzles is saved in eframe's storage.
+pub(crate) const PUZZLE_PROGRESS_KEY: &str = "puzzle_progress";
+/// The rating of a user who has not attempted any puzzles.
+const INITIAL_RATING: f64 = 1200.0;
+/// How far a user's rating moves after each puzzle (the Elo K-factor).
+const RATING_K: f64 = 32.0;
+/// The most plays a [`ProofSearch`] makes before giving up.
+const PROOF_SEARCH_BUDGET: u64 = 5_000_000;
+
+/// A position in which the side to play can force a win, with the line that wins it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct Puzzle {
+    /// Identifies the puzzle in the user's progress. Derived from the variant and `plays`.
+    pub(crate) id: String,
+    /// The name of the preset variant the puzzle is played in.
+    pub(crate) variant: String,
+    /// The plays leading from the variant's starting position to the puzzle's position.
+    pub(crate) plays: Vec<String>,
+    /// The winning line: the solver's plays alternating with the defence's replies, ending with the
+    /// play that wins. The solver's play is the only one that wins as quickly at each turn, and
+    /// each reply is one that holds out longest.
+    pub(crate) solution: Vec<String>,
+    /// How hard the puzzle is, on the same scale as the user's rating.
+    pub(crate) rating: u32,
+    /// Where the position came from (eg, "self-play").
+    pub(crate) source: String
+}
+
+impl Puzzle {
+
+    /// An ID for a puzzle in `variant` reached by `plays`, which is the same whenever the puzzle
+    /// is generated.
+    pub(crate) fn make_id(variant: &str, plays: &[String]) -> String {
+        let hash = fnv1a(format!("{variant}:{}", plays.join(" ")).as_bytes());
+        format!("{hash:016x}")
+    }
+
+    /// The number of plays the solver must make to win.
+    pub(crate) fn moves(&self) -> usize {
+        self.solution.len().div_ceil(2)
+    }
+}
+
+/// A file of puzzles in one variant, as written by the `puzzles` subcommand.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct PuzzleSet {
+    pub(crate) variant: String,
+    pub(crate) puzzles: Vec<Puzzle>
+}
+
+impl PuzzleSet {
+
+    pub(crate) fn new(variant: impl Into<String>) -> Self {
+        Self { variant: variant.into(), puzzles: vec![] }
+    }
+
+    /// Add `puzzle`, unless the set already contains a puzzle with the same ID. Returns whether it
+    /// was added.
+    pub(crate) fn add(&mut self, puzzle: Puzzle) -> bool {
+        if self.puzzles.iter().any(|p| p.id == puzzle.id) {
+            return false
+        }
+        self.puzzles.push(puzzle);
+        true
+    }
+}
+
+/// The user's puzzle rating and the puzzles they have attempted, persisted between sessions.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub(crate) struct PuzzleProgress {
+    pub(crate) rating: f64,
+    /// Whether each puzzle attempted (by ID) was solved at the first attempt.
+    pub(crate) results: BTreeMap<String, bool>
+}
+
+impl Default for PuzzleProgress {
+    fn default() -> Self {
+        Self { rating: INITIAL_RATING, results: BTreeMap::new() }
+    }
+}
+
+impl PuzzleProgress {
+
+    /// Record an attempt at `puzzle`. Only the first attempt at each puzzle changes the rating, by
+    /// the Elo formula (treating the puzzle as an opponent with the puzzle's rating).
+    pub(crate) fn record(&mut self, puzzle: &Puzzle, solved: bool) {
+        if self.results.contains_key(&puzzle.id) {
+            return
+        }
+        let expected = 1.0 / (1.0 + 10f64.powf((puzzle.rating as f64 - self.rating) / 400.0));
+        let score = if solved { 1.0 } else { 0.0 };
+        self.rating += RATING_K * (score - expected);
+        self.results.insert(puzzle.id.clone(), solved);
+    }
+
+    /// The number of puzzles solved at the first attempt.
+    pub(crate) fn solved(&self) -> usize {
+        self.results.values().filter(|s| **s).count()
+    }
+
+    /// The puzzle to attempt next, out of those in `variant` (or any variant if `None`) other than
+    /// `current`: the unattempted puzzle whose rating is closest to the user's, or if all have been
+    /// attempted, the closest one that was failed.
+    pub(crate) fn next_puzzle(
+        &self,
+        puzzles: &[Arc<Puzzle>],
+        variant: Option<&str>,
+        current: Option<&str>
+    ) -> Option<Arc<Puzzle>> {
+        let closest = |candidates: Vec<&Arc<Puzzle>>| candidates.into_iter()
+            .min_by_key(|p| (p.rating as f64 - self.rating).abs() as u64)
+            .cloned();
+        let candidates: Vec<&Arc<Puzzle>> = puzzles.iter()
+            .filter(|p| variant.is_none_or(|v| v == p.variant))
+            .filter(|p| current != Some(p.id.as_str()))
+            .collect();
+        let unattempted = candidates.iter()
+            .filter(|p| !self.results.contains_key(&p.id))
+            .copied()
+            .collect();
+        closest(unattempted).or_else(|| {
+            closest(candidates.into_iter().filter(|p| self.results.get(&p.id) == Some(&false)).collect())
+        })
+    }
+}
+
+/// Whether `side` has won in `state` in one of the ways puzzles are about: by freeing or capturing
+/// the king.
+pub(crate) fn puzzle_won<T: BoardState>(state: &GameState<T>, side: Side) -> bool {
+    matches!(state.status, Over(Win(WinReason::KingEscaped | WinReason::KingCaptured, winner)) if winner == side)
+}
+
+/// A [`ProofSearch`] gave up, having made [`PROOF_SEARCH_BUDGET`] plays.
+#[derive(Debug)]
+pub(crate) struct OverBudget;
+
+/// An exact search for forced wins, which searches every defence but stops looking at the plays
+/// from a position as soon as their result is known: at the solver's first winning play, or the
+/// defence's first play that escapes the win. It gives up once it has made
+/// [`PROOF_SEARCH_BUDGET`] plays, as positions with many plays can take too long to solve.
+pub(crate) struct ProofSearch<'a, T: BoardState> {
+    logic: &'a GameLogic<T>,
+    plays_made: u64
+}
+
+impl<'a, T: BoardState> ProofSearch<'a, T> {
+
+    pub(crate) fn new(logic: &'a GameLogic<T>) -> Self {
+        Self { logic, plays_made: 0 }
+    }
+
+    /// Make `vp` in `state`, counting it against the budget.
+    fn do_play(&mut self, vp: ValidPlay, state: GameState<T>) -> Result<GameState<T>, OverBudget> {
+        self.plays_made += 1;
+        if self.plays_made > PROOF_SEARCH_BUDGET {
+            return Err(OverBudget)
+        }
+        Ok(self.logic.do_valid_play(vp, state).new_state)
+    }
+
+    /// Whether making `vp` in `state` lets the side to play force a win within `moves` of its own
+    /// plays (counting `vp`), however the other side replies.
+    pub(crate) fn play_forces_win(&mut self, vp: ValidPlay, state: &GameState<T>, moves: u8) -> Result<bool, OverBudget> {
+        let side = state.side_to_play;
+        let next = self.do_play(vp, *state)?;
+        if next.status != Ongoing {
+            return Ok(puzzle_won(&next, side))
+        }
+        if moves <= 1 {
+            return Ok(false)
+        }
+        for reply in all_plays(self.logic, &next) {
+            let after = self.do_play(reply, next)?;
+            let won = if after.status == Ongoing {
+                self.can_force_win(&after, moves - 1)?
+            } else {
+                puzzle_won(&after, side)
+            };
+            if !won {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether the side to play in `state` can force a win within `moves` of its own plays.
+    fn can_force_win(&mut self, state: &GameState<T>, moves: u8) -> Result<bool, OverBudget> {
+        if moves == 0 {
+            return Ok(false)
+        }
+        for vp in all_plays(self.logic, state) {
+            if self.play_forces_win(vp, state, moves)? {
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+
+    /// The fewest plays within which the side to play in `state` can force a win, if no more than
+    /// `max_moves`.
+    fn moves_to_win(&mut self, state: &GameState<T>, max_moves: u8) -> Result<Option<u8>, OverBudget> {
+        for n in 1..=max_moves {
+            if self.can_force_win(state, n)? {
+                return Ok(Some(n))
+            }
+        }
+        Ok(None)
+    }
+
+    /// The line by which the side to play in `state` wins within `moves` plays, if at each of its
+    /// turns there is only one play that wins as quickly as possible. Also returns the number of
+    /// plays the solver could have chosen from, over all its turns.
+    fn unique_solution(&mut self, state: &GameState<T>, moves: u8) -> Result<Option<(Vec<ValidPlay>, usize)>, OverBudget> {
+        let mut state = *state;
+        let mut remaining = moves;
+        let mut line = vec![];
+        let mut choices = 0;
+        loop {
+            let plays = all_plays(self.logic, &state);
+            choices += plays.len();
+            let mut winning = vec![];
+            for vp in plays {
+                if self.play_forces_win(vp, &state, remaining)? {
+                    winning.push(vp);
+                }
+            }
+            let [play] = winning[..] else { return Ok(None) };
+            line.push(play);
+            state = self.do_play(play, state)?;
+            if state.status != Ongoing {
+                return Ok(Some((line, choices)))
+            }
+            // The defence holds out as long as it can
+            let mut longest = None;
+            for reply in all_plays(self.logic, &state) {
+                let after = self.do_play(reply, state)?;
+                let n = if after.status == Ongoing {
+                    let Some(n) = self.moves_to_win(&after, remaining - 1)? else { return Ok(None) };
+                    n
+                } else {
+                    0
+                };
+                if longest.is_none_or(|(_, _, longest_n)| n > longest_n) {
+                    longest = Some((reply, after, n));
+                }
+            }
+            let Some((reply, after, reply_remaining)) = longest else { return Ok(None) };
+            line.push(reply);
+            if after.status != Ongoing {
+                // The defence could only lose at once
+                return Ok(None)
+            }
+            state = after;
+            remaining = reply_remaining;
+        }
+    }
+
+    /// The quickest win for the side to play in `state` within `max_moves` plays, as for
+    /// [`ProofSearch::unique_solution`], if there is one.
+    pub(crate) fn solve(&mut self, state: &GameState<T>, max_moves: u8) -> Result<Option<(Vec<ValidPlay>, usize)>, OverBudget> {
+        match self.moves_to_win(state, max_moves)? {
+            Some(moves) => self.unique_solution(state, moves),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_rules::preset_variants;
+    use crate::position_editor_view::grid_to_board_string;
+    use hnefatafl::aliases::LargeBasicBoardState;
+    use hnefatafl::game::Game;
+    use hnefatafl::pieces::PieceType::Soldier;
+    use hnefatafl::pieces::Side::{Attacker, Defender};
+    use hnefatafl::pieces::{Piece, KING};
+    use hnefatafl::tiles::Tile;
+
+    /// A Brandubh game with the king and attackers on the given tiles, with the defender to play.
+    fn brandubh_position(king: (usize, usize), attackers: &[(usize, usize)]) -> Game<LargeBasicBoardState> {
+        let (ruleset, _) = preset_variants().remove("Brandubh").unwrap();
+        let mut grid = vec![vec![None; 7]; 7];
+        grid[king.0][king.1] = Some(KING);
+        for (row, col) in attackers {
+            grid[*row][*col] = Some(Piece::new(Soldier, Attacker));
+        }
+        let mut game = Game::new(ruleset, &grid_to_board_string(&grid)).unwrap();
+        game.state.side_to_play = Defender;
+        game
+    }
+
+    fn puzzle(rating: u32) -> Puzzle {
+        Puzzle {
+            id: Puzzle::make_id("Brandubh", &[]),
+            variant: "Brandubh".to_string(),
+            plays: vec![],
+            solution: vec![],
+            rating,
+            source: "test".to_string()
+        }
+    }
+
+    #[test]
+    fn finds_unique_win_in_one() {
+        // The king can only reach the nearer corner, as the attacker blocks the way to the other.
+        let game = brandubh_position((0, 1), &[(0, 4)]);
+        let (line, _) = ProofSearch::new(&game.logic).solve(&game.state, 2).unwrap().unwrap();
+        assert_eq!(line.len(), 1);
+        assert_eq!(line[0].play.to(), Tile::new(0, 0));
+    }
+
+    #[test]
+    fn rejects_two_equally_fast_wins() {
+        // The king can escape to either corner.
+        let game = brandubh_position((0, 3), &[(5, 5)]);
+        assert!(ProofSearch::new(&game.logic).solve(&game.state, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn only_first_attempt_counts() {
+        let puzzle = puzzle(1200);
+        let mut progress = PuzzleProgress::default();
+        progress.record(&puzzle, false);
+        let rating = progress.rating;
+        assert!(rating < INITIAL_RATING);
+        progress.record(&puzzle, true);
+        assert_eq!(progress.rating, rating);
+        assert_eq!(progress.results.get(&puzzle.id), Some(&false));
+        assert_eq!(progress.solved(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::ai::{is_forced_win, BasicAi, Difficulty};
+use crate::app::APP_NAME;
+use crate::book_builder::{output_path, parse_difficulty, read_games_file, self_play_game, write_output};
+use crate::custom_rules::preset_variants;
+use crate::puzzle::{OverBudget, ProofSearch, Puzzle, PuzzleSet};
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::pieces::Side::Attacker;
+use hnefatafl::play::Play;
+use hnefatafl::rules::Ruleset;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The rating of a puzzle is this, plus [`PUZZLE_RATING_PER_MOVE`] for each play the solver makes,
+/// plus one for every other play the solver could have made instead.
+const PUZZLE_BASE_RATING: u32 = 700;
+const PUZZLE_RATING_PER_MOVE: u32 = 300;
+
+pub(crate) const PUZZLES_USAGE: &str = "\
+Usage: hnefatafl-egui puzzles <variant> [--self-play <games>] [--time <seconds>] [--difficulty <level>]
+                             [--games <games file>] [--max-moves <moves>] [--output <puzzle file>]
+
+Find puzzles for a preset variant in games played by the AI against itself and/or in a file of games
+(one per line, as for the `book` subcommand). Every position in the games is searched by the AI;
+where it finds that the side to play can force a win by capturing or freeing the king, the quickest
+win is worked out exactly. The position becomes a puzzle if that win takes no more than 3 plays (or
+--max-moves) by the winning side and, at each of its turns, only one play wins as quickly. Puzzles
+are added to any puzzle set at the output path, which defaults to the directory the app loads
+puzzles from. The AI uses 0.5 seconds per position, and self-play is at Medium difficulty (whose
+mistakes leave more wins to find), unless told otherwise.";
+
+/// The directory from which the app loads puzzles generated by the `puzzles` subcommand.
+pub(crate) fn puzzles_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|d| d.join("puzzles"))
+}
+
+fn read_puzzle_set(path: &Path) -> Result<PuzzleSet, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("Could not parse {}: {e}", path.display()))
+}
+
+/// Load the puzzles in every puzzle set in [`puzzles_dir`]. Files that cannot be read are skipped.
+pub(crate) fn load_puzzle_files() -> Vec<Arc<Puzzle>> {
+    let mut puzzles = vec![];
+    let Some(entries) = puzzles_dir().and_then(|d| std::fs::read_dir(d).ok()) else { return puzzles };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue
+        }
+        match read_puzzle_set(&path) {
+            Ok(set) => puzzles.extend(set.puzzles.into_iter().map(Arc::new)),
+            Err(e) => log::warn!("{e}")
+        }
+    }
+    puzzles
+}
+
+/// Find puzzles in the game made up of `plays`. Positions are searched with `ai`, and those in
+/// which it finds a forced win are solved exactly with a [`ProofSearch`]. Positions whose exact
+/// search goes over its budget are skipped, as are positions in the solution of a puzzle already
+/// found, which would give the same puzzle again.
+fn game_puzzles(
+    variant: &str,
+    ruleset: Ruleset,
+    starting_board: &str,
+    plays: &[Play],
+    source: &str,
+    ai: &mut BasicAi<LargeBasicBoardState>,
+    max_moves: u8
+) -> Result<Vec<Puzzle>, String> {
+    let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    let mut puzzles = vec![];
+    let mut skip_until = 0;
+    for (i, play) in plays.iter().enumerate() {
+        if i >= skip_until && game.state.status == Ongoing {
+            let side = game.state.side_to_play;
+            let found_win = ai.score_plays(&game.state).first()
+                .is_some_and(|(_, score)| is_forced_win(*score, side));
+            let solution = if found_win {
+                match ProofSearch::new(&game.logic).solve(&game.state, max_moves) {
+                    Ok(solution) => solution,
+                    Err(OverBudget) => {
+                        println!("Skipped the position after play {i}, which took too long to solve.");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if let Some((line, choices)) = solution {
+                let moves = line.len().div_ceil(2) as u32;
+                let plays: Vec<String> = plays[..i].iter().map(Play::to_string).collect();
+                puzzles.push(Puzzle {
+                    id: Puzzle::make_id(variant, &plays),
+                    variant: variant.to_string(),
+                    plays,
+                    solution: line.iter().map(|vp| vp.play.to_string()).collect(),
+                    rating: PUZZLE_BASE_RATING + PUZZLE_RATING_PER_MOVE * moves + (choices as u32 - moves),
+                    source: source.to_string()
+                });
+                println!("Found a puzzle after play {i}: {side:?} to win in {moves}.");
+                skip_until = i + line.len();
+            }
+        }
+        game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+    }
+    Ok(puzzles)
+}
+
+/// Run the `puzzles` subcommand with the given arguments.
+pub(crate) fn run_puzzles_command(args: &[String]) -> Result<(), String> {
+    let Some((variant, mut rest)) = args.split_first() else {
+        return Err(PUZZLES_USAGE.to_string())
+    };
+    let (ruleset, starting_board) = preset_variants().remove(variant.as_str())
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let mut self_play_games = 0u32;
+    let mut time = Duration::from_secs_f64(0.5);
+    let mut difficulty = Difficulty::Medium;
+    let mut games_path: Option<&String> = None;
+    let mut max_moves = 3u8;
+    let mut output: Option<PathBuf> = None;
+    while let [flag, value, tail @ ..] = rest {
+        match flag.as_str() {
+            "--self-play" => self_play_games = value.parse()
+                .map_err(|_| format!("Invalid number of games {value:?}."))?,
+            "--time" => time = value.parse().map(Duration::from_secs_f64)
+                .map_err(|_| format!("Invalid time {value:?}."))?,
+            "--difficulty" => difficulty = parse_difficulty(value)?,
+            "--games" => games_path = Some(value),
+            "--max-moves" => max_moves = value.parse().ok().filter(|m| *m > 0)
+                .ok_or(format!("Invalid number of moves {value:?}."))?,
+            "--output" => output = Some(PathBuf::from(value)),
+            _ => return Err(PUZZLES_USAGE.to_string())
+        }
+        rest = tail;
+    }
+    if !rest.is_empty() || (self_play_games == 0 && games_path.is_none()) {
+        return Err(PUZZLES_USAGE.to_string())
+    }
+    let output = output_path(output, puzzles_dir(), variant, "json")?;
+
+    let mut set = if output.exists() { read_puzzle_set(&output)? } else { PuzzleSet::new(variant.as_str()) };
+    if set.variant != *variant {
+        return Err(format!("{} is a puzzle set for {}.", output.display(), set.variant))
+    }
+    let logic = Game::<LargeBasicBoardState>::new(ruleset, &starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?
+        .logic;
+    let mut ai = BasicAi::new(logic, Attacker, time, Difficulty::Maximum);
+    let mut games: Vec<(Vec<Play>, String)> = vec![];
+    if let Some(path) = games_path {
+        for (line, plays) in read_games_file(path)? {
+            games.push((plays, format!("{path}, line {line}")));
+        }
+    }
+    for i in 0..self_play_games {
+        let (plays, result) = self_play_game(ruleset, &starting_board, time, difficulty)?;
+        println!(
+            "Self-play game {} of {self_play_games}: {} plays, {}.",
+            i + 1,
+            plays.len(),
+            result.map_or("abandoned".to_string(), |r| r.describe())
+        );
+        games.push((plays, "self-play".to_string()));
+    }
+    let mut added = 0;
+    for (plays, source) in &games {
+        for puzzle in game_puzzles(variant, ruleset, &starting_board, plays, source, &mut ai, max_moves)? {
+            if set.add(puzzle) {
+                added += 1;
+            }
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&set).expect("Puzzle set should be serializable.");
+    write_output(&output, json)?;
+    println!("Added {added} puzzle(s), making {} in {}.", set.puzzles.len(), output.display());
+    Ok(())
+}
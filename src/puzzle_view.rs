@@ -0,0 +1,384 @@
+use crate::ai::{Ai, BasicAi, Difficulty};
+use crate::board::Board;
+use crate::custom_rules::preset_variants;
+use crate::puzzle::{puzzle_won, ProofSearch, Puzzle, PuzzleProgress};
+use crate::saved_game::parse_plays;
+use crate::settings::Settings;
+use eframe::emath::Align;
+use egui::{Layout, RichText};
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::pieces::Side;
+use hnefatafl::play::{Play, ValidPlay};
+use std::collections::BTreeSet;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(target_arch = "wasm32")]
+use wasm_thread as thread;
+
+type State = GameState<LargeBasicBoardState>;
+
+/// How long the AI searches for the defence's replies.
+const DEFENCE_TIME_PER_PLAY: Duration = Duration::from_millis(500);
+/// How often the view is redrawn while waiting for the defence thread.
+const DEFENCE_REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) enum PuzzleAction {
+    /// The user's progress has changed and should be saved.
+    SaveProgress(PuzzleProgress),
+    /// Return to the game setup screen.
+    Back
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Status {
+    /// The user is looking for the next play of the solution.
+    Solving,
+    Solved,
+    /// The user made a wrong play or asked to see the solution.
+    Failed
+}
+
+/// Requests to the thread that checks the user's plays and plays the defence.
+enum Request {
+    /// Check whether the play lets the side making it force a win within the given number of its
+    /// own plays (counting the play).
+    Check(State, ValidPlay, u8),
+    /// Choose the defence's reply in the position.
+    Reply(State)
+}
+
+/// Responses from the defence thread, with the position each request was made in.
+enum Response {
+    /// Whether the play checked forces a win, or `None` if the check took too long.
+    Checked(State, ValidPlay, Option<bool>),
+    Replied(State, ValidPlay)
+}
+
+/// Start a thread that answers [`Request`]s, with the AI playing the defence as `defender`.
+fn spawn_defence(logic: GameLogic<LargeBasicBoardState>, defender: Side) -> (Sender<Request>, Receiver<Response>) {
+    let (request_sender, request_receiver) = std::sync::mpsc::channel::<Request>();
+    let (response_sender, response_receiver) = std::sync::mpsc::channel::<Response>();
+    thread::spawn(move || {
+        let mut ai = BasicAi::new(logic, defender, DEFENCE_TIME_PER_PLAY, Difficulty::Maximum);
+        while let Ok(request) = request_receiver.recv() {
+            let response = match request {
+                Request::Check(state, vp, moves) => Response::Checked(
+                    state,
+                    vp,
+                    ProofSearch::new(&logic).play_forces_win(vp, &state, moves).ok()
+                ),
+                Request::Reply(state) => match ai.next_play(&state) {
+                    Ok((vp, _)) => Response::Replied(state, vp),
+                    Err(_) => continue
+                }
+            };
+            // Don't panic if we can't send the response, it probably just means that the user has
+            // moved on to another puzzle
+            let _ = response_sender.send(response);
+        }
+    });
+    (request_sender, response_receiver)
+}
+
+/// The puzzle being attempted.
+struct Attempt {
+    puzzle: Arc<Puzzle>,
+    game: Game<LargeBasicBoardState>,
+    solution: Vec<Play>,
+    /// The number of plays made since the puzzle's position.
+    made: usize,
+    /// Whether the plays made so far are those of the solution. The defence need not follow the
+    /// solution, as any reply is answered by a play that still forces the win.
+    on_solution: bool,
+    /// The side that has to find the win.
+    solver: Side,
+    /// The number of plays within which the solver still has to win.
+    moves_left: u8,
+    board_ui: Board<LargeBasicBoardState>,
+    status: Status,
+    /// Whether a play is being checked or the defence is choosing its reply.
+    waiting: bool,
+    defence_sender: Sender<Request>,
+    defence_receiver: Receiver<Response>
+}
+
+impl Attempt {
+
+    fn new(puzzle: Arc<Puzzle>, settings: &Settings) -> Result<Self, String> {
+        let (ruleset, starting_board) = preset_variants().remove(&puzzle.variant)
+            .ok_or(format!("Unknown variant {:?}.", puzzle.variant))?;
+        let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, &starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        for play in parse_plays(&puzzle.plays)? {
+            game.do_play(play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+        }
+        let solution = parse_plays(&puzzle.solution)?;
+        let solver = game.state.side_to_play;
+        let moves_left = puzzle.moves() as u8;
+        let mut board_ui = Board::new(&game, Some(solver));
+        board_ui.flipped = settings.flip_board;
+        board_ui.animate_moves = settings.animate_moves;
+        let (defence_sender, defence_receiver) = spawn_defence(game.logic, solver.other());
+        Ok(Self {
+            puzzle,
+            game,
+            solution,
+            made: 0,
+            on_solution: true,
+            solver,
+            moves_left,
+            board_ui,
+            status: Status::Solving,
+            waiting: false,
+            defence_sender,
+            defence_receiver
+        })
+    }
+
+    /// The next play of the solution, if the plays made so far have followed it.
+    fn next_solution_play(&self) -> Option<Play> {
+        self.solution.get(self.made).copied().filter(|_| self.on_solution)
+    }
+
+    /// Make `play`, which the board or the defence thread has already found to be valid.
+    fn make_play(&mut self, play: Play) {
+        let on_solution = self.next_solution_play() == Some(play);
+        match self.game.do_play(play) {
+            Ok(_) => {
+                self.made += 1;
+                self.on_solution = on_solution;
+            },
+            Err(e) => log::error!("Could not make play {play} in puzzle {}: {e:?}", self.puzzle.id)
+        }
+    }
+}
+
+/// A view in which the user solves puzzles: positions in which they must find the only way to win
+/// by force, against the AI's defence.
+pub(crate) struct PuzzleView {
+    puzzles: Vec<Arc<Puzzle>>,
+    progress: PuzzleProgress,
+    /// Only puzzles in this variant are chosen, if set.
+    variant: Option<String>,
+    attempt: Option<Attempt>,
+    message: Option<String>,
+    settings: Settings
+}
+
+impl PuzzleView {
+
+    pub(crate) fn new(puzzles: Vec<Arc<Puzzle>>, progress: PuzzleProgress, settings: &Settings) -> Self {
+        let mut view = Self {
+            puzzles,
+            progress,
+            variant: None,
+            attempt: None,
+            message: None,
+            settings: settings.clone()
+        };
+        view.next_puzzle();
+        view
+    }
+
+    /// Start the puzzle best suited to the user's rating, other than the current one.
+    fn next_puzzle(&mut self) {
+        let current = self.attempt.as_ref().map(|a| a.puzzle.id.clone());
+        self.attempt = None;
+        self.message = None;
+        let Some(puzzle) = self.progress.next_puzzle(&self.puzzles, self.variant.as_deref(), current.as_deref())
+        else {
+            self.message = Some("There are no more puzzles to attempt.".to_string());
+            return
+        };
+        match Attempt::new(puzzle, &self.settings) {
+            Ok(attempt) => self.attempt = Some(attempt),
+            Err(e) => self.message = Some(format!("Could not load puzzle: {e}"))
+        }
+    }
+
+    /// Have the defence thread check a play made by the user.
+    fn handle_play(&mut self, play: Play) {
+        let Some(attempt) = &mut self.attempt else { return };
+        let state = attempt.game.state;
+        match attempt.game.logic.validate_play(play, &state) {
+            Ok(vp) => {
+                attempt.waiting = attempt.defence_sender.send(Request::Check(state, vp, attempt.moves_left)).is_ok();
+                self.message = Some(format!("Checking {play}..."));
+            },
+            Err(e) => self.message = Some(format!("Cannot play {play}: {e:?}."))
+        }
+    }
+
+    /// Finish the attempt, returning the progress to save.
+    fn finish(&mut self, solved: bool) -> Option<PuzzleProgress> {
+        let attempt = self.attempt.as_mut()?;
+        attempt.status = if solved { Status::Solved } else { Status::Failed };
+        attempt.waiting = false;
+        self.progress.record(&attempt.puzzle, solved);
+        Some(self.progress.clone())
+    }
+
+    /// Handle a response from the defence thread, returning the progress to save if the puzzle has
+    /// been finished. Responses to requests made in other positions, or after the attempt ended,
+    /// are ignored.
+    fn handle_response(&mut self, response: Response) -> Option<PuzzleProgress> {
+        let attempt = self.attempt.as_mut()?;
+        if attempt.status != Status::Solving {
+            return None
+        }
+        match response {
+            Response::Checked(state, vp, forces_win) if state == attempt.game.state => {
+                let best = attempt.next_solution_play();
+                // A play whose check took too long is judged by the solution, if it still applies.
+                let forces_win = forces_win.unwrap_or(best == Some(vp.play));
+                if !forces_win {
+                    let mut message = format!("{} does not force a win in {}.", vp.play, attempt.moves_left);
+                    if let Some(best) = best {
+                        message.push_str(&format!(" The solution continues with {best}."));
+                    }
+                    self.message = Some(message);
+                    return self.finish(false)
+                }
+                attempt.make_play(vp.play);
+                attempt.moves_left -= 1;
+                if attempt.game.state.status != Ongoing {
+                    self.message = Some("Solved!".to_string());
+                    return self.finish(true)
+                }
+                attempt.waiting = attempt.defence_sender.send(Request::Reply(attempt.game.state)).is_ok();
+                self.message = Some(format!("{} is right. Keep going.", vp.play));
+            },
+            Response::Replied(state, vp) if state == attempt.game.state => {
+                attempt.waiting = false;
+                attempt.make_play(vp.play);
+                if attempt.game.state.status != Ongoing {
+                    // Only possible if the defence loses at once, as every reply was checked
+                    let solved = puzzle_won(&attempt.game.state, attempt.solver);
+                    self.message = Some(if solved { "Solved!" } else { "The game is over." }.to_string());
+                    return self.finish(solved)
+                }
+            },
+            _ => {}
+        }
+        None
+    }
+
+    /// Give up on the current puzzle, which counts as failing it, and show the rest of the
+    /// solution.
+    fn show_solution(&mut self) -> Option<PuzzleProgress> {
+        let attempt = self.attempt.as_ref()?;
+        self.message = Some(format!("Solution: {}.", attempt.puzzle.solution.join(", ")));
+        self.finish(false)
+    }
+
+    fn variant_ui(&mut self, ui: &mut egui::Ui) {
+        let variants: BTreeSet<String> = self.puzzles.iter().map(|p| p.variant.clone()).collect();
+        let mut selected = self.variant.clone();
+        ui.label("Variant:");
+        egui::ComboBox::from_id_salt("puzzle_variant")
+            .selected_text(selected.clone().unwrap_or("Any".to_string()))
+            .show_ui(ui, |combo_box| {
+                combo_box.selectable_value(&mut selected, None, "Any");
+                for v in variants {
+                    combo_box.selectable_value(&mut selected, Some(v.clone()), v);
+                }
+            });
+        if selected != self.variant {
+            self.variant = selected;
+            self.next_puzzle();
+        }
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<PuzzleAction> {
+        let mut action: Option<PuzzleAction> = None;
+        let responses: Vec<Response> = self.attempt.as_ref()
+            .map(|a| a.defence_receiver.try_iter().collect())
+            .unwrap_or_default();
+        for response in responses {
+            if let Some(progress) = self.handle_response(response) {
+                action = Some(PuzzleAction::SaveProgress(progress));
+            }
+        }
+        if let Some(attempt) = &mut self.attempt {
+            if attempt.waiting {
+                ctx.request_repaint_after(DEFENCE_REPAINT_INTERVAL);
+            }
+            // The board only takes plays while it is the user's turn to find one
+            attempt.board_ui.read_only = attempt.status != Status::Solving || attempt.waiting;
+        }
+
+        egui::TopBottomPanel::top("puzzle_header").show(ctx, |ui| {
+            ui.label(RichText::new("Puzzles").heading());
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Your rating: {:.0}. Solved {} of {} attempted ({} puzzles).",
+                    self.progress.rating,
+                    self.progress.solved(),
+                    self.progress.results.len(),
+                    self.puzzles.len()
+                ));
+                ui.separator();
+                self.variant_ui(ui);
+            });
+        });
+        egui::TopBottomPanel::bottom("puzzle_controls").show(ctx, |ui| {
+            ui.with_layout(Layout::bottom_up(Align::Min), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Back").clicked() {
+                        action = Some(PuzzleAction::Back);
+                    }
+                    ui.separator();
+                    let status = self.attempt.as_ref().map(|a| a.status);
+                    if status == Some(Status::Solving) && ui.button("Show solution").clicked() {
+                        if let Some(progress) = self.show_solution() {
+                            action = Some(PuzzleAction::SaveProgress(progress));
+                        }
+                    }
+                    let next_play = self.attempt.as_ref().and_then(Attempt::next_solution_play);
+                    if status == Some(Status::Failed) && next_play.is_some() && ui.button("Next play of solution").clicked() {
+                        if let (Some(attempt), Some(play)) = (&mut self.attempt, next_play) {
+                            attempt.make_play(play);
+                        }
+                    }
+                    if ui.add_enabled(!self.puzzles.is_empty(), egui::Button::new("Next puzzle")).clicked() {
+                        self.next_puzzle();
+                    }
+                });
+                if let Some(msg) = &self.message {
+                    ui.label(msg);
+                }
+                if let Some(attempt) = &self.attempt {
+                    ui.label(RichText::new(format!(
+                        "{:?} to play and win in {}. ({}, rated {}, from {}.)",
+                        attempt.solver,
+                        attempt.puzzle.moves(),
+                        attempt.puzzle.variant,
+                        attempt.puzzle.rating,
+                        attempt.puzzle.source
+                    )).strong());
+                }
+            });
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.puzzles.is_empty() {
+                ui.label("There are no puzzles yet. They are generated with the `puzzles` subcommand of \
+                    the native app.");
+                return
+            }
+            let board_side_px = ui.available_width().min(ui.available_height());
+            let Some(attempt) = &mut self.attempt else { return };
+            if let Some(play) = attempt.board_ui.update(&attempt.game, ctx, ui, board_side_px) {
+                self.handle_play(play);
+            }
+        });
+        action
+    }
+
+}
@@ -0,0 +1,542 @@
+use crate::ai::describe_score;
+use crate::annotation::{win_chance, Annotator, GameAnnotation, Judgement, PlayAnnotation};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::annotation::PositionAnalysis;
+use crate::archive::{ArchivedGame, Variation};
+use crate::board::Board;
+use crate::game_play_view::GameSetup;
+use crate::saved_game::parse_plays;
+use crate::settings::Settings;
+use eframe::emath::Align;
+use egui::{Color32, Layout, RichText, Stroke};
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::pieces;
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::play::Play;
+use std::cmp::min;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{Receiver, TryRecvError};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+use std::time::Duration;
+
+/// How often the view is redrawn to show progress while the game is being annotated.
+const ANNOTATION_REPAINT_INTERVAL: Duration = Duration::from_millis(200);
+/// Height of the graph of winning chances, in points.
+const EVAL_GRAPH_HEIGHT: f32 = 80.0;
+
+pub(crate) enum ReplayAction {
+    /// Start a new game from the position being shown, which is reached by making `plays`. The
+    /// new game is a variation of the archived game with index `archive_index`.
+    PlayFromHere {
+        archive_index: usize,
+        setup: GameSetup,
+        plays: Vec<Play>
+    },
+    /// Analyse the position being shown, which is reached by making `plays`, with the AI.
+    Analyse {
+        archive_index: usize,
+        setup: GameSetup,
+        plays: Vec<Play>
+    },
+    /// Return to the game archive.
+    Back
+}
+
+/// A line of play in the game being replayed, with any variations branching off it.
+struct Line {
+    plays: Vec<Play>,
+    /// Variations, each with the number of plays of this line that are made before it starts.
+    variations: Vec<(usize, Line)>
+}
+
+impl Line {
+
+    fn parse(plays: &[String], variations: &[Variation]) -> Result<Self, String> {
+        Ok(Self {
+            plays: parse_plays(plays)?,
+            variations: variations.iter()
+                .map(|v| Ok((v.branch_point, Self::parse(&v.plays, &v.variations)?)))
+                .collect::<Result<_, String>>()?
+        })
+    }
+
+    /// Check that this line and all its variations are legal when played from the current state
+    /// of `game`. `game` is returned to its original state if the check succeeds.
+    fn check<T: BoardState>(&self, game: &mut Game<T>) -> Result<(), String> {
+        let start = game.play_history.len();
+        for i in 0..=self.plays.len() {
+            for (_, v) in self.variations.iter().filter(|(bp, _)| *bp == i) {
+                v.check(game)?;
+            }
+            if let Some(play) = self.plays.get(i) {
+                game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+            }
+        }
+        while game.play_history.len() > start {
+            game.undo_last_play();
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of a finished game, which can be stepped through play by play or played back
+/// automatically.
+pub(crate) struct ReplayView<T: BoardState> {
+    /// The index of the game in the archive.
+    archive_index: usize,
+    setup: GameSetup,
+    /// The game, at the position currently being shown.
+    game: Game<T>,
+    /// The plays of the game, and any variations.
+    tree: Line,
+    /// The line (main line or variation) currently being shown, from the start of the game.
+    line: Vec<Play>,
+    board_ui: Board<T>,
+    /// Short description of the game, shown above the board.
+    title: String,
+    /// Whether plays are being stepped through automatically.
+    autoplay: bool,
+    /// How long each position is shown for during autoplay, in seconds.
+    secs_per_play: f64,
+    /// The time (from egui's clock) at which the last play was made during autoplay.
+    last_step_time: f64,
+    /// The side the AI should play when playing on from the current position, or `None` if
+    /// humans should play both sides.
+    branch_ai_side: Option<pieces::Side>,
+    /// The AI's analysis of the main line, if the game is being annotated.
+    annotation: Option<GameAnnotation>,
+    /// Receives the analysis of each position of the main line from the thread annotating it.
+    #[cfg(not(target_arch = "wasm32"))]
+    annotation_receiver: Option<Receiver<PositionAnalysis>>,
+    /// Annotates the main line on the UI thread, one position per frame.
+    #[cfg(target_arch = "wasm32")]
+    annotator: Option<Annotator<T>>
+}
+
+/// The colour in which plays of the given kind are highlighted.
+fn judgement_color(judgement: Judgement) -> Color32 {
+    match judgement {
+        Judgement::Inaccuracy => Color32::GOLD,
+        Judgement::Mistake => Color32::ORANGE,
+        Judgement::Blunder => Color32::RED
+    }
+}
+
+/// Describe the AI's assessment of play `n` (counting from 1) of a game.
+fn describe_annotation(n: usize, play: Play, annotation: &PlayAnnotation) -> String {
+    let played_score = describe_score(annotation.played_score);
+    let best_score = describe_score(annotation.best_score);
+    match annotation.judgement() {
+        Some(j) => format!("{n}. {play} was {} {} ({played_score}). {} was better ({best_score}).",
+            if j == Judgement::Inaccuracy { "an" } else { "a" }, j.name(), annotation.best_play),
+        None if play == annotation.best_play => format!("{n}. {play} was the best play ({played_score})."),
+        None => format!("{n}. {play} ({played_score}). The best play was {} ({best_score}).", annotation.best_play)
+    }
+}
+
+/// Draw a graph of the attacker's chances of winning after each play of the main line, marking the
+/// position being shown (if it is on the main line) and any mistakes. Returns the position clicked,
+/// if any.
+fn eval_graph(ui: &mut egui::Ui, annotation: &GameAnnotation, current: Option<usize>) -> Option<usize> {
+    let size = egui::vec2(ui.available_width(), EVAL_GRAPH_HEIGHT);
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    let positions = annotation.play_count.max(1) as f32;
+    let x = |i: usize| rect.left() + rect.width() * i as f32 / positions;
+    painter.rect_filled(rect, 0.0, visuals.extreme_bg_color);
+    painter.hline(rect.x_range(), rect.center().y, visuals.widgets.noninteractive.bg_stroke);
+    if let Some(n) = current {
+        painter.vline(x(n), rect.y_range(), Stroke::new(1.0, visuals.selection.bg_fill));
+    }
+    let points: Vec<egui::Pos2> = annotation.evals.iter()
+        .enumerate()
+        .map(|(i, eval)| egui::pos2(x(i), rect.bottom() - rect.height() * win_chance(*eval, Attacker) as f32))
+        .collect();
+    for (i, a) in annotation.plays.iter().enumerate() {
+        if let (Some(j), Some(p)) = (a.and_then(|a| a.judgement()), points.get(i + 1)) {
+            painter.circle_filled(*p, 3.0, judgement_color(j));
+        }
+    }
+    painter.add(egui::Shape::line(points, Stroke::new(1.5, visuals.text_color())));
+    let response = response.on_hover_text(
+        "The attacker's chances of winning after each play, according to the AI. Click to jump to a play."
+    );
+    if !response.clicked() {
+        return None
+    }
+    response.interact_pointer_pos()
+        .map(|p| ((p.x - rect.left()) / rect.width() * positions).round().clamp(0.0, positions) as usize)
+}
+
+impl<T: BoardState + Send + 'static> ReplayView<T> where T::BitField: Send {
+
+    pub(crate) fn new(
+        archive_index: usize,
+        archived: &ArchivedGame,
+        settings: &Settings
+    ) -> Result<Self, String> {
+        let setup = archived.game.setup()?;
+        let tree = Line::parse(&archived.game.plays, &archived.variations)?;
+        let mut game: Game<T> = Game::new(setup.ruleset, &setup.starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        // Check that all the plays are legal before showing anything.
+        tree.check(&mut game)?;
+        let mut board_ui = Board::new(&game, None);
+        board_ui.read_only = true;
+        board_ui.flipped = settings.flip_board;
+        board_ui.animate_moves = settings.animate_moves;
+        let title = format!(
+            "{} ({}), {} ({})",
+            archived.game.ruleset_name,
+            archived.date(),
+            archived.result.describe(),
+            archived.reason
+        );
+        let mut view = Self {
+            archive_index,
+            branch_ai_side: setup.ai_side,
+            setup,
+            game,
+            line: tree.plays.clone(),
+            tree,
+            board_ui,
+            title,
+            autoplay: false,
+            secs_per_play: 1.0,
+            last_step_time: 0.0,
+            annotation: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            annotation_receiver: None,
+            #[cfg(target_arch = "wasm32")]
+            annotator: None
+        };
+        if settings.annotate_games {
+            view.start_annotation();
+        }
+        Ok(view)
+    }
+
+    /// Start having the AI analyse every position of the main line, in the background on native or
+    /// one position per frame on the web.
+    fn start_annotation(&mut self) {
+        let annotator = match Annotator::<T>::new(&self.setup, self.tree.plays.clone()) {
+            Ok(annotator) => annotator,
+            Err(e) => {
+                log::error!("Could not annotate game: {e}");
+                return
+            }
+        };
+        self.annotation = Some(GameAnnotation::new(self.tree.plays.len()));
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            thread::spawn(move || {
+                for analysis in annotator {
+                    // Stop once the view has been closed
+                    if sender.send(analysis).is_err() {
+                        break
+                    }
+                }
+            });
+            self.annotation_receiver = Some(receiver);
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.annotator = Some(annotator);
+        }
+    }
+
+    /// Add any newly analysed positions to the annotation.
+    fn receive_annotation(&mut self, ctx: &egui::Context) {
+        let Some(annotation) = &mut self.annotation else { return };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(receiver) = &self.annotation_receiver {
+            loop {
+                match receiver.try_recv() {
+                    Ok(analysis) => annotation.push(analysis),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        annotation.complete = true;
+                        break
+                    }
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        match self.annotator.as_mut().and_then(|a| a.next()) {
+            Some(analysis) => annotation.push(analysis),
+            None => annotation.complete = true
+        }
+        if !annotation.complete {
+            ctx.request_repaint_after(ANNOTATION_REPAINT_INTERVAL);
+        }
+    }
+
+    /// The AI's assessment of play `i` (counting from 0) of the main line, if it has been made.
+    fn play_annotation(&self, i: usize) -> Option<PlayAnnotation> {
+        self.annotation.as_ref().and_then(|a| a.plays.get(i).copied().flatten())
+    }
+
+    /// Whether the position being shown is on the main line.
+    fn on_main_line(&self) -> bool {
+        self.tree.plays.starts_with(&self.line[..self.current_play()])
+    }
+
+    /// The number of plays made to reach the position being shown.
+    fn current_play(&self) -> usize {
+        self.game.play_history.len()
+    }
+
+    /// Show the position after the first `n` plays of the current line.
+    fn go_to_play(&mut self, n: usize) {
+        let n = min(n, self.line.len());
+        while self.current_play() > n {
+            self.game.undo_last_play();
+        }
+        while self.current_play() < n {
+            let play = self.line[self.current_play()];
+            // All plays were checked when the view was created.
+            self.game.do_play(play).expect("Saved play should be legal.");
+        }
+    }
+
+    /// Switch to showing `line`, at the position after its first `n` plays.
+    fn go_to_line(&mut self, line: Vec<Play>, n: usize) {
+        let common = self.line.iter().zip(&line).take_while(|(a, b)| a == b).count();
+        self.go_to_play(min(common, self.current_play()));
+        self.line = line;
+        self.go_to_play(n);
+    }
+
+    /// Advance to the next play if autoplay is on and the current position has been shown for long
+    /// enough.
+    fn handle_autoplay(&mut self, ctx: &egui::Context) {
+        if !self.autoplay {
+            return
+        }
+        if self.current_play() >= self.line.len() {
+            self.autoplay = false;
+            return
+        }
+        let now = ctx.input(|i| i.time);
+        let elapsed = now - self.last_step_time;
+        if elapsed >= self.secs_per_play {
+            self.go_to_play(self.current_play() + 1);
+            self.last_step_time = now;
+            ctx.request_repaint_after_secs(self.secs_per_play as f32);
+        } else {
+            ctx.request_repaint_after_secs((self.secs_per_play - elapsed) as f32);
+        }
+    }
+
+    /// Show the plays of `line`, which follows on from the plays in `prefix`, with each variation
+    /// indented below the play it is an alternative to. Returns the line (from the start of the
+    /// game) and the number of plays into it to jump to, if a play was clicked.
+    fn line_ui(&self, ui: &mut egui::Ui, line: &Line, prefix: &[Play]) -> Option<(Vec<Play>, usize)> {
+        let mut selected = None;
+        let full_line = || [prefix, &line.plays].concat();
+        let is_main_line = std::ptr::eq(line, &self.tree);
+        for i in 0..=line.plays.len() {
+            if let Some(play) = line.plays.get(i) {
+                let n = prefix.len() + i + 1;
+                let is_current = self.current_play() == n
+                    && self.line.starts_with(prefix)
+                    && self.line[prefix.len()..].starts_with(&line.plays[..=i]);
+                let annotation = if is_main_line { self.play_annotation(i) } else { None };
+                let mut text = RichText::new(format!("{n}. {play}"));
+                if let Some(j) = annotation.and_then(|a| a.judgement()) {
+                    text = RichText::new(format!("{n}. {play}{}", j.symbol())).color(judgement_color(j));
+                }
+                let mut label = ui.selectable_label(is_current, text);
+                if let Some(a) = annotation {
+                    label = label.on_hover_text(describe_annotation(n, *play, &a));
+                }
+                if is_current && self.autoplay {
+                    label.scroll_to_me(None);
+                }
+                if label.clicked() {
+                    selected = Some((full_line(), n));
+                }
+            }
+            for (_, v) in line.variations.iter().filter(|(bp, _)| *bp == i) {
+                let v_prefix = [prefix, &line.plays[..i]].concat();
+                ui.indent(("variation", prefix.len() + i), |ui| {
+                    if let Some(s) = self.line_ui(ui, v, &v_prefix) {
+                        selected = Some(s);
+                    }
+                });
+            }
+        }
+        selected
+    }
+
+    /// The list of plays and variations, any of which can be clicked to jump to the position after
+    /// it.
+    fn play_list_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+            let mut selected = None;
+            if ui.selectable_label(self.current_play() == 0, "Start").clicked() {
+                selected = Some((self.line.clone(), 0));
+            }
+            if let Some(s) = self.line_ui(ui, &self.tree, &[]) {
+                selected = Some(s);
+            }
+            if let Some((line, n)) = selected {
+                self.autoplay = false;
+                self.go_to_line(line, n);
+            }
+        });
+    }
+
+    /// The AI's review of the main line: a graph of the winning chances, each side's accuracy and
+    /// the assessment of the play that led to the position being shown.
+    fn annotation_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(annotation) = &self.annotation else { return };
+        if annotation.complete {
+            ui.label(RichText::new("Review").strong());
+        } else {
+            ui.label(format!(
+                "Reviewing position {} of {}...",
+                annotation.evals.len() + 1,
+                annotation.play_count + 1
+            ));
+        }
+        let n = self.current_play();
+        let on_main_line = self.on_main_line();
+        let clicked = eval_graph(ui, annotation, on_main_line.then_some(n));
+        for side in [Attacker, Defender] {
+            let Some(accuracy) = annotation.accuracy(side) else { continue };
+            let counts: Vec<String> = [Judgement::Blunder, Judgement::Mistake, Judgement::Inaccuracy].iter()
+                .map(|j| {
+                    let count = annotation.plays.iter()
+                        .flatten()
+                        .filter(|a| a.side == side && a.judgement() == Some(*j))
+                        .count();
+                    format!("{count} {}{}", j.name(), if count == 1 { "" } else { "s" })
+                })
+                .collect();
+            ui.label(format!("{side:?}: {accuracy:.0}% accuracy, {}.", counts.join(", ")));
+        }
+        if let Some(a) = n.checked_sub(1).filter(|_| on_main_line).and_then(|i| self.play_annotation(i)) {
+            ui.label(describe_annotation(n, self.line[n - 1], &a));
+        }
+        ui.separator();
+        if let Some(n) = clicked {
+            self.autoplay = false;
+            self.go_to_line(self.tree.plays.clone(), n);
+        }
+    }
+
+    /// Controls for starting a new game from the position being shown.
+    fn branch_ui(&mut self, ui: &mut egui::Ui) -> Option<ReplayAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            ui.label("AI side:");
+            egui::ComboBox::from_id_salt("branch_ai_side")
+                .selected_text(match self.branch_ai_side {
+                    Some(side) => format!("{side:?}"),
+                    None => "None".to_string()
+                })
+                .show_ui(ui, |combo_box| {
+                    combo_box.selectable_value(&mut self.branch_ai_side, None, "None");
+                    for side in [pieces::Side::Attacker, pieces::Side::Defender] {
+                        combo_box.selectable_value(&mut self.branch_ai_side, Some(side), format!("{side:?}"));
+                    }
+                });
+            let can_branch = self.game.state.status == Ongoing;
+            if ui.add_enabled(can_branch, egui::Button::new("Play from here")).clicked() {
+                let mut setup = self.setup.clone();
+                setup.ai_side = self.branch_ai_side;
+                action = Some(ReplayAction::PlayFromHere {
+                    archive_index: self.archive_index,
+                    setup,
+                    plays: self.line[..self.current_play()].to_vec()
+                });
+            }
+            if ui.button("Analyse").clicked() {
+                action = Some(ReplayAction::Analyse {
+                    archive_index: self.archive_index,
+                    setup: self.setup.clone(),
+                    plays: self.line[..self.current_play()].to_vec()
+                });
+            }
+        });
+        action
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<ReplayAction> {
+        let mut action: Option<ReplayAction> = None;
+        self.handle_autoplay(ctx);
+        self.receive_annotation(ctx);
+        let bottom_panel_height = ctx.screen_rect().max.y * 0.25;
+
+        egui::TopBottomPanel::bottom("replay_controls").exact_height(bottom_panel_height).show(ctx, |ui| {
+            ui.with_layout(Layout::bottom_up(Align::Min), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Back").clicked() {
+                        action = Some(ReplayAction::Back);
+                    }
+                    ui.separator();
+                    let n = self.current_play();
+                    let mut target = None;
+                    if ui.add_enabled(n > 0, egui::Button::new("⏮")).clicked() {
+                        target = Some(0);
+                    }
+                    if ui.add_enabled(n > 0, egui::Button::new("⏴")).clicked() {
+                        target = Some(n - 1);
+                    }
+                    let at_end = n >= self.line.len();
+                    let play_pause = if self.autoplay { "⏸" } else { "▶" };
+                    if ui.add_enabled(!at_end, egui::Button::new(play_pause)).clicked() {
+                        self.autoplay = !self.autoplay;
+                        // Make the first play straight away
+                        self.last_step_time = f64::NEG_INFINITY;
+                        ctx.request_repaint();
+                    }
+                    if ui.add_enabled(!at_end, egui::Button::new("⏵")).clicked() {
+                        target = Some(n + 1);
+                    }
+                    if ui.add_enabled(!at_end, egui::Button::new("⏭")).clicked() {
+                        target = Some(self.line.len());
+                    }
+                    ui.separator();
+                    ui.label("Seconds per play:");
+                    ui.add(egui::Slider::new(&mut self.secs_per_play, 0.2..=5.0));
+                    if let Some(n) = target {
+                        self.autoplay = false;
+                        self.go_to_play(n);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Jump to play:");
+                    let mut n = self.current_play();
+                    let slider = egui::Slider::new(&mut n, 0..=self.line.len())
+                        .suffix(format!(" of {}", self.line.len()));
+                    if ui.add(slider).changed() {
+                        self.autoplay = false;
+                        self.go_to_play(n);
+                    }
+                });
+                if let Some(a) = self.branch_ui(ui) {
+                    action = Some(a);
+                }
+                ui.label(RichText::new(&self.title).strong());
+            })
+        });
+        egui::SidePanel::right("replay_plays").show(ctx, |ui| {
+            self.annotation_ui(ui);
+            self.play_list_ui(ui);
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // The board is square, so it fits in whatever is left after the side panel
+            let board_side_px = ui.available_width().min(ui.available_height());
+            self.board_ui.update(&self.game, ctx, ui, board_side_px);
+        });
+        action
+    }
+
+}
@@ -0,0 +1,129 @@
+use crate::ai::{AiEngine, Difficulty};
+use crate::custom_rules::{preset_variants, CustomRules};
+use crate::game_play_view::GameSetup;
+use hnefatafl::pieces;
+use hnefatafl::play::Play;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Key under which the unfinished game is saved in eframe's storage.
+pub(crate) const SAVED_GAME_KEY: &str = "saved_game";
+
+/// Serializable version of [`pieces::Side`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub(crate) enum SavedSide {
+    Attacker,
+    Defender
+}
+
+impl From<pieces::Side> for SavedSide {
+    fn from(side: pieces::Side) -> Self {
+        match side {
+            pieces::Side::Attacker => SavedSide::Attacker,
+            pieces::Side::Defender => SavedSide::Defender
+        }
+    }
+}
+
+impl From<SavedSide> for pieces::Side {
+    fn from(side: SavedSide) -> Self {
+        match side {
+            SavedSide::Attacker => pieces::Side::Attacker,
+            SavedSide::Defender => pieces::Side::Defender
+        }
+    }
+}
+
+/// A game's setup and the plays made so far, in a form that can be saved and later replayed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct SavedGame {
+    pub(crate) ruleset_name: String,
+    /// The preset variant the rules are based on.
+    pub(crate) base_variant: String,
+    pub(crate) rules: CustomRules,
+    pub(crate) starting_board: String,
+    pub(crate) ai_side: Option<SavedSide>,
+    pub(crate) ai_time_secs: u64,
+    #[serde(default)]
+    pub(crate) ai_difficulty: Difficulty,
+    #[serde(default)]
+    pub(crate) ai_engine: AiEngine,
+    /// Plays made so far, in the format accepted by `Play::from_str` (eg, `d1-d4`).
+    pub(crate) plays: Vec<String>,
+    /// Whether `ai_side` was played by a remote player over the network rather than the AI.
+    #[serde(default)]
+    pub(crate) remote: bool,
+    /// Identifies the game, so that it is only archived once however many times it is finished
+    /// (eg, after undoing the last play and finishing it differently). Zero if not known.
+    #[serde(default)]
+    pub(crate) id: u64
+}
+
+impl SavedGame {
+
+    pub(crate) fn new(setup: &GameSetup, plays: impl Iterator<Item = Play>) -> Self {
+        Self {
+            ruleset_name: setup.ruleset_name.clone(),
+            base_variant: setup.base_variant.clone(),
+            rules: CustomRules::from_ruleset(&setup.ruleset),
+            starting_board: setup.starting_board.clone(),
+            ai_side: setup.ai_side.map(SavedSide::from),
+            ai_time_secs: setup.ai_time.as_secs(),
+            ai_difficulty: setup.ai_difficulty,
+            ai_engine: setup.ai_engine,
+            plays: plays.map(|p| p.to_string()).collect(),
+            remote: false,
+            id: 0
+        }
+    }
+
+    /// Reconstruct the game's setup.
+    pub(crate) fn setup(&self) -> Result<GameSetup, String> {
+        let (base_rules, _) = preset_variants().remove(&self.base_variant)
+            .ok_or(format!("Unknown variant {:?}.", self.base_variant))?;
+        Ok(GameSetup {
+            ruleset: self.rules.apply(base_rules),
+            base_variant: self.base_variant.clone(),
+            ruleset_name: self.ruleset_name.clone(),
+            starting_board: self.starting_board.clone(),
+            ai_side: self.ai_side.map(pieces::Side::from),
+            ai_time: Duration::from_secs(self.ai_time_secs),
+            ai_difficulty: self.ai_difficulty,
+            ai_engine: self.ai_engine,
+            opening_book: None,
+            tablebase: None,
+            network: None
+        })
+    }
+
+    /// Parse the saved plays.
+    pub(crate) fn plays(&self) -> Result<Vec<Play>, String> {
+        parse_plays(&self.plays)
+    }
+
+    /// A new game in the named preset variant, with humans playing both sides.
+    #[cfg(test)]
+    pub(crate) fn preset(variant: &str) -> Self {
+        let (ruleset, starting_board) = preset_variants().remove(variant).expect("Unknown variant.");
+        Self {
+            ruleset_name: variant.to_string(),
+            base_variant: variant.to_string(),
+            rules: CustomRules::from_ruleset(&ruleset),
+            starting_board,
+            ai_side: None,
+            ai_time_secs: 1,
+            ai_difficulty: Difficulty::default(),
+            ai_engine: AiEngine::default(),
+            plays: vec![],
+            remote: false,
+            id: 0
+        }
+    }
+}
+
+/// Parse plays saved in the format accepted by `Play::from_str`.
+pub(crate) fn parse_plays(plays: &[String]) -> Result<Vec<Play>, String> {
+    plays.iter()
+        .map(|p| p.parse::<Play>().map_err(|e| format!("Could not parse play {p:?}: {e:?}")))
+        .collect()
+}
@@ -0,0 +1,401 @@
+use crate::online::{ClientMessage, LobbyGame, ServerMessage};
+use crate::saved_game::{SavedGame, SavedSide};
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameStatus::Ongoing;
+use hnefatafl::pieces;
+use hnefatafl::play::Play;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+
+pub(crate) const SERVE_USAGE: &str = "Usage: hnefatafl-egui serve [port]";
+/// The port the server listens on by default.
+pub(crate) const DEFAULT_SERVER_PORT: u16 = 8787;
+/// How long each connection waits for a message before checking for messages to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A game on the server.
+struct ServerGame {
+    /// The game's setup and the plays made so far.
+    saved: SavedGame,
+    /// The server's copy of the game, used to check that plays are legal.
+    game: Game<LargeBasicBoardState>,
+    /// The side played by the player who created the game.
+    host_side: SavedSide,
+    /// The connections of the players, by side. The guest is missing until someone joins.
+    players: BTreeMap<SavedSide, u64>,
+    spectators: Vec<u64>,
+    /// The side that has offered a draw, if the offer has not been answered.
+    draw_offer: Option<SavedSide>,
+    /// Whether the game has ended (by the rules, or by resignation or agreement).
+    finished: bool
+}
+
+impl ServerGame {
+
+    fn new(saved: SavedGame, host_side: SavedSide, host: u64) -> Result<Self, String> {
+        let setup = saved.setup()?;
+        let mut game = Game::new(setup.ruleset, &setup.starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        for play in saved.plays()? {
+            game.do_play(play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+        }
+        let finished = game.state.status != Ongoing;
+        Ok(Self {
+            saved,
+            game,
+            host_side,
+            players: BTreeMap::from([(host_side, host)]),
+            spectators: vec![],
+            draw_offer: None,
+            finished
+        })
+    }
+
+    fn side_of(&self, conn: u64) -> Option<SavedSide> {
+        self.players.iter().find(|(_, c)| **c == conn).map(|(s, _)| *s)
+    }
+
+    /// All connections taking part in the game, as players or spectators.
+    fn connections(&self) -> impl Iterator<Item = u64> + '_ {
+        self.players.values().chain(&self.spectators).copied()
+    }
+
+    /// Check that the player of `side` can make `play`, and make it.
+    fn play(&mut self, side: SavedSide, play: &str) -> Result<(), String> {
+        if self.finished {
+            return Err("The game is over.".to_string())
+        }
+        if self.players.len() < 2 {
+            return Err("Waiting for an opponent to join.".to_string())
+        }
+        if pieces::Side::from(side) != self.game.state.side_to_play {
+            return Err("It is not your turn.".to_string())
+        }
+        let play: Play = play.parse().map_err(|e| format!("Could not parse play {play:?}: {e:?}"))?;
+        self.game.do_play(play).map_err(|e| format!("Illegal play {play}: {e:?}"))?;
+        self.saved.plays.push(play.to_string());
+        self.draw_offer = None;
+        self.finished = self.game.state.status != Ongoing;
+        Ok(())
+    }
+}
+
+/// The state shared by all connections.
+#[derive(Default)]
+struct Lobby {
+    next_id: u64,
+    /// Channels for sending messages to each connection, by connection ID.
+    clients: HashMap<u64, Sender<ServerMessage>>,
+    /// The game each connection is playing or watching, if any.
+    client_games: HashMap<u64, u64>,
+    games: BTreeMap<u64, ServerGame>
+}
+
+impl Lobby {
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn send(&self, conn: u64, msg: ServerMessage) {
+        if let Some(client) = self.clients.get(&conn) {
+            // The connection may just have closed, in which case it will soon be removed.
+            let _ = client.send(msg);
+        }
+    }
+
+    fn error(&self, conn: u64, reason: impl Into<String>) {
+        self.send(conn, ServerMessage::Error { reason: reason.into() });
+    }
+
+    /// Send a message to everyone in the game with the given ID except `except`.
+    fn broadcast(&self, game_id: u64, except: u64, msg: ServerMessage) {
+        if let Some(game) = self.games.get(&game_id) {
+            for conn in game.connections().filter(|c| *c != except) {
+                self.send(conn, msg.clone());
+            }
+        }
+    }
+
+    fn game_list(&self) -> Vec<LobbyGame> {
+        self.games.iter()
+            .filter(|(_, g)| !g.finished)
+            .map(|(id, g)| LobbyGame {
+                id: *id,
+                ruleset_name: g.saved.ruleset_name.clone(),
+                host_side: g.host_side,
+                started: g.players.len() == 2,
+                spectators: g.spectators.len()
+            })
+            .collect()
+    }
+
+    /// Send the list of games to everyone browsing the lobby (ie, not in a game).
+    fn update_lobby(&self) {
+        let games = self.game_list();
+        for conn in self.clients.keys().filter(|c| !self.client_games.contains_key(c)) {
+            self.send(*conn, ServerMessage::Games { games: games.clone() });
+        }
+    }
+
+    fn add_client(&mut self, client: Sender<ServerMessage>) -> u64 {
+        let conn = self.new_id();
+        self.clients.insert(conn, client);
+        conn
+    }
+
+    fn remove_client(&mut self, conn: u64) {
+        self.leave(conn);
+        self.clients.remove(&conn);
+    }
+
+    /// Remove `conn` from the game it is in, if any. Games are removed once both players have left.
+    fn leave(&mut self, conn: u64) {
+        let Some(game_id) = self.client_games.remove(&conn) else { return };
+        let Some(game) = self.games.get_mut(&game_id) else { return };
+        game.spectators.retain(|c| *c != conn);
+        if let Some(side) = game.side_of(conn) {
+            game.players.remove(&side);
+            // The game can't continue without both players.
+            game.finished = true;
+            let abandoned = game.players.is_empty();
+            self.broadcast(game_id, conn, ServerMessage::Left { side });
+            if abandoned {
+                if let Some(game) = self.games.remove(&game_id) {
+                    for spectator in game.spectators {
+                        self.client_games.remove(&spectator);
+                    }
+                }
+            }
+        }
+        self.update_lobby();
+    }
+
+    fn handle(&mut self, conn: u64, msg: ClientMessage) {
+        let game_id = self.client_games.get(&conn).copied();
+        let side = game_id.and_then(|id| self.games.get(&id)).and_then(|g| g.side_of(conn));
+        match msg {
+            ClientMessage::ListGames => self.send(conn, ServerMessage::Games { games: self.game_list() }),
+            ClientMessage::CreateGame { game, host_side } => {
+                self.leave(conn);
+                match ServerGame::new(game, host_side, conn) {
+                    Ok(game) => {
+                        let id = self.new_id();
+                        self.games.insert(id, game);
+                        self.client_games.insert(conn, id);
+                        self.send(conn, ServerMessage::Created { id });
+                        self.update_lobby();
+                    },
+                    Err(e) => self.error(conn, e)
+                }
+            },
+            ClientMessage::JoinGame { id } => {
+                let Some(game) = self.games.get_mut(&id).filter(|g| g.players.len() == 1 && !g.finished) else {
+                    return self.error(conn, format!("Game {id} cannot be joined."))
+                };
+                let host_side = game.host_side;
+                let host = game.players[&host_side];
+                let guest_side = SavedSide::from(pieces::Side::from(host_side).other());
+                game.players.insert(guest_side, conn);
+                let saved = game.saved.clone();
+                self.client_games.insert(conn, id);
+                self.send(host, ServerMessage::Started { id, game: saved.clone(), side: Some(host_side) });
+                self.send(conn, ServerMessage::Started { id, game: saved, side: Some(guest_side) });
+                self.update_lobby();
+            },
+            ClientMessage::Watch { id } => {
+                let Some(game) = self.games.get_mut(&id) else {
+                    return self.error(conn, format!("No game with ID {id}."))
+                };
+                game.spectators.push(conn);
+                let saved = game.saved.clone();
+                self.client_games.insert(conn, id);
+                self.send(conn, ServerMessage::Started { id, game: saved, side: None });
+                self.update_lobby();
+            },
+            ClientMessage::Leave => self.leave(conn),
+            msg => {
+                // The remaining messages can only be sent by a player in a game.
+                let (Some(game_id), Some(side)) = (game_id, side) else {
+                    return self.error(conn, "You are not playing a game.")
+                };
+                let game = self.games.get_mut(&game_id).expect("Game should exist.");
+                let to_send = match msg {
+                    ClientMessage::Play { play } => match game.play(side, &play) {
+                        Ok(()) => ServerMessage::Played { play },
+                        Err(e) => return self.error(conn, e)
+                    },
+                    _ if game.finished => return self.error(conn, "The game is over."),
+                    ClientMessage::Resign => {
+                        game.finished = true;
+                        ServerMessage::Resigned { side }
+                    },
+                    ClientMessage::OfferDraw => {
+                        game.draw_offer = Some(side);
+                        ServerMessage::DrawOffered { side }
+                    },
+                    ClientMessage::AcceptDraw if game.draw_offer.is_some_and(|s| s != side) => {
+                        game.finished = true;
+                        ServerMessage::DrawAccepted
+                    },
+                    ClientMessage::DeclineDraw if game.draw_offer.is_some_and(|s| s != side) => {
+                        game.draw_offer = None;
+                        ServerMessage::DrawDeclined
+                    },
+                    _ => return self.error(conn, "No draw has been offered.")
+                };
+                let finished = game.finished;
+                self.broadcast(game_id, conn, to_send);
+                if finished {
+                    self.update_lobby();
+                }
+            }
+        }
+    }
+}
+
+/// Serve a single WebSocket connection until it closes.
+fn handle_connection(stream: TcpStream, lobby: Arc<Mutex<Lobby>>) {
+    let Ok(mut socket) = tungstenite::accept(stream) else { return };
+    if socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return
+    }
+    let (tx, rx) = channel();
+    let conn = lobby.lock().unwrap().add_client(tx);
+    'connection: loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let mut lobby = lobby.lock().unwrap();
+                match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(msg) => lobby.handle(conn, msg),
+                    Err(e) => lobby.error(conn, format!("Invalid message: {e}"))
+                }
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {},
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+            Err(_) => break
+        }
+        while let Ok(msg) = rx.try_recv() {
+            let text = serde_json::to_string(&msg).expect("Message should be serializable.");
+            if socket.send(Message::Text(text)).is_err() {
+                break 'connection
+            }
+        }
+    }
+    lobby.lock().unwrap().remove_client(conn);
+}
+
+/// Accept connections on `listener` forever, serving each on its own thread.
+pub(crate) fn serve(listener: TcpListener) {
+    let lobby = Arc::new(Mutex::new(Lobby::default()));
+    for stream in listener.incoming().flatten() {
+        let lobby = lobby.clone();
+        thread::spawn(move || handle_connection(stream, lobby));
+    }
+}
+
+/// Run the lobby server from the command line.
+pub(crate) fn run_server_command(args: &[String]) -> Result<(), String> {
+    let port = match args {
+        [] => DEFAULT_SERVER_PORT,
+        [port] => port.parse().map_err(|_| format!("Invalid port {port:?}.\n{SERVE_USAGE}"))?,
+        _ => return Err(SERVE_USAGE.to_string())
+    };
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .map_err(|e| format!("Could not listen on port {port}: {e}"))?;
+    println!("Lobby server listening on ws://0.0.0.0:{port}");
+    serve(listener);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::online::OnlineClient;
+    use std::time::Instant;
+
+    /// How long to wait for a message from the server before failing.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Start a server on a free port and connect a client to it.
+    fn start_server() -> (u16, OnlineClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || serve(listener));
+        (port, connect(port))
+    }
+
+    fn connect(port: u16) -> OnlineClient {
+        OnlineClient::connect(&format!("ws://127.0.0.1:{port}")).unwrap()
+    }
+
+    /// Wait for the next message from the server, ignoring updates to the list of games.
+    fn recv(client: &mut OnlineClient) -> ServerMessage {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            match client.poll().unwrap() {
+                Some(ServerMessage::Games { .. }) => {},
+                Some(msg) => return msg,
+                None => thread::sleep(Duration::from_millis(10))
+            }
+        }
+        panic!("Timed out waiting for the server.")
+    }
+
+    fn send_play(client: &mut OnlineClient, play: &str) {
+        client.send(&ClientMessage::Play { play: play.to_string() });
+    }
+
+    fn expect_played(client: &mut OnlineClient, expected: &str) {
+        match recv(client) {
+            ServerMessage::Played { play } => assert_eq!(play, expected),
+            other => panic!("Expected {expected}, got {other:?}.")
+        }
+    }
+
+    #[test]
+    fn games_are_created_joined_played_and_watched() {
+        let (port, mut host) = start_server();
+        let mut guest = connect(port);
+        let mut spectator = connect(port);
+
+        host.send(&ClientMessage::CreateGame {
+            game: SavedGame::preset("Brandubh"),
+            host_side: SavedSide::Attacker
+        });
+        let ServerMessage::Created { id } = recv(&mut host) else { panic!("Game not created.") };
+        guest.send(&ClientMessage::JoinGame { id });
+        assert!(matches!(recv(&mut guest), ServerMessage::Started { side: Some(SavedSide::Defender), .. }));
+        assert!(matches!(recv(&mut host), ServerMessage::Started { side: Some(SavedSide::Attacker), .. }));
+        spectator.send(&ClientMessage::Watch { id });
+        assert!(matches!(recv(&mut spectator), ServerMessage::Started { side: None, .. }));
+
+        send_play(&mut host, "d1-c1");
+        expect_played(&mut guest, "d1-c1");
+        expect_played(&mut spectator, "d1-c1");
+
+        // Plays out of turn, and plays that break the rules, are rejected.
+        send_play(&mut host, "c1-b1");
+        assert!(matches!(recv(&mut host), ServerMessage::Error { .. }));
+        send_play(&mut guest, "d3-d1");
+        assert!(matches!(recv(&mut guest), ServerMessage::Error { .. }));
+
+        send_play(&mut guest, "d3-c3");
+        expect_played(&mut host, "d3-c3");
+        expect_played(&mut spectator, "d3-c3");
+
+        guest.send(&ClientMessage::Resign);
+        assert!(matches!(recv(&mut host), ServerMessage::Resigned { side: SavedSide::Defender }));
+        assert!(matches!(recv(&mut spectator), ServerMessage::Resigned { side: SavedSide::Defender }));
+    }
+}
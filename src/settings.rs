@@ -0,0 +1,115 @@
+use crate::ai::{AiEngine, Difficulty};
+use crate::game_setup_view::difficulty_combo_box;
+use crate::online::DEFAULT_SERVER_URL;
+use egui::{RichText, ThemePreference};
+use serde::{Deserialize, Serialize};
+
+/// Key under which settings are saved in eframe's storage.
+pub(crate) const SETTINGS_KEY: &str = "settings";
+
+/// User preferences, persisted between sessions via eframe's storage (a file on native, local
+/// storage on the web). The window size and position are persisted by eframe itself on native.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// The variant last selected in the game setup screen.
+    pub(crate) variant: Option<String>,
+    /// The AI side last selected in the game setup screen.
+    pub(crate) ai_side: Option<String>,
+    /// The AI time per move (in seconds) last selected in the game setup screen.
+    pub(crate) ai_time: u8,
+    /// The AI difficulty last selected in the game setup screen.
+    pub(crate) ai_difficulty: Difficulty,
+    /// The AI engine (and its settings) last selected in the game setup screen.
+    pub(crate) ai_engine: AiEngine,
+    /// Whether the AI evaluates positions with a trained network, for variants that have one.
+    pub(crate) use_network_eval: bool,
+    /// Whether the AI analyses games being replayed, to point out mistakes.
+    pub(crate) annotate_games: bool,
+    pub(crate) theme: ThemePreference,
+    /// Whether to animate pieces moving on the board.
+    pub(crate) animate_moves: bool,
+    /// Whether to draw the board rotated by 180 degrees.
+    pub(crate) flip_board: bool,
+    /// URL of the lobby server last used for online games.
+    pub(crate) server_url: String
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            variant: None,
+            ai_side: None,
+            ai_time: 5,
+            ai_difficulty: Difficulty::default(),
+            ai_engine: AiEngine::default(),
+            use_network_eval: false,
+            annotate_games: true,
+            theme: ThemePreference::System,
+            animate_moves: true,
+            flip_board: false,
+            server_url: DEFAULT_SERVER_URL.to_string()
+        }
+    }
+}
+
+pub(crate) enum SettingsAction {
+    /// Save the edited settings and return to the game setup screen.
+    Save(Settings),
+    /// Discard any changes and return to the game setup screen.
+    Cancel
+}
+
+pub(crate) struct SettingsView {
+    settings: Settings
+}
+
+impl SettingsView {
+
+    pub(crate) fn new(settings: Settings) -> Self {
+        Self { settings }
+    }
+
+    pub(crate) fn update(&mut self, ctx: &egui::Context) -> Option<SettingsAction> {
+        let mut action: Option<SettingsAction> = None;
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.label(RichText::new("Settings").heading());
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::Grid::new("settings_grid").show(ui, |ui| {
+                ui.label("Theme:");
+                self.settings.theme.radio_buttons(ui);
+                ui.end_row();
+                ui.label("Board:");
+                ui.vertical(|ui| {
+                    ui.checkbox(&mut self.settings.animate_moves, "Animate moves");
+                    ui.checkbox(&mut self.settings.flip_board, "Flip board");
+                });
+                ui.end_row();
+                ui.label("Default AI time per move:");
+                ui.add(egui::Slider::new(&mut self.settings.ai_time, 1..=60));
+                ui.end_row();
+                ui.label("Default AI difficulty:");
+                difficulty_combo_box(ui, "settings_ai_difficulty", &mut self.settings.ai_difficulty);
+                ui.end_row();
+                ui.label("AI evaluation:");
+                ui.checkbox(&mut self.settings.use_network_eval, "Use trained network where available")
+                    .on_hover_text("Networks are trained with the `train` subcommand of the native app.");
+                ui.end_row();
+                ui.label("Game review:");
+                ui.checkbox(&mut self.settings.annotate_games, "Point out mistakes in replayed games");
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    action = Some(SettingsAction::Save(self.settings.clone()));
+                }
+                if ui.button("Cancel").clicked() {
+                    action = Some(SettingsAction::Cancel);
+                }
+            });
+        });
+        action
+    }
+
+}
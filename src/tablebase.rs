@@ -0,0 +1,189 @@
+use crate::byte_reader::{fnv1a, ByteReader};
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::pieces::PieceType::King;
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::pieces::Side;
+use hnefatafl::rules::Ruleset;
+
+/// Identifies a tablebase file (and the version of its format).
+const MAGIC: &[u8; 8] = b"TAFLTB2\n";
+/// The value stored for a drawn position.
+const DRAW_VALUE: i16 = i16::MIN;
+
+/// The result of a position in a tablebase, from the point of view of the side to play.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TablebaseResult {
+    /// The side to play wins in this many plays (counting both sides' plays).
+    Win(u16),
+    /// The side to play loses in this many plays, however well it defends.
+    Loss(u16),
+    Draw
+}
+
+impl TablebaseResult {
+
+    /// Decode a value stored in a tablebase: positive for a win in that many plays, [`DRAW_VALUE`]
+    /// for a draw, and otherwise zero or negative for a loss.
+    fn from_value(value: i16) -> Self {
+        if value == DRAW_VALUE {
+            TablebaseResult::Draw
+        } else if value > 0 {
+            TablebaseResult::Win(value as u16)
+        } else {
+            TablebaseResult::Loss(value.unsigned_abs())
+        }
+    }
+
+    /// The side that wins with best play, if either, given the side to play.
+    pub(crate) fn winner(&self, side_to_play: Side) -> Option<Side> {
+        match self {
+            TablebaseResult::Win(_) => Some(side_to_play),
+            TablebaseResult::Loss(_) => Some(side_to_play.other()),
+            TablebaseResult::Draw => None
+        }
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            TablebaseResult::Win(n) => format!("win in {n} plays"),
+            TablebaseResult::Loss(n) => format!("loss in {n} plays"),
+            TablebaseResult::Draw => "draw".to_string()
+        }
+    }
+}
+
+/// The number of bits needed to store the index of a tile on a board of the given side length.
+fn tile_bits(side_len: u8) -> u32 {
+    let n_tiles = side_len as u32 * side_len as u32;
+    u32::BITS - (n_tiles - 1).leading_zeros()
+}
+
+/// The largest number of pieces for which a position on a board of the given side length can be
+/// packed into a `u64` key.
+pub(crate) fn max_supported_pieces(side_len: u8) -> u8 {
+    // Each piece takes the bits for its tile plus two for its type; one bit is for the side to play.
+    (63 / (tile_bits(side_len) + 2)) as u8
+}
+
+/// Pack the position in `state`, on a board of the given side length, into a key, or return `None`
+/// if it has more than `max_pieces` pieces. The key is made up of a code for each piece (its tile
+/// and type), largest first, followed by a bit for the side to play.
+pub(crate) fn position_key<T: BoardState>(state: &GameState<T>, side_len: u8, max_pieces: u8) -> Option<u64> {
+    let board = &state.board;
+    let n_pieces = board.count_pieces_of_side(Attacker) + board.count_pieces_of_side(Defender);
+    if n_pieces > max_pieces {
+        return None
+    }
+    let bits = tile_bits(side_len) + 2;
+    let mut codes: Vec<u64> = [Attacker, Defender].into_iter()
+        .flat_map(|side| board.occupied_by_side(side))
+        .map(|t| {
+            let piece = board.get_piece(t).expect("There should be a piece here.");
+            let piece_code = if piece.piece_type == King { 3 } else if piece.side == Attacker { 1 } else { 2 };
+            let index = t.row as u64 * side_len as u64 + t.col as u64;
+            (index << 2) | piece_code
+        })
+        .collect();
+    codes.sort_unstable_by(|a, b| b.cmp(a));
+    let key = codes.into_iter().fold(0u64, |key, code| (key << bits) | code);
+    Some((key << 1) | (state.side_to_play == Defender) as u64)
+}
+
+/// A fingerprint of every rule in `ruleset`, so that a tablebase is only used under exactly the
+/// rules it was generated under.
+pub(crate) fn ruleset_fingerprint(ruleset: &Ruleset) -> u64 {
+    fnv1a(format!("{ruleset:?}").as_bytes())
+}
+
+/// Results of every position with up to a given number of pieces in one variant, generated by
+/// retrograde analysis.
+pub(crate) struct Tablebase {
+    /// The name of the variant the tablebase is for.
+    pub(crate) variant: String,
+    /// The [`ruleset_fingerprint`] of the rules the tablebase was generated under.
+    pub(crate) rules_fingerprint: u64,
+    pub(crate) side_len: u8,
+    /// Positions with at most this many pieces (including the king) are covered.
+    pub(crate) max_pieces: u8,
+    /// Position keys (see [`position_key`]) and their values, sorted by key.
+    entries: Vec<(u64, i16)>
+}
+
+impl Tablebase {
+
+    /// Create a tablebase from unsorted `(key, value)` pairs, where the value is `None` for a draw,
+    /// positive for a win in that many plays and zero or negative for a loss.
+    pub(crate) fn new(
+        variant: String,
+        ruleset: &Ruleset,
+        side_len: u8,
+        max_pieces: u8,
+        entries: Vec<(u64, Option<i16>)>
+    ) -> Self {
+        let mut entries: Vec<(u64, i16)> = entries.into_iter()
+            .map(|(key, value)| (key, value.unwrap_or(DRAW_VALUE)))
+            .collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        Self { variant, rules_fingerprint: ruleset_fingerprint(ruleset), side_len, max_pieces, entries }
+    }
+
+    /// The number of positions in the tablebase.
+    pub(crate) fn positions(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The number of won or lost positions in the tablebase.
+    pub(crate) fn decided_positions(&self) -> usize {
+        self.entries.iter().filter(|(_, value)| *value != DRAW_VALUE).count()
+    }
+
+    /// Look up the position in `state`, returning `None` if the tablebase does not contain it.
+    pub(crate) fn probe<T: BoardState>(&self, logic: &GameLogic<T>, state: &GameState<T>) -> Option<TablebaseResult> {
+        if logic.board_geo.side_len != self.side_len {
+            return None
+        }
+        let key = position_key(state, self.side_len, self.max_pieces)?;
+        let i = self.entries.binary_search_by_key(&key, |(k, _)| *k).ok()?;
+        Some(TablebaseResult::from_value(self.entries[i].1))
+    }
+
+    /// Encode the tablebase in its on-disk format: a header (magic bytes, variant name, rules
+    /// fingerprint, board size and piece limit) followed by the sorted entries, each a
+    /// little-endian `u64` key and `i16` value.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 + self.variant.len() + self.entries.len() * 10);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.variant.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.variant.as_bytes());
+        bytes.extend_from_slice(&self.rules_fingerprint.to_le_bytes());
+        bytes.push(self.side_len);
+        bytes.push(self.max_pieces);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (key, value) in &self.entries {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a tablebase encoded by [`Tablebase::to_bytes`].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(bytes, MAGIC, "tablebase")?;
+        let variant = reader.string()?;
+        let rules_fingerprint = reader.u64()?;
+        let side_len = reader.u8()?;
+        let max_pieces = reader.u8()?;
+        let n_entries = reader.u32()? as usize;
+        let entries = reader.take(n_entries * 10)?
+            .chunks_exact(10)
+            .map(|e| (
+                u64::from_le_bytes(e[..8].try_into().unwrap()),
+                i16::from_le_bytes(e[8..].try_into().unwrap())
+            ))
+            .collect();
+        reader.finish()?;
+        Ok(Self { variant, rules_fingerprint, side_len, max_pieces, entries })
+    }
+}
@@ -0,0 +1,293 @@
+use crate::app::APP_NAME;
+use crate::book_builder::{output_path, variant_file_name, write_output};
+use crate::custom_rules::preset_variants;
+use crate::position_editor_view::grid_to_board_string;
+use crate::tablebase::{max_supported_pieces, position_key, ruleset_fingerprint, Tablebase};
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::board::state::BoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::state::GameState;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameOutcome::{Draw, Win};
+use hnefatafl::game::GameStatus::{Ongoing, Over};
+use hnefatafl::pieces::PieceType::Soldier;
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::pieces::{Piece, KING};
+use hnefatafl::rules::Ruleset;
+use hnefatafl::tiles::Tile;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+type State = GameState<LargeBasicBoardState>;
+
+pub(crate) const TABLEBASE_USAGE: &str = "\
+Usage: hnefatafl-egui tablebase <variant> [--pieces <n>] [--output <file>]
+
+Generate an endgame tablebase for a preset variant by retrograde analysis, covering every position
+with at most the given number of pieces (including the king; 3 by default). The tablebase is written
+to the directory the app loads tablebases from unless an output file is given. Generation is only
+practical on small boards such as Brandubh's: each extra piece multiplies the work by roughly the
+number of tiles.";
+
+/// The directory from which the app loads tablebases generated by the `tablebase` subcommand.
+pub(crate) fn tablebases_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_NAME).map(|d| d.join("tablebases"))
+}
+
+/// Load the tablebase for `variant` from [`tablebases_dir`], if there is one that was generated
+/// under the same rules.
+pub(crate) fn load_tablebase(variant: &str, ruleset: &Ruleset) -> Option<Tablebase> {
+    let path = tablebases_dir()?.join(variant_file_name(variant, "tb"));
+    let bytes = std::fs::read(&path).ok()?;
+    match Tablebase::from_bytes(&bytes) {
+        Ok(tb) if tb.variant == variant && tb.rules_fingerprint == ruleset_fingerprint(ruleset) => Some(tb),
+        Ok(_) => {
+            log::warn!("Ignoring tablebase {}, which was generated for different rules.", path.display());
+            None
+        },
+        Err(e) => {
+            log::warn!("Could not load tablebase {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Call `f` with every combination of `k` of `items`.
+fn for_each_combination(items: &[Tile], k: usize, chosen: &mut Vec<Tile>, f: &mut impl FnMut(&[Tile])) {
+    if chosen.len() == k {
+        f(chosen);
+        return
+    }
+    for (i, t) in items.iter().enumerate() {
+        chosen.push(*t);
+        for_each_combination(&items[i + 1..], k, chosen, f);
+        chosen.pop();
+    }
+}
+
+/// Every position (with either side to play) with the king and up to `max_pieces - 1` soldiers.
+/// The king is never placed on a corner, and soldiers are never placed on the throne or a corner.
+fn enumerate_positions(ruleset: Ruleset, logic: &GameLogic<LargeBasicBoardState>, max_pieces: u8) -> Vec<State> {
+    let side_len = logic.board_geo.side_len;
+    let special_tiles = logic.board_geo.special_tiles;
+    let tiles: Vec<Tile> = logic.board_geo.iter_tiles()
+        .filter(|t| !special_tiles.corners.contains(*t))
+        .collect();
+    let mut positions = vec![];
+    for king_tile in &tiles {
+        let soldier_tiles: Vec<Tile> = tiles.iter()
+            .copied()
+            .filter(|t| t != king_tile && *t != special_tiles.throne)
+            .collect();
+        for n_soldiers in 0..max_pieces as usize {
+            for_each_combination(&soldier_tiles, n_soldiers, &mut vec![], &mut |chosen| {
+                // Each bit of `defenders` says whether the corresponding soldier is a defender.
+                for defenders in 0u32..(1 << n_soldiers) {
+                    let mut grid = vec![vec![None; side_len as usize]; side_len as usize];
+                    grid[king_tile.row as usize][king_tile.col as usize] = Some(KING);
+                    for (i, t) in chosen.iter().enumerate() {
+                        let side = if defenders & (1 << i) == 0 { Attacker } else { Defender };
+                        grid[t.row as usize][t.col as usize] = Some(Piece::new(Soldier, side));
+                    }
+                    let Ok(game) = Game::<LargeBasicBoardState>::new(ruleset, &grid_to_board_string(&grid)) else {
+                        continue
+                    };
+                    for side in [Attacker, Defender] {
+                        let mut state = game.state;
+                        state.side_to_play = side;
+                        positions.push(state);
+                    }
+                }
+            });
+        }
+    }
+    positions
+}
+
+/// What the plays available in a position lead to.
+#[derive(Default)]
+struct Successors {
+    /// Whether some play wins the game immediately.
+    immediate_win: bool,
+    /// Whether some play draws the game immediately, or leads to a position outside the table
+    /// (which is treated as a draw).
+    can_draw: bool,
+    /// The indices of the positions that the other plays lead to.
+    positions: Vec<u32>
+}
+
+fn successors(
+    logic: &GameLogic<LargeBasicBoardState>,
+    state: &State,
+    index: &HashMap<u64, u32>,
+    max_pieces: u8
+) -> Successors {
+    let mut succ = Successors::default();
+    for t in state.board.occupied_by_side(state.side_to_play) {
+        let Ok(plays) = logic.iter_plays(t, state) else { continue };
+        for vp in plays {
+            let new_state = logic.do_valid_play(vp, *state).new_state;
+            match new_state.status {
+                Over(Win(_, winner)) if winner == state.side_to_play => succ.immediate_win = true,
+                // A play that loses immediately is never better than any other play
+                Over(Win(_, _)) => {},
+                Over(Draw(_)) => succ.can_draw = true,
+                Ongoing => match position_key(&new_state, logic.board_geo.side_len, max_pieces)
+                    .and_then(|k| index.get(&k)) {
+                    Some(i) => succ.positions.push(*i),
+                    None => succ.can_draw = true
+                }
+            }
+        }
+    }
+    succ
+}
+
+/// Solve every position with up to `max_pieces` pieces. Each round decides the positions whose
+/// result is reached in exactly that many plays: on odd rounds, positions from which some play
+/// leads to a position lost in one play fewer; on even rounds, positions from which every play
+/// leads to a position already known to be won for the opponent.
+fn generate(variant: &str, ruleset: Ruleset, starting_board: &str, max_pieces: u8) -> Result<Tablebase, String> {
+    let game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    let logic = game.logic;
+    let side_len = logic.board_geo.side_len;
+    let max_supported = max_supported_pieces(side_len);
+    if max_pieces == 0 || max_pieces > max_supported {
+        return Err(format!("The number of pieces must be between 1 and {max_supported} for {variant}."))
+    }
+
+    let positions = enumerate_positions(ruleset, &logic, max_pieces);
+    println!("Enumerated {} positions.", positions.len());
+    let keys: Vec<u64> = positions.iter()
+        .map(|s| position_key(s, side_len, max_pieces).expect("Position should have few enough pieces."))
+        .collect();
+    let index: HashMap<u64, u32> = keys.iter().enumerate().map(|(i, k)| (*k, i as u32)).collect();
+    let succs: Vec<Successors> = positions.iter()
+        .map(|s| successors(&logic, s, &index, max_pieces))
+        .collect();
+    println!("Generated all plays.");
+
+    // Positive values are wins in that many plays, and zero or negative values are losses.
+    let mut values: Vec<Option<i16>> = succs.iter()
+        .map(|s| if s.immediate_win {
+            Some(1)
+        } else if s.positions.is_empty() && !s.can_draw {
+            // No plays available (or every play loses immediately)
+            Some(0)
+        } else {
+            None
+        })
+        .collect();
+    let mut idle_rounds = 0;
+    for round in 1..=i16::MAX {
+        let previous = values.clone();
+        let mut decided = 0usize;
+        for (i, s) in succs.iter().enumerate().filter(|(i, _)| previous[*i].is_none()) {
+            if round % 2 == 1 {
+                if s.positions.iter().any(|j| previous[*j as usize] == Some(1 - round)) {
+                    values[i] = Some(round);
+                    decided += 1;
+                }
+            } else if !s.can_draw && s.positions.iter().all(|j| previous[*j as usize].is_some_and(|v| v > 0)) {
+                values[i] = Some(-round);
+                decided += 1;
+            }
+        }
+        println!("Round {round}: decided {decided} positions.");
+        idle_rounds = if decided == 0 { idle_rounds + 1 } else { 0 };
+        if idle_rounds == 2 {
+            break
+        }
+    }
+
+    // Positions still undecided are draws
+    let entries = keys.into_iter().zip(values).collect();
+    Ok(Tablebase::new(variant.to_string(), &ruleset, side_len, max_pieces, entries))
+}
+
+/// Run the `tablebase` subcommand with the given arguments.
+pub(crate) fn run_tablebase_command(args: &[String]) -> Result<(), String> {
+    let Some((variant, mut rest)) = args.split_first() else {
+        return Err(TABLEBASE_USAGE.to_string())
+    };
+    let (ruleset, starting_board) = preset_variants().remove(variant.as_str())
+        .ok_or(format!("Unknown variant {variant:?}."))?;
+    let mut max_pieces = 3u8;
+    let mut output: Option<PathBuf> = None;
+    while let [flag, value, tail @ ..] = rest {
+        match flag.as_str() {
+            "--pieces" => max_pieces = value.parse()
+                .map_err(|_| format!("Invalid number of pieces {value:?}."))?,
+            "--output" => output = Some(PathBuf::from(value)),
+            _ => return Err(TABLEBASE_USAGE.to_string())
+        }
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(TABLEBASE_USAGE.to_string())
+    }
+    let output = output_path(output, tablebases_dir(), variant, "tb")?;
+
+    let tablebase = generate(variant, ruleset, &starting_board, max_pieces)?;
+    write_output(&output, tablebase.to_bytes())?;
+    println!(
+        "Wrote tablebase with {} positions ({} won or lost) to {}.",
+        tablebase.positions(),
+        tablebase.decided_positions(),
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tablebase::TablebaseResult;
+    use hnefatafl::pieces::Side;
+
+    /// A Brandubh position with the king on the top edge, between two open corners, and a single
+    /// attacker that cannot reach both corners in one play.
+    fn king_between_corners(ruleset: Ruleset, side_to_play: Side) -> State {
+        let mut grid = vec![vec![None; 7]; 7];
+        grid[0][3] = Some(KING);
+        grid[5][5] = Some(Piece::new(Soldier, Attacker));
+        let game = Game::<LargeBasicBoardState>::new(ruleset, &grid_to_board_string(&grid)).unwrap();
+        let mut state = game.state;
+        state.side_to_play = side_to_play;
+        state
+    }
+
+    #[test]
+    fn solves_brandubh_endings() {
+        let (ruleset, starting_board) = preset_variants().remove("Brandubh").unwrap();
+        let tb = generate("Brandubh", ruleset, &starting_board, 2).unwrap();
+        let game = Game::<LargeBasicBoardState>::new(ruleset, &starting_board).unwrap();
+        let logic = game.logic;
+
+        // The king escapes to either corner straight away.
+        let state = king_between_corners(ruleset, Defender);
+        assert_eq!(tb.probe(&logic, &state), Some(TablebaseResult::Win(1)));
+        // Whichever corner the attacker blocks, the king escapes to the other.
+        let state = king_between_corners(ruleset, Attacker);
+        assert_eq!(tb.probe(&logic, &state), Some(TablebaseResult::Loss(2)));
+        // The starting position has too many pieces to be in the table.
+        assert_eq!(tb.probe(&logic, &game.state), None);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let (ruleset, starting_board) = preset_variants().remove("Brandubh").unwrap();
+        let tb = generate("Brandubh", ruleset, &starting_board, 2).unwrap();
+        let bytes = tb.to_bytes();
+        let decoded = Tablebase::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.variant, tb.variant);
+        assert_eq!(decoded.rules_fingerprint, tb.rules_fingerprint);
+        assert_eq!(decoded.side_len, tb.side_len);
+        assert_eq!(decoded.max_pieces, tb.max_pieces);
+        assert_eq!(decoded.positions(), tb.positions());
+        assert_eq!(decoded.decided_positions(), tb.decided_positions());
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert!(Tablebase::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+use crate::ai::{all_plays, Ai, AiEngine, BasicAi, Difficulty, MctsAi, MctsConfig, PlayoutPolicy};
+use crate::archive::ArchivedResult;
+use crate::book_builder::parse_difficulty;
+use crate::custom_rules::preset_variants;
+use crate::neural_eval::EvalNetwork;
+use crate::neural_trainer::load_network_files;
+use crate::saved_game::SavedSide;
+use hnefatafl::aliases::LargeBasicBoardState;
+use hnefatafl::game::logic::GameLogic;
+use hnefatafl::game::Game;
+use hnefatafl::game::GameOutcome::{Draw, Win};
+use hnefatafl::game::GameStatus::{Ongoing, Over};
+use hnefatafl::pieces::Side;
+use hnefatafl::pieces::Side::{Attacker, Defender};
+use hnefatafl::play::Play;
+use hnefatafl::rules::Ruleset;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// Games are abandoned (and counted as draws) after this many plays.
+const MAX_GAME_PLIES: usize = 300;
+/// Attempts at finding a random opening that does not end the game before giving up.
+const OPENING_ATTEMPTS: usize = 100;
+/// The z-score of the 95% confidence interval given for Elo estimates.
+const CONFIDENCE_Z: f64 = 1.96;
+/// The probabilities of accepting the wrong hypothesis in an SPRT.
+const SPRT_ALPHA: f64 = 0.05;
+const SPRT_BETA: f64 = 0.05;
+/// The size of each alpha-beta engine's transposition table, in megabytes, by default. Small,
+/// because one table is allocated for each engine in each game being played.
+const DEFAULT_HASH_MB: usize = 16;
+
+pub(crate) const TOURNAMENT_USAGE: &str = "\
+Usage: hnefatafl-egui tournament --engine <spec> --engine <spec> [--engine <spec> ...] [--gauntlet]
+                                 [--variants <variant>,...] [--rounds <rounds>]
+                                 [--random-plies <plays>] [--threads <threads>]
+                                 [--hash <MB>] [--sprt <elo0>,<elo1>] [--log <file>]
+
+Play AI configurations against each other, headlessly, and estimate their relative strength. Every
+pair of engines plays (or with --gauntlet, the first engine plays each of the others) in each
+variant (all preset variants by default), from <rounds> random openings of <random-plies> plays
+(2 and 4 by default), playing each opening once from each side. Games are played in parallel on
+<threads> threads (one per core by default). Each alpha-beta engine's transposition table takes
+<MB> megabytes (16 by default) in each game being played.
+
+An engine spec is a comma-separated list of key=value settings:
+    name=<name>                 name to show in the results (the spec itself by default)
+    engine=ab|mcts              alpha-beta search (the default) or Monte Carlo tree search
+    time=<seconds>              time per play (1 by default)
+    depth=<plays>               maximum search depth (alpha-beta only)
+    difficulty=<level>          difficulty level (alpha-beta only; Maximum by default)
+    eval=handcrafted|network|<file>
+                                evaluation (alpha-beta only): the handcrafted evaluation (the
+                                default), the networks trained with `train`, or a network file
+    playouts=random|heuristic   playout policy (MCTS only)
+    cutoff=<plays>|none         plays after which playouts are evaluated (MCTS only)
+    exploration=<constant>      UCT exploration constant (MCTS only)
+    reuse=true|false            whether to reuse the search tree (MCTS only)
+
+Results are reported as wins, draws and losses with an Elo estimate and 95% error margin. With
+--sprt and exactly two engines, a sequential probability ratio test of whether the first engine is
+<elo1> rather than <elo0> Elo stronger than the second is run, stopping once it is decided. With
+--log, every game is written to a file in a PGN-like format, with results given as 1-0 for an
+attacker win, 0-1 for a defender win and 1/2-1/2 for a draw (or * for an abandoned game).";
+
+/// Which evaluation an alpha-beta engine uses.
+#[derive(Clone)]
+enum EvalChoice {
+    Handcrafted,
+    /// The networks trained by the `train` subcommand for each variant.
+    TrainedNetworks,
+    /// The network in the given file.
+    NetworkFile(PathBuf)
+}
+
+/// An AI configuration taking part in a tournament.
+#[derive(Clone)]
+struct EngineConfig {
+    name: String,
+    engine: AiEngine,
+    time_to_play: Duration,
+    max_depth: Option<u8>,
+    difficulty: Difficulty,
+    eval: EvalChoice,
+    /// The network to evaluate with in each variant, if any (resolved from `eval`).
+    networks: BTreeMap<String, Arc<EvalNetwork>>,
+    /// The size of the transposition table, in megabytes (alpha-beta only).
+    hash_mb: usize
+}
+
+impl EngineConfig {
+
+    /// Parse an engine spec (see [`TOURNAMENT_USAGE`]).
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = Self {
+            name: spec.to_string(),
+            engine: AiEngine::AlphaBeta,
+            time_to_play: Duration::from_secs(1),
+            max_depth: None,
+            difficulty: Difficulty::Maximum,
+            eval: EvalChoice::Handcrafted,
+            networks: BTreeMap::new(),
+            hash_mb: DEFAULT_HASH_MB
+        };
+        let mut mcts = MctsConfig::default();
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let (key, value) = setting.split_once('=')
+                .ok_or(format!("Invalid engine setting {setting:?}."))?;
+            let invalid = || format!("Invalid value for {key} in engine spec {spec:?}.");
+            match key {
+                "name" => config.name = value.to_string(),
+                "engine" => config.engine = match value {
+                    "ab" => AiEngine::AlphaBeta,
+                    "mcts" => AiEngine::Mcts(mcts),
+                    _ => return Err(invalid())
+                },
+                "time" => config.time_to_play = value.parse().map(Duration::from_secs_f64)
+                    .map_err(|_| invalid())?,
+                "depth" => config.max_depth = Some(value.parse().map_err(|_| invalid())?),
+                "difficulty" => config.difficulty = parse_difficulty(value).map_err(|_| invalid())?,
+                "eval" => config.eval = match value {
+                    "handcrafted" => EvalChoice::Handcrafted,
+                    "network" => EvalChoice::TrainedNetworks,
+                    path => EvalChoice::NetworkFile(PathBuf::from(path))
+                },
+                "playouts" => mcts.playout_policy = match value {
+                    "random" => PlayoutPolicy::Random,
+                    "heuristic" => PlayoutPolicy::Heuristic,
+                    _ => return Err(invalid())
+                },
+                "cutoff" => mcts.eval_cutoff = match value {
+                    "none" => None,
+                    n => Some(n.parse().map_err(|_| invalid())?)
+                },
+                "exploration" => mcts.exploration = value.parse().map_err(|_| invalid())?,
+                "reuse" => mcts.reuse_tree = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("Unknown engine setting {key:?}."))
+            }
+        }
+        if let AiEngine::Mcts(c) = &mut config.engine {
+            *c = mcts;
+        }
+        Ok(config)
+    }
+
+    /// Find the network to use in each of `variants` (with their board sizes), if the engine uses
+    /// one.
+    fn resolve_networks(&mut self, variants: &[(String, u8)]) -> Result<(), String> {
+        let available: BTreeMap<String, Arc<EvalNetwork>> = match &self.eval {
+            EvalChoice::Handcrafted => return Ok(()),
+            EvalChoice::TrainedNetworks => load_network_files(),
+            EvalChoice::NetworkFile(path) => {
+                let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+                let network = Arc::new(EvalNetwork::from_bytes(&bytes)
+                    .map_err(|e| format!("Could not load {}: {e}", path.display()))?);
+                variants.iter().map(|(v, _)| (v.clone(), network.clone())).collect()
+            }
+        };
+        for (variant, side_len) in variants {
+            match available.get(variant).filter(|n| n.side_len == *side_len) {
+                Some(network) => {
+                    self.networks.insert(variant.clone(), network.clone());
+                },
+                None => return Err(format!("{} has no network for {variant}.", self.name))
+            }
+        }
+        Ok(())
+    }
+
+    fn build_ai(
+        &self,
+        logic: GameLogic<LargeBasicBoardState>,
+        side: Side,
+        variant: &str
+    ) -> Box<dyn Ai<BoardState = LargeBasicBoardState>> {
+        match self.engine {
+            AiEngine::AlphaBeta => Box::new(
+                BasicAi::new(logic, side, self.time_to_play, self.difficulty)
+                    .with_hash_size(self.hash_mb)
+                    .with_max_depth(self.max_depth)
+                    .with_network(self.networks.get(variant).cloned())
+            ),
+            AiEngine::Mcts(config) => Box::new(MctsAi::new(logic, side, self.time_to_play, config))
+        }
+    }
+}
+
+/// A game to be played.
+#[derive(Clone)]
+struct Job {
+    variant: String,
+    round: usize,
+    opening: Vec<Play>,
+    /// Indices of the engines playing each side.
+    attacker: usize,
+    defender: usize
+}
+
+/// A finished (or abandoned) game.
+struct GameRecord {
+    job: Job,
+    plays: Vec<Play>,
+    /// The result, or `None` if the game was abandoned.
+    result: Option<ArchivedResult>
+}
+
+impl GameRecord {
+
+    /// The score of the attacking engine: 1 for a win, 0.5 for a draw and 0 for a loss.
+    fn attacker_score(&self) -> f64 {
+        match self.result {
+            Some(ArchivedResult::Win(SavedSide::Attacker)) => 1.0,
+            Some(ArchivedResult::Win(SavedSide::Defender)) => 0.0,
+            Some(ArchivedResult::Draw) | None => 0.5
+        }
+    }
+
+    fn result_tag(&self) -> &'static str {
+        match self.result {
+            Some(ArchivedResult::Win(SavedSide::Attacker)) => "1-0",
+            Some(ArchivedResult::Win(SavedSide::Defender)) => "0-1",
+            Some(ArchivedResult::Draw) => "1/2-1/2",
+            None => "*"
+        }
+    }
+
+    /// The game in a PGN-like format: tag pairs, then the numbered plays and the result.
+    fn to_log(&self, engines: &[EngineConfig]) -> String {
+        let mut log = format!(
+            "[Variant \"{}\"]\n[Round \"{}\"]\n[Attacker \"{}\"]\n[Defender \"{}\"]\n[OpeningPlies \"{}\"]\n[Result \"{}\"]\n\n",
+            self.job.variant,
+            self.job.round + 1,
+            engines[self.job.attacker].name,
+            engines[self.job.defender].name,
+            self.job.opening.len(),
+            self.result_tag()
+        );
+        let moves: Vec<String> = self.plays.chunks(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let plays: Vec<String> = pair.iter().map(|p| p.to_string()).collect();
+                format!("{}. {}", i + 1, plays.join(" "))
+            })
+            .collect();
+        log.push_str(&moves.join(" "));
+        log.push_str(&format!(" {}\n\n", self.result_tag()));
+        log
+    }
+}
+
+/// A sequence of random plays from the start of the game that does not end it.
+fn random_opening(ruleset: Ruleset, starting_board: &str, plies: usize, rng: &mut impl Rng) -> Result<Vec<Play>, String> {
+    for _ in 0..OPENING_ATTEMPTS {
+        let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        let mut plays = vec![];
+        while plays.len() < plies && game.state.status == Ongoing {
+            let candidates = all_plays(&game.logic, &game.state);
+            if candidates.is_empty() {
+                break
+            }
+            let vp = candidates[rng.gen_range(0..candidates.len())];
+            game.do_play(vp.play).map_err(|e| format!("Could not make play {vp}: {e:?}"))?;
+            plays.push(vp.play);
+        }
+        if game.state.status == Ongoing {
+            return Ok(plays)
+        }
+    }
+    Err("Could not find a random opening that does not end the game.".to_string())
+}
+
+/// Play the game described by `job`.
+fn play_game(job: Job, engines: &[EngineConfig]) -> Result<GameRecord, String> {
+    let (ruleset, starting_board) = preset_variants().remove(&job.variant)
+        .ok_or(format!("Unknown variant {:?}.", job.variant))?;
+    let mut game: Game<LargeBasicBoardState> = Game::new(ruleset, &starting_board)
+        .map_err(|e| format!("Could not create game: {e:?}"))?;
+    for play in &job.opening {
+        game.do_play(*play).map_err(|e| format!("Could not replay {play}: {e:?}"))?;
+    }
+    let mut attacker = engines[job.attacker].build_ai(game.logic, Attacker, &job.variant);
+    let mut defender = engines[job.defender].build_ai(game.logic, Defender, &job.variant);
+    while game.state.status == Ongoing && game.play_history.len() < MAX_GAME_PLIES {
+        let ai = if game.state.side_to_play == Attacker { &mut attacker } else { &mut defender };
+        let (vp, _) = ai.next_play(&game.state)
+            .map_err(|_| "The AI could not find a play.".to_string())?;
+        game.do_play(vp.play).map_err(|e| format!("The AI made an illegal play {vp}: {e:?}"))?;
+    }
+    let result = match game.state.status {
+        Over(Win(_, side)) => Some(ArchivedResult::Win(side.into())),
+        Over(Draw(_)) => Some(ArchivedResult::Draw),
+        Ongoing => None
+    };
+    Ok(GameRecord { job, plays: game.play_history.iter().map(|r| r.play).collect(), result })
+}
+
+/// Wins, draws and losses of one engine against another (or the field).
+#[derive(Clone, Copy, Default)]
+struct Record {
+    wins: u32,
+    draws: u32,
+    losses: u32
+}
+
+impl Record {
+
+    fn add(&mut self, score: f64) {
+        if score == 1.0 {
+            self.wins += 1;
+        } else if score == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The mean score per game and its variance.
+    fn score_and_variance(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let (w, d, l) = (self.wins as f64, self.draws as f64, self.losses as f64);
+        let score = (w + d / 2.0) / n;
+        let variance = (w * (1.0 - score).powi(2) + d * (0.5 - score).powi(2) + l * score.powi(2)) / n;
+        (score, variance)
+    }
+
+    /// The Elo difference implied by the record, and the margin of its 95% confidence interval.
+    fn elo(&self) -> Option<(f64, f64)> {
+        if self.games() == 0 {
+            return None
+        }
+        let (score, variance) = self.score_and_variance();
+        let margin = CONFIDENCE_Z * (variance / self.games() as f64).sqrt();
+        let (low, high) = (elo_from_score(score - margin), elo_from_score(score + margin));
+        Some((elo_from_score(score), (high - low) / 2.0))
+    }
+
+    /// The log-likelihood ratio of the hypothesis that the engine is `elo1` rather than `elo0` Elo
+    /// stronger than its opponent, using the normal approximation to the distribution of scores.
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0
+        }
+        let (score, variance) = self.score_and_variance();
+        if variance == 0.0 {
+            return 0.0
+        }
+        let (s0, s1) = (score_from_elo(elo0), score_from_elo(elo1));
+        self.games() as f64 * (s1 - s0) * (2.0 * score - s0 - s1) / (2.0 * variance)
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!("+{} ={} -{}", self.wins, self.draws, self.losses);
+        if let Some((elo, margin)) = self.elo() {
+            text.push_str(&format!(", Elo {elo:+.0} ± {margin:.0}"));
+        }
+        text
+    }
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Run the `tournament` subcommand with the given arguments.
+pub(crate) fn run_tournament_command(args: &[String]) -> Result<(), String> {
+    let mut engines: Vec<EngineConfig> = vec![];
+    let mut gauntlet = false;
+    let mut variant_names: Option<Vec<String>> = None;
+    let mut rounds = 2usize;
+    let mut random_plies = 4usize;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut sprt: Option<(f64, f64)> = None;
+    let mut log_path: Option<PathBuf> = None;
+    let mut hash_mb = DEFAULT_HASH_MB;
+    let mut rest = args;
+    loop {
+        rest = match rest {
+            [] => break,
+            [flag, tail @ ..] if flag == "--gauntlet" => {
+                gauntlet = true;
+                tail
+            },
+            [flag, value, tail @ ..] => {
+                match flag.as_str() {
+                    "--engine" => engines.push(EngineConfig::parse(value)?),
+                    "--variants" => variant_names = Some(value.split(',').map(String::from).collect()),
+                    "--rounds" => rounds = value.parse()
+                        .map_err(|_| format!("Invalid number of rounds {value:?}."))?,
+                    "--random-plies" => random_plies = value.parse()
+                        .map_err(|_| format!("Invalid number of plays {value:?}."))?,
+                    "--threads" => threads = value.parse().ok().filter(|t| *t > 0)
+                        .ok_or(format!("Invalid number of threads {value:?}."))?,
+                    "--sprt" => sprt = Some(value.split_once(',')
+                        .and_then(|(e0, e1)| Some((e0.parse().ok()?, e1.parse().ok()?)))
+                        .ok_or(format!("Invalid SPRT bounds {value:?}."))?),
+                    "--hash" => hash_mb = value.parse().ok().filter(|mb| *mb > 0)
+                        .ok_or(format!("Invalid hash size {value:?}."))?,
+                    "--log" => log_path = Some(PathBuf::from(value)),
+                    _ => return Err(TOURNAMENT_USAGE.to_string())
+                }
+                tail
+            },
+            _ => return Err(TOURNAMENT_USAGE.to_string())
+        }
+    }
+    if engines.len() < 2 || rounds == 0 {
+        return Err(TOURNAMENT_USAGE.to_string())
+    }
+    if sprt.is_some() && engines.len() != 2 {
+        return Err("An SPRT needs exactly two engines.".to_string())
+    }
+
+    let presets = preset_variants();
+    let mut variant_names = variant_names.unwrap_or_else(|| presets.keys().cloned().collect());
+    variant_names.sort();
+    let mut variants: Vec<(String, u8)> = vec![];
+    for name in &variant_names {
+        let (ruleset, starting_board) = presets.get(name).ok_or(format!("Unknown variant {name:?}."))?;
+        let game: Game<LargeBasicBoardState> = Game::new(*ruleset, starting_board)
+            .map_err(|e| format!("Could not create game: {e:?}"))?;
+        variants.push((name.clone(), game.logic.board_geo.side_len));
+    }
+    for engine in &mut engines {
+        engine.hash_mb = hash_mb;
+        engine.resolve_networks(&variants)?;
+    }
+
+    let pairings: Vec<(usize, usize)> = if gauntlet {
+        (1..engines.len()).map(|j| (0, j)).collect()
+    } else {
+        (0..engines.len()).flat_map(|i| (i + 1..engines.len()).map(move |j| (i, j))).collect()
+    };
+    let mut rng = SmallRng::from_entropy();
+    let mut jobs = VecDeque::new();
+    for (variant, _) in &variants {
+        let (ruleset, starting_board) = &presets[variant];
+        for round in 0..rounds {
+            let opening = random_opening(*ruleset, starting_board, random_plies, &mut rng)?;
+            for (i, j) in &pairings {
+                for (attacker, defender) in [(*i, *j), (*j, *i)] {
+                    jobs.push_back(Job { variant: variant.clone(), round, opening: opening.clone(), attacker, defender });
+                }
+            }
+        }
+    }
+    let total_games = jobs.len();
+    println!("Playing {total_games} games on {threads} threads.");
+
+    let mut log_file = match &log_path {
+        Some(path) => Some(File::create(path).map_err(|e| format!("Could not create {}: {e}", path.display()))?),
+        None => None
+    };
+    let engines = Arc::new(engines);
+    let jobs = Arc::new(Mutex::new(jobs));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel::<Result<GameRecord, String>>();
+    let workers: Vec<_> = (0..threads.min(total_games))
+        .map(|_| {
+            let (engines, jobs, stop, tx) = (engines.clone(), jobs.clone(), stop.clone(), tx.clone());
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(job) = jobs.lock().unwrap().pop_front() else { break };
+                    if tx.send(play_game(job, &engines)).is_err() {
+                        break
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    // Results of each engine against each other engine, and against the field.
+    let n = engines.len();
+    let mut head_to_head = vec![vec![Record::default(); n]; n];
+    let mut overall = vec![Record::default(); n];
+    let mut finished = 0;
+    let mut error = None;
+    for record in rx {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                stop.store(true, Ordering::Relaxed);
+                error = Some(e);
+                break
+            }
+        };
+        finished += 1;
+        let (a, d) = (record.job.attacker, record.job.defender);
+        let score = record.attacker_score();
+        head_to_head[a][d].add(score);
+        head_to_head[d][a].add(1.0 - score);
+        overall[a].add(score);
+        overall[d].add(1.0 - score);
+        println!(
+            "Game {finished} of {total_games} ({}, round {}): {} (attacker) vs {} (defender): {} after {} plays.",
+            record.job.variant,
+            record.job.round + 1,
+            engines[a].name,
+            engines[d].name,
+            record.result_tag(),
+            record.plays.len()
+        );
+        if let Some(file) = &mut log_file {
+            file.write_all(record.to_log(&engines).as_bytes())
+                .map_err(|e| format!("Could not write game log: {e}"))?;
+        }
+        if let Some((elo0, elo1)) = sprt {
+            let llr = head_to_head[0][1].llr(elo0, elo1);
+            let (lower, upper) = ((SPRT_BETA / (1.0 - SPRT_ALPHA)).ln(), ((1.0 - SPRT_BETA) / SPRT_ALPHA).ln());
+            println!("SPRT: LLR {llr:.2} ({lower:.2}, {upper:.2})");
+            if llr <= lower || llr >= upper {
+                let accepted = if llr >= upper { elo1 } else { elo0 };
+                println!("SPRT finished: {} is {accepted} Elo stronger than {} (H{} accepted).",
+                    engines[0].name, engines[1].name, if llr >= upper { 1 } else { 0 });
+                stop.store(true, Ordering::Relaxed);
+                break
+            }
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    println!();
+    for (i, engine) in engines.iter().enumerate() {
+        println!("{}: {}", engine.name, overall[i].describe());
+        for (j, other) in engines.iter().enumerate() {
+            if head_to_head[i][j].games() > 0 && n > 2 {
+                println!("    vs {}: {}", other.name, head_to_head[i][j].describe());
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_from_record() {
+        assert_eq!(Record::default().elo(), None);
+        let (elo, margin) = Record { wins: 10, draws: 5, losses: 10 }.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 0.0);
+        let (elo, _) = Record { wins: 30, draws: 0, losses: 10 }.elo().unwrap();
+        assert!((elo - 190.85).abs() < 0.01, "{elo}");
+        assert!((score_from_elo(elo) - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn llr_favours_nearer_hypothesis() {
+        let even = Record { wins: 10, draws: 5, losses: 10 };
+        assert!(even.llr(0.0, 50.0) < 0.0);
+        assert!(even.llr(-50.0, 0.0) > 0.0);
+        let strong = Record { wins: 30, draws: 0, losses: 10 };
+        assert!(strong.llr(0.0, 200.0) > 0.0);
+        assert!(strong.llr(200.0, 400.0) < 0.0);
+        assert_eq!(Record::default().llr(0.0, 50.0), 0.0);
+    }
+}